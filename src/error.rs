@@ -9,6 +9,8 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     Parse(String),
     Internal(String),
+    // Valid SQL, but not supported by the engine yet
+    Unsupported(String),
     WriteConflict,
 }
 
@@ -17,6 +19,7 @@ impl Display for Error {
         match self {
             Error::Parse(err) => write!(f, "Parse error {}", err),
             Error::Internal(err) => write!(f, "Internal error {}", err),
+            Error::Unsupported(err) => write!(f, "Unsupported {}", err),
             Error::WriteConflict => write!(f, "Write conflict, try transaction"),
         }
     }
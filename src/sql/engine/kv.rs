@@ -1,30 +1,70 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}, ops::{Bound, RangeInclusive}, path::PathBuf, sync::{atomic::{AtomicBool, Ordering}, Arc}};
 use serde::{Deserialize, Serialize};
-use crate::{error::{Error, Result}, sql::{schema::Table, types::{Row, Value}}, storage::{self, engine::Engine as StorageEngine, keycode::serialize_key}};
+use crate::{error::{Error, Result}, sql::{eval::{evaluate_predicate, ColumnMap}, executor::mutation::pad_row, parser::ast::Expression, schema::{format_row, Table}, types::{codec::{decode_row, BincodeCodec, RowCodec}, HashKey, Row, Value}}, storage::{self, disk::DiskEngine, engine::{prefix_end, Engine as StorageEngine}, keycode::serialize_key, memory::MemoryEngine, mvcc::VacuumStats}};
 use super::{Engine, Transaction};
 
 pub struct KVEngine<E: StorageEngine> {
     pub kv: storage::mvcc::Mvcc<E>,
+    // Codec for the rows written by this engine
+    codec: Arc<dyn RowCodec>,
+    // Reject NaN and +-Infinity in float columns
+    finite_only: bool,
 }
 
 impl<E: StorageEngine> KVEngine<E> {
     pub fn new(engine: E) -> Self {
+        Self::with_codec(engine, BincodeCodec)
+    }
+
+    pub fn with_codec(engine: E, codec: impl RowCodec + 'static) -> Self {
         Self {
             kv: storage::mvcc::Mvcc::new(engine),
+            codec: Arc::new(codec),
+            finite_only: false,
         }
     }
+
+    // Off by default, NaN and +-Infinity are stored as they are
+    pub fn finite_only(mut self, finite_only: bool) -> Self {
+        self.finite_only = finite_only;
+        self
+    }
+}
+
+impl KVEngine<MemoryEngine> {
+    // KV engine on top of a fresh memory engine
+    pub fn memory() -> Self {
+        Self::new(MemoryEngine::new())
+    }
+}
+
+impl KVEngine<DiskEngine> {
+    // KV engine on top of the disk log at file_path
+    pub fn disk(file_path: PathBuf) -> Result<Self> {
+        Ok(Self::new(DiskEngine::new(file_path)?))
+    }
 }
 
+// Only the Arcs are cloned, the storage behind them is shared
 impl<E: StorageEngine> Clone for KVEngine<E> {
     fn clone(&self) -> Self {
-        Self { kv: self.kv.clone() }
+        Self { kv: self.kv.clone(), codec: self.codec.clone(), finite_only: self.finite_only }
     }
 }
 
-impl<E: StorageEngine> Engine for KVEngine<E> {
+impl<E: StorageEngine + 'static> Engine for KVEngine<E> {
     type Transaction = KVTransaction<E>;
 
     fn begin(&self) -> Result<Self::Transaction> {
-        Ok(Self::Transaction::new(self.kv.begin()?))
+        Ok(Self::Transaction::new(self.kv.begin()?, self.codec.clone(), self.finite_only))
+    }
+
+    fn begin_as_of(&self, version: u64) -> Result<Self::Transaction> {
+        Ok(Self::Transaction::new(self.kv.begin_as_of(version)?, self.codec.clone(), self.finite_only))
+    }
+
+    fn vacuum(&self) -> Result<VacuumStats> {
+        self.kv.vacuum()
     }
 }
 
@@ -32,11 +72,177 @@ impl<E: StorageEngine> Engine for KVEngine<E> {
 // Package of MvccTransaction
 pub struct KVTransaction<E: StorageEngine> {
     txn: storage::mvcc::MvccTransaction<E>,
+    codec: Arc<dyn RowCodec>,
+    finite_only: bool,
+    // Set by the session for a cancellable statement
+    cancel: Option<Arc<AtomicBool>>,
+    // Schemas read so far, they only change by this transaction's DDL
+    tables: RefCell<HashMap<String, Table>>,
 }
 
 impl<E: StorageEngine> KVTransaction<E> {
-    pub fn new(txn:storage::mvcc::MvccTransaction<E>) -> Self {
-        Self { txn }
+    pub fn new(txn:storage::mvcc::MvccTransaction<E>, codec: Arc<dyn RowCodec>, finite_only: bool) -> Self {
+        Self { txn, codec, finite_only, cancel: None, tables: RefCell::new(HashMap::new()) }
+    }
+
+    // check_row, and the engine wide value restrictions
+    fn validate_row(&self, table: &Table, row: &Row) -> Result<()> {
+        check_row(table, row)?;
+        if self.finite_only {
+            if let Some((col, value)) = table.columns.iter().zip(row).find(|(_, v)| matches!(v, Value::Float(f) if !f.is_finite())) {
+                return Err(Error::Internal(format!("Column {} cannot be {}", col.name, value.to_canonical_string())));
+            }
+        }
+        Ok(())
+    }
+
+    // A NULL autoincrement value gets the next id. The counter is kept
+    // past the largest id given, so later ids never collide with it
+    fn assign_row_id(&mut self, table: &Table, row: &mut Row) -> Result<()> {
+        let Some(i) = table.columns.iter().position(|c| c.autoincrement) else {
+            return Ok(());
+        };
+        let key = Key::RowCounter(table.name.clone()).encode()?;
+        let last: i64 = match self.txn.get(key.clone())? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => 0,
+        };
+        let next = match row.get(i) {
+            Some(Value::Null) => {
+                let id = last.checked_add(1)
+                    .ok_or_else(|| Error::Internal(format!("Autoincrement overflow in table {}", table.name)))?;
+                row[i] = Value::Integer(id);
+                id
+            }
+            Some(Value::Integer(id)) if *id > last => *id,
+            _ => return Ok(()),
+        };
+        self.txn.set(key, bincode::serialize(&next)?)
+    }
+
+    // Check and store a new row. A table kept in insertion order gives
+    // it the sequence number, or the next one for a new row
+    fn insert_row(&mut self, table: &Table, mut row: Row, sequence: Option<u64>) -> Result<()> {
+        self.assign_row_id(table, &mut row)?;
+        self.validate_row(table, &row)?;
+
+        // find the primary key
+        let pk = table.get_primary_key(&row)?;
+        // check data conflict with primary key
+        let id = Key::Row(table.name.clone(), pk.clone()).encode()?;
+        if self.txn.get(id.clone())?.is_some() {
+            return Err(duplicate_error(table, &pk, &row));
+        }
+        self.check_unique(table, [&row])?;
+
+        // insert the data
+        let value = table.serialize_row(&row)?;
+        self.txn.set(id, value)?;
+        self.index_unique(table, &row, &pk)?;
+
+        if table.insertion_order {
+            let sequence = match sequence {
+                Some(sequence) => sequence,
+                None => self.next_insertion_sequence(&table.name)?,
+            };
+            self.txn.set(Key::InsertionOrder(table.name.clone(), sequence).encode()?, bincode::serialize(&pk)?)?;
+            self.txn.set(Key::InsertionSequence(table.name.clone(), pk).encode()?, bincode::serialize(&sequence)?)?;
+        }
+        Ok(())
+    }
+
+    // Delete the row, the sequence number it was inserted with is returned
+    fn remove_row(&mut self, table: &Table, pk: &Value) -> Result<Option<u64>> {
+        let id = Key::Row(table.name.clone(), pk.clone()).encode()?;
+        if !table.unique.is_empty() {
+            if let Some(value) = self.txn.get(id.clone())? {
+                self.unindex_unique(table, &table.deserialize_row(&value)?)?;
+            }
+        }
+        self.txn.delete(id)?;
+        if !table.insertion_order {
+            return Ok(None);
+        }
+        let key = Key::InsertionSequence(table.name.clone(), pk.clone()).encode()?;
+        let Some(value) = self.txn.get(key.clone())? else {
+            return Ok(None);
+        };
+        let sequence: u64 = bincode::deserialize(&value)?;
+        self.txn.delete(Key::InsertionOrder(table.name.clone(), sequence).encode()?)?;
+        self.txn.delete(key)?;
+        Ok(Some(sequence))
+    }
+
+    fn next_insertion_sequence(&mut self, table_name: &str) -> Result<u64> {
+        let key = Key::InsertionCounter(table_name.to_string()).encode()?;
+        let next = match self.txn.get(key.clone())? {
+            Some(value) => bincode::deserialize::<u64>(&value)? + 1,
+            None => 0,
+        };
+        self.txn.set(key, bincode::serialize(&next)?)?;
+        Ok(next)
+    }
+
+    // The entries of the insertion order of the table, by sequence number
+    fn insertion_order_range(table_name: &str) -> Result<RangeInclusive<Vec<u8>>> {
+        Ok(Key::InsertionOrder(table_name.to_string(), 0).encode()?..=Key::InsertionOrder(table_name.to_string(), u64::MAX).encode()?)
+    }
+
+    fn schema_version(&self, table_name: &str) -> Result<u64> {
+        match self.txn.get(Key::SchemaVersion(table_name.to_string()).encode()?)? {
+            Some(value) => Ok(bincode::deserialize(&value)?),
+            None => Ok(0),
+        }
+    }
+
+    fn bump_schema_version(&mut self, table_name: &str) -> Result<()> {
+        let version = self.schema_version(table_name)? + 1;
+        self.txn.set(Key::SchemaVersion(table_name.to_string()).encode()?, bincode::serialize(&version)?)
+    }
+
+    // The index keys of the unique column sets of the row, sets that
+    // are all NULL have none
+    fn unique_keys<'a>(table: &'a Table, row: &Row) -> Result<Vec<(&'a [String], Vec<u8>)>> {
+        let mut keys = Vec::with_capacity(table.unique.len());
+        for set in table.unique.iter() {
+            if let Some(key) = table.unique_key(set, row)? {
+                keys.push((set.as_slice(), Key::Unique(table.name.clone(), set.join(","), key).encode()?));
+            }
+        }
+        Ok(keys)
+    }
+
+    // New rows may not share the values of a unique column set with each
+    // other or with the rows in the table, unless those values are all NULL.
+    // Each set is looked up in its index, the table is not scanned
+    fn check_unique<'a>(&mut self, table: &Table, rows: impl IntoIterator<Item = &'a Row>) -> Result<()> {
+        if table.unique.is_empty() {
+            return Ok(());
+        }
+        let mut seen = HashSet::new();
+        for row in rows {
+            for (set, key) in Self::unique_keys(table, row)? {
+                if !seen.insert(key.clone()) || self.txn.get(key)?.is_some() {
+                    return Err(Error::Internal(format!("Duplicate data for unique ({}) in table {}: ({})",
+                        set.join(", "), table.name, format_row(table, row))));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn index_unique(&mut self, table: &Table, row: &Row, pk: &Value) -> Result<()> {
+        for (_, key) in Self::unique_keys(table, row)? {
+            self.txn.set(key, bincode::serialize(pk)?)?;
+        }
+        Ok(())
+    }
+
+    fn unindex_unique(&mut self, table: &Table, row: &Row) -> Result<()> {
+        for (_, key) in Self::unique_keys(table, row)? {
+            self.txn.delete(key)?;
+        }
+        Ok(())
     }
 }
     
@@ -50,45 +256,153 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
     }
 
     fn create_row(&mut self, table_name: String, row: Row) -> Result<()> {
-        let table = self.must_get_table(table_name.clone())?;
-        // Check if the row is valid
-        for (i,col) in table.columns.iter().enumerate() {
-            match row[i].datatype() {
-                Some(dt) if dt != col.datatype => return Err(Error::Internal(format!("Column {} datatype mismatch", col.name))),
-                None if col.nullable => {},
-                None => return Err(Error::Internal(format!("Column {} cannot be null", col.name))),
-                _ => {},
-            }
-        }
-
-        // find the primary key
-        let pk = table.get_primary_key(&row)?;
-        // check data conflict with primary key
-        let id = Key::Row(table_name.clone(), pk.clone()).encode()?;
-        if self.txn.get(id.clone())?.is_some() {
-            return Err(Error::Internal(format!("Duplicate data for primary key {} in table {}", pk, table_name)));
-        }
+        let table = self.must_get_table(table_name)?;
+        self.insert_row(&table, row, None)
+    }
 
-        // insert the data
-        // (Temporarily) (todo) set the first row as the primary key
-        let id = Key::Row(table_name.clone(), row[0].clone());
-        let value = bincode::serialize(&row)?;
-        self.txn.set(bincode::serialize(&id)?, value)?; 
+    fn delete_row(&mut self, table_name: String, pk: &Value) -> Result<()> {
+        let table = self.must_get_table(table_name)?;
+        self.remove_row(&table, pk).map(|_| ())
+    }
 
+    // An updated row keeps its place in the insertion order
+    fn update_rows(&mut self, table_name: String, rows: Vec<(Value, Row)>) -> Result<()> {
+        let table = self.must_get_table(table_name)?;
+        let sequences = rows.iter().map(|(pk, _)| self.remove_row(&table, pk)).collect::<Result<Vec<_>>>()?;
+        for ((_, row), sequence) in rows.into_iter().zip(sequences) {
+            self.insert_row(&table, row, sequence)?;
+        }
         Ok(())
     }
 
     fn scan_table(&mut self, table_name: String) -> Result<Vec<Row>> {
+        self.scan_table_range(table_name, None, None)
+    }
+
+    // Only the first rows are read from the storage
+    fn scan_table_first(&mut self, table_name: String, limit: usize) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table_name.clone())?;
+        let prefix = KeyPrefix::Row(table_name).encode()?;
+        self.txn.scan_first((Bound::Included(prefix.clone()), prefix_end(&prefix)), limit)?
+            .iter().map(|result| {
+                self.check_cancelled()?;
+                table.deserialize_row(&result.value)
+            }).collect()
+    }
+
+    fn scan_table_value_range(&mut self, table_name: String, start: Bound<Value>, end: Bound<Value>) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table_name.clone())?;
         let prefix = KeyPrefix::Row(table_name.clone()).encode()?;
-        let results = self.txn.scan_prefix(prefix)?;
+        // The key encoding keeps the value order, so each bound maps to the
+        // key of its value. Open bounds are replaced by the bounds of the table prefix
+        let key = |v: Value| Key::Row(table_name.clone(), v).encode();
+        let start = match start {
+            Bound::Included(v) => Bound::Included(key(v)?),
+            Bound::Excluded(v) => Bound::Excluded(key(v)?),
+            Bound::Unbounded => Bound::Included(prefix.clone()),
+        };
+        let end = match end {
+            Bound::Included(v) => Bound::Included(key(v)?),
+            Bound::Excluded(v) => Bound::Excluded(key(v)?),
+            Bound::Unbounded => prefix_end(&prefix),
+        };
+        // The storage engines expect start <= end
+        let empty = match (&start, &end) {
+            (Bound::Included(s), Bound::Included(e)) => s > e,
+            (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => s >= e,
+            _ => false,
+        };
+        if empty {
+            return Ok(Vec::new());
+        }
+        let results = self.txn.scan((start, end))?;
+
         let mut rows = Vec::new();
         for result in results {
-            let row: Row = bincode::deserialize(&result.value)?;
-            rows.push(row);
+            self.check_cancelled()?;
+            rows.push(table.deserialize_row(&result.value)?);
         }
         Ok(rows)
     }
 
+    fn scan_table_insertion_order(&mut self, table_name: String) -> Result<Vec<Row>> {
+        let table = self.must_get_table(table_name.clone())?;
+        if !table.insertion_order {
+            return Err(Error::Internal(format!("Table {} is not kept in insertion order", table_name)));
+        }
+        let mut rows = Vec::new();
+        for result in self.txn.scan(Self::insertion_order_range(&table_name)?)? {
+            self.check_cancelled()?;
+            let pk: Value = bincode::deserialize(&result.value)?;
+            let value = self.txn.get(Key::Row(table_name.clone(), pk.clone()).encode()?)?
+                .ok_or_else(|| Error::Internal(format!("Row {} of table {} is missing", pk, table_name)))?;
+            rows.push(table.deserialize_row(&value)?);
+        }
+        Ok(rows)
+    }
+
+    fn count_rows_matching(&mut self, table_name: String, predicate: Option<&Expression>) -> Result<usize> {
+        let table = self.must_get_table(table_name.clone())?;
+        let columns = table.columns.iter().map(|c| c.name.clone()).collect::<Vec<_>>();
+        let schema = ColumnMap::new(&columns);
+        let prefix = KeyPrefix::Row(table_name).encode()?;
+
+        let mut count = 0;
+        for result in self.txn.scan((Bound::Included(prefix.clone()), prefix_end(&prefix)))? {
+            self.check_cancelled()?;
+            let row = table.deserialize_row(&result.value)?;
+            if let Some(predicate) = predicate {
+                if evaluate_predicate(predicate, &row, &schema)? != Some(true) {
+                    continue;
+                }
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn bulk_insert(&mut self, table_name: &str, rows: Vec<Row>) -> Result<usize> {
+        let table = self.must_get_table(table_name.to_string())?;
+        // Each row needs its sequence number, they are inserted one by one
+        if table.insertion_order {
+            return self.insert(table_name, rows);
+        }
+        let count = rows.len();
+
+        // Duplicates within the rows are still found up front
+        let mut keys = HashSet::new();
+        let mut entries = Vec::with_capacity(count);
+        let mut checked = Vec::with_capacity(count);
+        for row in rows {
+            let mut row = pad_row(&table, &row)?;
+            self.assign_row_id(&table, &mut row)?;
+            self.validate_row(&table, &row)?;
+            let pk = table.get_primary_key(&row)?;
+            let id = Key::Row(table_name.to_string(), pk.clone()).encode()?;
+            if !keys.insert(id.clone()) {
+                return Err(duplicate_error(&table, &pk, &row));
+            }
+            entries.push((id, table.serialize_row(&row)?));
+            checked.push((pk, row));
+        }
+        self.check_unique(&table, checked.iter().map(|(_, row)| row))?;
+
+        let prefix = KeyPrefix::Row(table_name.to_string()).encode()?;
+        if !self.txn.bulk_load((Bound::Included(prefix.clone()), prefix_end(&prefix)), &entries)? {
+            // Safe path, the same checks as create_row
+            for ((id, value), (pk, row)) in entries.into_iter().zip(checked.iter()) {
+                if self.txn.get(id.clone())?.is_some() {
+                    return Err(duplicate_error(&table, pk, row));
+                }
+                self.txn.set(id, value)?;
+            }
+        }
+        for (pk, row) in checked.iter() {
+            self.index_unique(&table, row, pk)?;
+        }
+        Ok(count)
+    }
+
     fn create_table(&mut self, table: Table) -> Result<()> {
         // Check if it exists
         if self.get_table(table.name.clone())?.is_some() {
@@ -100,14 +414,96 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
         let key = Key::Table(table.name.clone()).encode()?;
         let val = bincode::serialize(&table)?;
         self.txn.set(key, val)?;
+        self.tables.get_mut().remove(&table.name);
+        self.bump_schema_version(&table.name)
+    }
 
+    fn update_table(&mut self, table: Table) -> Result<()> {
+        self.must_get_table(table.name.clone())?;
+        table.validate()?;
+
+        let key = Key::Table(table.name.clone()).encode()?;
+        self.txn.set(key, bincode::serialize(&table)?)?;
+        self.tables.get_mut().remove(&table.name);
+        self.bump_schema_version(&table.name)
+    }
+
+    fn drop_table(&mut self, table_name: String) -> Result<()> {
+        let table = self.must_get_table(table_name.clone())?;
+        self.tables.get_mut().remove(&table_name);
+
+        // Delete the rows and their unique index keys
+        let prefix = KeyPrefix::Row(table_name.clone()).encode()?;
+        for result in self.txn.scan((Bound::Included(prefix.clone()), prefix_end(&prefix)))? {
+            if !table.unique.is_empty() {
+                self.unindex_unique(&table, &table.deserialize_row(&result.value)?)?;
+            }
+            self.txn.delete(result.key)?;
+        }
+
+        // Delete the insertion order of the rows
+        for result in self.txn.scan(Self::insertion_order_range(&table_name)?)? {
+            let pk: Value = bincode::deserialize(&result.value)?;
+            self.txn.delete(Key::InsertionSequence(table_name.clone(), pk).encode()?)?;
+            self.txn.delete(result.key)?;
+        }
+
+        // Delete the schema and the counters. The schema version
+        // is kept, a new table of the same name continues it
+        self.txn.delete(Key::RowCounter(table_name.clone()).encode()?)?;
+        self.txn.delete(Key::InsertionCounter(table_name.clone()).encode()?)?;
+        self.txn.delete(Key::Table(table_name).encode()?)?;
         Ok(())
     }
 
+    fn scan_all_tables(&mut self) -> Result<Vec<(Table, Vec<Row>)>> {
+        let prefix = KeyPrefix::Table.encode()?;
+        let mut tables = Vec::new();
+        for result in self.txn.scan((Bound::Included(prefix.clone()), prefix_end(&prefix)))? {
+            let table: Table = bincode::deserialize(&result.value)?;
+            // Decoded as stored, rows that do not fit the schema are kept
+            let prefix = KeyPrefix::Row(table.name.clone()).encode()?;
+            let rows = self.txn.scan((Bound::Included(prefix.clone()), prefix_end(&prefix)))?.iter()
+                .map(|result| decode_row(&result.value))
+                .collect::<Result<Vec<_>>>()?;
+            tables.push((table, rows));
+        }
+        Ok(tables)
+    }
+
+    fn set_cancel(&mut self, token: Option<Arc<AtomicBool>>) {
+        self.txn.set_cancel(token.clone());
+        self.cancel = token;
+    }
+
+    fn check_cancelled(&self) -> Result<()> {
+        match &self.cancel {
+            Some(token) if token.load(Ordering::Relaxed) => Err(Error::Internal("query cancelled".into())),
+            _ => Ok(()),
+        }
+    }
+
+    fn table_exists(&self, table_name: &str) -> Result<bool> {
+        Ok(self.txn.get(Key::Table(table_name.to_string()).encode()?)?.is_some())
+    }
+
     fn get_table(&self, table_name: String) -> Result<Option<Table>> {
+        if let Some(table) = self.tables.borrow().get(&table_name) {
+            return Ok(Some(table.clone()));
+        }
         let key = Key::Table(table_name).encode()?;
-        Ok(self.txn.get(key)?
-        .map(|c|bincode::deserialize(&c)).transpose()?)
+        let table: Option<Table> = self.txn.get(key)?
+        .map(|c|bincode::deserialize(&c)).transpose()?;
+        let version = match &table {
+            Some(table) => self.schema_version(&table.name)?,
+            None => 0,
+        };
+        Ok(table.map(|mut table| {
+            table.schema_version = version;
+            table.row_codec = self.codec.tag();
+            self.tables.borrow_mut().insert(table.name.clone(), table.clone());
+            table
+        }))
     }
 }
 
@@ -115,6 +511,19 @@ impl<E: StorageEngine> Transaction for KVTransaction<E> {
 pub enum Key {
     Table(String),
     Row(String, Value),
+    // The last autoincrement id of the table
+    RowCounter(String),
+    // The schema version of the table
+    SchemaVersion(String),
+    // The primary key of the row inserted with the sequence number
+    InsertionOrder(String, u64),
+    // The sequence number of the row with the primary key
+    InsertionSequence(String, Value),
+    // The last insertion sequence number of the table
+    InsertionCounter(String),
+    // The primary key of the row holding the values of a unique column
+    // set, the set is named by its columns joined with commas
+    Unique(String, String, Vec<HashKey>),
 }
 
 impl Key {
@@ -135,11 +544,107 @@ impl KeyPrefix {
     }
 }
 
+// Check the values match the column types and nullability
+fn check_row(table: &Table, row: &Row) -> Result<()> {
+    for (i,col) in table.columns.iter().enumerate() {
+        match row[i].datatype() {
+            Some(dt) if dt != col.datatype => return Err(Error::Internal(format!("Column {} datatype mismatch", col.name))),
+            None if col.nullable => {},
+            None => return Err(Error::Internal(format!("Column {} cannot be null", col.name))),
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+fn duplicate_error(table: &Table, pk: &Value, row: &Row) -> Error {
+    Error::Internal(format!("Duplicate data for primary key {} in table {}: ({})", pk, table.name, format_row(table, row)))
+}
+
 #[cfg(test)]
 
 mod tests {
-    use crate::{sql::engine::Engine, storage::memory::MemoryEngine, error::Result};
-    use super::KVEngine;
+    use crate::{sql::{engine::{Engine, Session, Transaction}, executor::ResultSet, parser::{ast::Statement, IdentCase, Parser}, types::{codec::{encode_row, CompactCodec}, DataTypes, Row, Value}}, storage::memory::MemoryEngine, error::{Error, Result}};
+    use super::{BincodeCodec, Key, KVEngine};
+    use std::{ops::RangeBounds, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc}};
+    use crate::storage::{engine::{Engine as StorageEngine, EngineIterator}, memory::MemoryEngineIterator};
+
+    // The rows a query returns, any other result fails the test
+    fn query_rows<E: Engine>(s: &mut Session<E>, sql: &str) -> Result<Vec<Row>> {
+        match s.execute(sql)? {
+            ResultSet::Scan { rows, .. } => Ok(rows),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    // Memory engine counting the entries its scans produce,
+    // optionally setting a cancellation token after that many reads
+    struct CountingEngine {
+        inner: MemoryEngine,
+        reads: Arc<AtomicUsize>,
+        cancel_at: Option<(usize, Arc<AtomicBool>)>,
+    }
+
+    struct CountingIterator<'a> {
+        inner: MemoryEngineIterator<'a>,
+        reads: Arc<AtomicUsize>,
+        cancel_at: Option<(usize, Arc<AtomicBool>)>,
+    }
+
+    impl CountingIterator<'_> {
+        fn record_read(&self) {
+            let reads = self.reads.fetch_add(1, Ordering::Relaxed) + 1;
+            if let Some((at, token)) = &self.cancel_at {
+                if reads >= *at {
+                    token.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    impl Iterator for CountingIterator<'_> {
+        type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            let item = self.inner.next();
+            if item.is_some() {
+                self.record_read();
+            }
+            item
+        }
+    }
+
+    impl DoubleEndedIterator for CountingIterator<'_> {
+        fn next_back(&mut self) -> Option<Self::Item> {
+            let item = self.inner.next_back();
+            if item.is_some() {
+                self.record_read();
+            }
+            item
+        }
+    }
+
+    impl EngineIterator for CountingIterator<'_> {}
+
+    impl StorageEngine for CountingEngine {
+        type EngineIterator<'a> = CountingIterator<'a>;
+
+        fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+            self.inner.set(key, value)
+        }
+
+        fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+            self.inner.delete(key)
+        }
+
+        fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+            CountingIterator { inner: self.inner.scan(range), reads: self.reads.clone(), cancel_at: self.cancel_at.clone() }
+        }
+    }
 
     #[test]
     fn test_create_table() -> Result<()> {
@@ -160,4 +665,1798 @@ mod tests {
         println!("{:?}", v);
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_execute_without_semicolon() -> Result<()> {
+        let kvengine = KVEngine::new(MemoryEngine::new());
+        let mut s = kvengine.session()?;
+
+        s.execute("CREATE TABLE t (a int primary key, b int)")?;
+        s.execute("INSERT INTO t VALUES (1, 2)")?;
+        match s.execute("SELECT * FROM t")? {
+            ResultSet::Scan { columns, .. } => assert_eq!(columns, vec!["a".to_string(), "b".to_string()]),
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_engine_constructors() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key);")?;
+        s.execute("INSERT INTO t VALUES (1);")?;
+
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        let mut s = KVEngine::disk(p.clone())?.session()?;
+        s.execute("CREATE TABLE t (a int primary key);")?;
+        s.execute("INSERT INTO t VALUES (1);")?;
+        drop(s);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_vacuum() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        let engine = KVEngine::disk(p.clone())?;
+        let mut s = engine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, 'z');")?;
+        for i in 0..50 {
+            s.execute(&format!("UPDATE t SET b = 'value {}' WHERE a < 3;", i))?;
+        }
+        s.execute("DELETE FROM t WHERE a = 3;")?;
+
+        // Not while a transaction is active, in this session or another
+        let mut other = engine.session()?;
+        other.execute("BEGIN;")?;
+        assert!(s.execute("VACUUM;").is_err());
+        assert!(other.execute("VACUUM;").is_err());
+        other.execute("ROLLBACK;")?;
+
+        let before = std::fs::metadata(&p)?.len();
+        let (versions, bytes) = match s.execute("VACUUM;")? {
+            ResultSet::Vacuum { versions, bytes } => (versions, bytes),
+            r => panic!("unexpected result {:?}", r),
+        };
+        let after = std::fs::metadata(&p)?.len();
+        // 50 older versions of rows 1 and 2, and both versions of row 3
+        assert_eq!(versions, 102);
+        assert!(after < before / 4, "{} -> {}", before, after);
+        assert_eq!(bytes, before - after);
+        assert!(matches!(s.execute("VACUUM;")?, ResultSet::Vacuum { versions: 0, .. }));
+
+        // The history is gone, reading it is refused instead of wrong
+        match s.execute("SELECT * FROM t AS OF VERSION 3;") {
+            Err(Error::Internal(msg)) => assert!(msg.starts_with("Version 3 was removed by VACUUM"), "{}", msg),
+            r => panic!("unexpected result {:?}", r),
+        }
+        let mut query_only = engine.session()?;
+        query_only.disallow_ddl();
+        assert!(matches!(query_only.execute("VACUUM;"), Err(Error::Unsupported(_))));
+        drop(query_only);
+
+        // The data is kept, also after a restart
+        let expected = vec![
+            vec![Value::Integer(1), Value::String("value 49".into())],
+            vec![Value::Integer(2), Value::String("value 49".into())],
+        ];
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, expected);
+        drop((s, other, engine));
+        let mut s = KVEngine::disk(p.clone())?.session()?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, expected);
+        s.execute("INSERT INTO t VALUES (3, 'w');")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?.len(), 3);
+        drop(s);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_autoincrement_restart() -> Result<()> {
+        let ids = |rows: Vec<Row>| rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        let mut s = KVEngine::disk(p.clone())?.session()?;
+        s.execute("CREATE TABLE t (a int primary key autoincrement, b varchar);")?;
+        s.execute("INSERT INTO t (b) VALUES ('x'), ('y');")?;
+        s.execute("INSERT INTO t VALUES (NULL, 'z');")?;
+        // An explicit id moves the counter past it
+        s.execute("INSERT INTO t VALUES (10, 'w');")?;
+        s.execute("INSERT INTO t VALUES (5, 'v');")?;
+        assert_eq!(ids(query_rows(&mut s, "SELECT a FROM t;")?), vec![1.into(), 2.into(), 3.into(), 5.into(), 10.into()]);
+        drop(s);
+
+        let mut s = KVEngine::disk(p.clone())?.session()?;
+        s.execute("INSERT INTO t (b) VALUES ('u'), ('t');")?;
+        assert_eq!(ids(query_rows(&mut s, "SELECT a FROM t;")?), vec![1.into(), 2.into(), 3.into(), 5.into(), 10.into(), 11.into(), 12.into()]);
+        drop(s);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+
+        let mut s = KVEngine::memory().session()?;
+        assert!(s.execute("CREATE TABLE t1 (a int primary key, b int autoincrement);").is_err());
+        assert!(s.execute("CREATE TABLE t2 (a varchar primary key autoincrement);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_autoincrement_omitted() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (name varchar, id int primary key autoincrement, score int default 0);")?;
+        s.execute("INSERT INTO t (name) VALUES ('x'), ('y');")?;
+        s.execute("INSERT INTO t (score, name) VALUES (5, 'z');")?;
+        s.execute("INSERT INTO t (name, id) VALUES ('w', NULL);")?;
+        assert_eq!(query_rows(&mut s, "SELECT id, name, score FROM t;")?, vec![
+            vec![Value::Integer(1), Value::String("x".into()), Value::Integer(0)],
+            vec![Value::Integer(2), Value::String("y".into()), Value::Integer(0)],
+            vec![Value::Integer(3), Value::String("z".into()), Value::Integer(5)],
+            vec![Value::Integer(4), Value::String("w".into()), Value::Integer(0)],
+        ]);
+
+        // Without AUTOINCREMENT the primary key must be given
+        s.execute("CREATE TABLE t2 (name varchar, id int primary key);")?;
+        match s.execute("INSERT INTO t2 (name) VALUES ('x');") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 0: Missing value for required column id"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_required_columns() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key autoincrement, note varchar default 'n/a', name varchar not null, score int, at int default current_timestamp);")?;
+        let mut error = |sql: &str| match s.execute(sql) {
+            Err(Error::Internal(msg)) => msg,
+            r => panic!("unexpected result {:?}", r),
+        };
+
+        // Only name has to be given
+        assert_eq!(error("INSERT INTO t (note, score) VALUES ('x', 1);"), "row 0: Missing value for required column name");
+        assert_eq!(error("INSERT INTO t VALUES (NULL, 'x');"), "row 0: Missing value for required column name");
+        assert_eq!(error("INSERT INTO t (name) VALUES ('a', 'b');"), "row 0: expected 1 value, got 2");
+        assert_eq!(error("INSERT INTO t VALUES (NULL, 'x', 'a', 1, 2, 3);"), "row 0: expected 5 values, got 6");
+        s.execute("INSERT INTO t (name) VALUES ('a');")?;
+        s.execute("INSERT INTO t (score, name, id) VALUES (3, 'b', NULL);")?;
+        s.execute("INSERT INTO t VALUES (NULL, 'x', 'c');")?;
+        assert_eq!(query_rows(&mut s, "SELECT id, note, name, score FROM t;")?, vec![
+            vec![Value::Integer(1), Value::String("n/a".into()), Value::String("a".into()), Value::Null],
+            vec![Value::Integer(2), Value::String("n/a".into()), Value::String("b".into()), Value::Integer(3)],
+            vec![Value::Integer(3), Value::String("x".into()), Value::String("c".into()), Value::Null],
+        ]);
+
+        // Every missing one is named
+        s.execute("CREATE TABLE t2 (a int primary key, b int not null, c int default 0, d int not null);")?;
+        match s.execute("INSERT INTO t2 (c) VALUES (1);") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 0: Missing values for required columns a, b, d"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_insertion_order() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE log (id int primary key, msg varchar) WITH INSERTION ORDER;")?;
+        s.execute("INSERT INTO log VALUES (30, 'a'), (10, 'b');")?;
+        s.execute("INSERT INTO log VALUES (20, 'c');")?;
+        let mut txn = kvengine.begin()?;
+        txn.bulk_insert("log", vec![vec![Value::Integer(5), Value::String("d".into())]])?;
+        txn.commit()?;
+        s.execute("INSERT INTO log VALUES (40, 'e');")?;
+        let ids = |rows: Vec<Row>| rows.iter().map(|row| match row[0] {
+            Value::Integer(id) => id,
+            ref v => panic!("unexpected value {}", v),
+        }).collect::<Vec<_>>();
+
+        assert_eq!(ids(query_rows(&mut s, "SELECT * FROM log;")?), vec![5, 10, 20, 30, 40]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT * FROM log ORDER BY INSERTION;")?), vec![30, 10, 20, 5, 40]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id, msg FROM log WHERE id > 10 ORDER BY INSERTION DESC LIMIT 2;")?), vec![40, 20]);
+        // An updated row keeps its place, a deleted and inserted one moves to the end
+        s.execute("UPDATE log SET msg = 'x' WHERE id = 10;")?;
+        s.execute("UPDATE log SET id = 11 WHERE id = 20;")?;
+        s.execute("DELETE FROM log WHERE id = 30;")?;
+        s.execute("INSERT INTO log VALUES (30, 'f');")?;
+        assert_eq!(ids(query_rows(&mut s, "SELECT * FROM log ORDER BY INSERTION;")?), vec![10, 11, 5, 40, 30]);
+        assert_eq!(s.execute("EXPLAIN SELECT * FROM log WHERE id > 1 ORDER BY INSERTION;")?.to_string(),
+            "Filter (id > 1)\n  InsertionScan log ASC");
+        match s.execute("DESCRIBE log;")? {
+            ResultSet::Describe { schema } => assert!(schema.ends_with(") WITH INSERTION ORDER"), "{}", schema),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Only for the tables created with it, a new table starts over
+        s.execute("CREATE TABLE t (id int primary key);")?;
+        assert!(s.execute("SELECT * FROM t ORDER BY INSERTION;").is_err());
+        assert!(s.execute("SELECT * FROM log ORDER BY INSERTION, id;").is_err());
+        s.execute("DROP TABLE log;")?;
+        s.execute("CREATE TABLE log (id int primary key) WITH INSERTION ORDER;")?;
+        s.execute("INSERT INTO log VALUES (2), (1);")?;
+        assert_eq!(ids(query_rows(&mut s, "SELECT * FROM log ORDER BY INSERTION;")?), vec![2, 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_not_nullable() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+
+        assert!(s.execute("CREATE TABLE t1 (a int primary key null, b int);").is_err());
+
+        s.execute("CREATE TABLE t2 (a int primary key, b int);")?;
+        let txn = kvengine.begin()?;
+        let table = txn.must_get_table("t2".to_string())?;
+        assert!(!table.columns[0].nullable);
+        assert_eq!(table.columns[0].default, None);
+        txn.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_table_range() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar);")?;
+        s.execute("CREATE TABLE t2 (a int primary key);")?;
+        s.execute("INSERT INTO t VALUES (5, 'e'), (1, 'a'), (3, 'c'), (7, 'g'), (2, 'b'), (4, 'd'), (6, 'f'), (0, 'z');")?;
+        s.execute("INSERT INTO t2 VALUES (3);")?;
+
+        let mut txn = kvengine.begin()?;
+        let pks = |rows: Vec<Row>| rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        let ints = |v: Vec<i64>| v.into_iter().map(Value::Integer).collect::<Vec<_>>();
+
+        let rows = txn.scan_table_range("t".into(), Some(Value::Integer(2)), Some(Value::Integer(5)))?;
+        assert_eq!(pks(rows), ints(vec![2, 3, 4, 5]));
+        let rows = txn.scan_table_range("t".into(), None, Some(Value::Integer(2)))?;
+        assert_eq!(pks(rows), ints(vec![0, 1, 2]));
+        let rows = txn.scan_table_range("t".into(), Some(Value::Integer(6)), None)?;
+        assert_eq!(pks(rows), ints(vec![6, 7]));
+        let rows = txn.scan_table_range("t".into(), None, None)?;
+        assert_eq!(rows, txn.scan_table("t".into())?);
+
+        // Every combination of inclusive and exclusive bounds
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+        let i = |v: i64| Included(Value::Integer(v));
+        let e = |v: i64| Excluded(Value::Integer(v));
+        for (start, end, expected) in [
+            (i(2), i(5), vec![2, 3, 4, 5]),
+            (i(2), e(5), vec![2, 3, 4]),
+            (e(2), i(5), vec![3, 4, 5]),
+            (e(2), e(5), vec![3, 4]),
+            (e(2), e(3), vec![]),
+            (e(3), e(3), vec![]),
+            (i(3), e(3), vec![]),
+            (i(3), i(3), vec![3]),
+            (i(5), i(2), vec![]),
+            (Unbounded, e(2), vec![0, 1]),
+            (e(6), Unbounded, vec![7]),
+            (e(7), Unbounded, vec![]),
+            (Unbounded, e(0), vec![]),
+        ] {
+            let rows = txn.scan_table_value_range("t".into(), start.clone(), end.clone())?;
+            assert_eq!(pks(rows), ints(expected), "{:?} {:?}", start, end);
+        }
+        let rows = txn.scan_table_value_range("t2".into(), Unbounded, Unbounded)?;
+        assert_eq!(pks(rows), ints(vec![3]));
+
+        // Matching the WHERE on the key
+        assert_eq!(pks(query_rows(&mut s, "SELECT a FROM t WHERE a >= 2 AND a < 5;")?), ints(vec![2, 3, 4]));
+        assert_eq!(pks(query_rows(&mut s, "SELECT a FROM t WHERE 5 > a AND a > 2 AND a <= 9;")?), ints(vec![3, 4]));
+        txn.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_row_codec() -> Result<()> {
+        let storage = MemoryEngine::new();
+        let kvengine = KVEngine::with_codec(storage, CompactCodec);
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b float, c varchar, d bool);")?;
+        s.execute("INSERT INTO t VALUES (1, 1.5, 'apple', true), (2, 2.5, null, false);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![
+            vec![Value::Integer(1), Value::Float(1.5), Value::String("apple".into()), Value::Boolean(true)],
+            vec![Value::Integer(2), Value::Float(2.5), Value::Null, Value::Boolean(false)],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_table_as() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t1 (a int primary key, b float not null, c varchar default 'apple');")?;
+        s.execute("INSERT INTO t1 VALUES (1, 1.5, 'x'), (2, 2.5);")?;
+        s.execute("CREATE TABLE t2 AS SELECT * FROM t1;")?;
+
+        let rows = query_rows(&mut s, "SELECT * FROM t2;")?;
+        assert_eq!(rows, vec![
+            vec![Value::Integer(1), Value::Float(1.5), Value::String("x".into())],
+            vec![Value::Integer(2), Value::Float(2.5), Value::String("apple".into())],
+        ]);
+
+        let txn = kvengine.begin()?;
+        let t1 = txn.must_get_table("t1".into())?;
+        let t2 = txn.must_get_table("t2".into())?;
+        assert_eq!(t1.columns, t2.columns);
+        txn.commit()?;
+
+        // The target must not exist yet
+        assert!(s.execute("CREATE TABLE t2 AS SELECT * FROM t1;").is_err());
+
+        // NOT NULL, DEFAULT and AUTOINCREMENT come along, UNIQUE does not
+        s.execute("CREATE TABLE u1 (id int primary key autoincrement, n int not null, d varchar default 'x', k int unique) WITH INSERTION ORDER;")?;
+        s.execute("INSERT INTO u1 (n, k) VALUES (1, 7);")?;
+        s.execute("CREATE TABLE u2 AS SELECT * FROM u1;")?;
+        let txn = kvengine.begin()?;
+        let (u1, u2) = (txn.must_get_table("u1".into())?, txn.must_get_table("u2".into())?);
+        txn.commit()?;
+        assert_eq!(u1.columns, u2.columns);
+        assert_eq!(u1.unique, vec![vec!["k".to_string()]]);
+        assert!(u2.unique.is_empty() && !u2.insertion_order);
+        assert!(s.execute("INSERT INTO u2 (n, k) VALUES (NULL, 8);").is_err());
+        s.execute("INSERT INTO u2 (n, k) VALUES (2, 7);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM u2;")?[1], vec![Value::Integer(2), Value::Integer(2), Value::String("x".into()), Value::Integer(7)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_or_replace_table() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        s.execute("CREATE TABLE t_other (a int primary key);")?;
+        s.execute("INSERT INTO t VALUES (1, 1), (2, 2);")?;
+        s.execute("INSERT INTO t_other VALUES (1);")?;
+        assert!(s.execute("CREATE TABLE t (a int primary key);").is_err());
+
+        s.execute("CREATE OR REPLACE TABLE t (x varchar primary key, y float);")?;
+        match s.execute("SELECT * FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["x".to_string(), "y".to_string()]);
+                assert!(rows.is_empty());
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        s.execute("INSERT INTO t VALUES ('a', 1.5);")?;
+
+        // Other tables are untouched; replacing a missing table just creates it
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t_other;")?.len(), 1);
+        s.execute("CREATE OR REPLACE TABLE t_new (a int primary key);")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_transaction_api() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        kvengine.session()?.execute("CREATE TABLE t (a int primary key, b varchar default 'x');")?;
+
+        let mut txn = kvengine.begin()?;
+        txn.insert("t", vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(2)],
+        ])?;
+        txn.create_row("t".into(), vec![Value::Integer(3), Value::Null])?;
+        assert_eq!(txn.scan_table("t".into())?.len(), 3);
+        txn.commit()?;
+
+        // Rolled back writes are discarded
+        let mut txn = kvengine.begin()?;
+        txn.insert("t", vec![vec![Value::Integer(4)]])?;
+        txn.rollback()?;
+
+        let mut txn = kvengine.begin()?;
+        assert_eq!(txn.scan_table("t".into())?, vec![
+            vec![Value::Integer(1), Value::String("a".into())],
+            vec![Value::Integer(2), Value::String("x".into())],
+            vec![Value::Integer(3), Value::Null],
+        ]);
+        assert!(txn.insert("missing", vec![vec![Value::Integer(1)]]).is_err());
+        txn.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_negative_default() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (k int primary key, a int default -1, b float default -2.5);")?;
+        s.execute("INSERT INTO t (k) VALUES (1);")?;
+        s.execute("INSERT INTO t VALUES (-2, -3, -0.5);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![
+            vec![Value::Integer(-2), Value::Integer(-3), Value::Float(-0.5)],
+            vec![Value::Integer(1), Value::Integer(-1), Value::Float(-2.5)],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_where() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        s.execute("INSERT INTO t VALUES (1, 1), (2, 2), (3, 3);")?;
+
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t WHERE true;")?.len(), 3);
+        match s.execute("SELECT * FROM t WHERE false;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["a".to_string(), "b".to_string()]);
+                assert!(rows.is_empty());
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("SELECT * FROM t WHERE 1;").is_err());
+        assert!(s.execute("SELECT * FROM missing WHERE false;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_as_of_version() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        // version 1 and 2
+        s.execute("CREATE TABLE t (a int primary key, b varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 'old');")?;
+        // version 3 and 4 overwrite the row
+        s.execute("CREATE OR REPLACE TABLE t (a int primary key, b varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 'new');")?;
+
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t AS OF VERSION 2;")?, vec![vec![Value::Integer(1), Value::String("old".into())]]);
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![vec![Value::Integer(1), Value::String("new".into())]]);
+        // the table did not exist yet
+        assert!(s.execute("SELECT * FROM t AS OF VERSION 0;").is_err());
+        assert!(s.execute("SELECT * FROM t AS OF VERSION 100;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_count_rows_matching() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        for i in 0..10 {
+            s.execute(&format!("INSERT INTO t VALUES ({}, {});", i, i % 4))?;
+        }
+
+        // Only the count comes back, the rows are never exposed
+        let predicate = Parser::new("SELECT * FROM t WHERE a > 6;").parse()?;
+        let predicate = match predicate {
+            Statement::Select { filter: Some(filter), .. } => filter,
+            stmt => panic!("unexpected statement {:?}", stmt),
+        };
+        let mut txn = kvengine.begin()?;
+        assert_eq!(txn.count_rows_matching("t".into(), Some(&predicate))?, 3);
+        assert_eq!(txn.count_rows_matching("t".into(), None)?, 10);
+        txn.commit()?;
+
+        match s.execute("SELECT COUNT(*) FROM t WHERE b = 1;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["count".to_string()]);
+                assert_eq!(rows, vec![vec![Value::Integer(3)]]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t WHERE false;")?, vec![vec![Value::Integer(0)]]);
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t WHERE b < 1;")?.iter().map(|r| r[0].clone()).collect::<Vec<_>>(),
+            vec![Value::Integer(0), Value::Integer(4), Value::Integer(8)]);
+        assert!(s.execute("SELECT COUNT(*) FROM t WHERE c = 1;").is_err());
+        assert!(s.execute("SELECT COUNT(*) FROM t WHERE a = 'x';").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_limit() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int, c varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 2, 'x'), (2, 1, 'y'), (3, 2, null), (4, 1, 'z'), (5, 3, 'w');")?;
+
+        let first = |rows: Vec<Row>| rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        let ints = |v: Vec<i64>| v.into_iter().map(Value::Integer).collect::<Vec<_>>();
+
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY b, a DESC;")?), ints(vec![4, 2, 3, 1, 5]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY b DESC, a LIMIT 3;")?), ints(vec![5, 1, 3]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY b DESC, a LIMIT 2 OFFSET 1;")?), ints(vec![1, 3]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY c LIMIT 2;")?), ints(vec![3, 5]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t WHERE b > 1 LIMIT 2;")?), ints(vec![1, 3]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t OFFSET 4;")?), ints(vec![5]));
+        assert!(s.execute("SELECT * FROM t ORDER BY d LIMIT 1;").is_err());
+        // A LIMIT far past the rows neither allocates for it nor overflows with OFFSET
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY a LIMIT 9223372036854775807;")?), ints(vec![1, 2, 3, 4, 5]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY a DESC LIMIT 9223372036854775807 OFFSET 9223372036854775807;")?), ints(vec![]));
+        assert_eq!(first(query_rows(&mut s, "SELECT * FROM t ORDER BY a DESC LIMIT 9223372036854775807 OFFSET 3;")?), ints(vec![2, 1]));
+
+        // Ties keep the order of the full sort, LIMIT takes a prefix of it
+        s.execute("CREATE TABLE u (id int primary key, k int);")?;
+        s.execute("INSERT INTO u VALUES (1, 0), (2, 0), (3, 0), (4, 0), (5, 0), (6, 0);")?;
+        assert_eq!(first(query_rows(&mut s, "SELECT id, k FROM u ORDER BY k;")?), ints(vec![1, 2, 3, 4, 5, 6]));
+        assert_eq!(first(query_rows(&mut s, "SELECT id, k FROM u ORDER BY k LIMIT 3;")?), ints(vec![1, 2, 3]));
+        assert_eq!(first(query_rows(&mut s, "SELECT id, k FROM u ORDER BY k LIMIT 2 OFFSET 3;")?), ints(vec![4, 5]));
+
+        // ORDER BY a column that is not selected, or an output alias
+        s.execute("UPDATE u SET k = 10 - id;")?;
+        assert_eq!(first(query_rows(&mut s, "SELECT id FROM u ORDER BY k;")?), ints(vec![6, 5, 4, 3, 2, 1]));
+        assert_eq!(first(query_rows(&mut s, "SELECT id FROM u ORDER BY k LIMIT 2 OFFSET 1;")?), ints(vec![5, 4]));
+        assert_eq!(first(query_rows(&mut s, "SELECT id, k * 2 AS j FROM u ORDER BY j DESC LIMIT 2;")?), ints(vec![1, 2]));
+        assert!(s.execute("SELECT DISTINCT id FROM u ORDER BY k;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_arithmetic() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, a int null);")?;
+        s.execute("INSERT INTO t VALUES (1, 10), (2, null), (3, -4);")?;
+
+        match s.execute("SELECT id, a + 1, a * 2 - id FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["id".to_string(), "?column?".to_string(), "?column?".to_string()]);
+                assert_eq!(rows, vec![
+                    vec![Value::Integer(1), Value::Integer(11), Value::Integer(19)],
+                    vec![Value::Integer(2), Value::Null, Value::Null],
+                    vec![Value::Integer(3), Value::Integer(-3), Value::Integer(-11)],
+                ]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        // NULL + 1 > 5 is NULL, the row is filtered out
+        assert_eq!(query_rows(&mut s, "SELECT id FROM t WHERE a + 1 > 5;")?, vec![vec![Value::Integer(1)]]);
+        assert!(s.execute("SELECT a / 0 FROM t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_alias() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 'x'), (2, 'y');")?;
+
+        match s.execute("SELECT a AS id, b AS val, a * 10, a + 1 AS next FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["id".to_string(), "val".to_string(), "?column?".to_string(), "next".to_string()]);
+                assert_eq!(rows[1], vec![2.into(), "y".into(), 20.into(), 3.into()]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        match s.execute("SELECT COUNT(*) AS n FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["n".to_string()]);
+                assert_eq!(rows, vec![vec![2.into()]]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_explain() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+
+        match s.execute("EXPLAIN SELECT * FROM t;")? {
+            ResultSet::Explain { plan_text } => assert_eq!(plan_text, "Scan t"),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Nothing is run, the table is neither read nor written
+        s.execute("EXPLAIN INSERT INTO t VALUES (1, 2);")?;
+        s.execute("EXPLAIN SELECT * FROM missing;")?;
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?, vec![vec![0.into()]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_constant_folding() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        s.execute("INSERT INTO t VALUES (1, 3), (2, 3), (3, 4);")?;
+
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t WHERE a = 1 + 1 AND 2 > 1;")?, vec![vec![Value::Integer(2), Value::Integer(3)]]);
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t WHERE b = 2 + 1 OR false;")?, vec![vec![Value::Integer(2)]]);
+        assert!(s.execute("SELECT * FROM t WHERE b = 1 / 0;").is_err());
+
+        // What folding drops is still checked against the schema
+        for sql in ["SELECT * FROM t WHERE zz = 1 OR true;", "SELECT a FROM t WHERE false AND zz = 1;",
+            "SELECT COUNT(*) FROM t WHERE (t.zz > 1 OR 1 = 1) AND b = 3;", "DELETE FROM t WHERE upper(zz) = 'X' AND 1 = 0;",
+            "UPDATE t SET b = 1 WHERE true OR zz = 1;", "CREATE TABLE t2 AS SELECT * FROM t WHERE zz = 1 OR true;"] {
+            match s.execute(sql) {
+                Err(Error::Internal(msg)) => assert!(msg == "Column zz not found" || msg == "Column t.zz not found", "{}", msg),
+                r => panic!("unexpected result {:?} for {}", r, sql),
+            }
+        }
+        assert!(s.execute("EXPLAIN SELECT * FROM t WHERE zz = 1 OR true;").is_ok());
+        // An empty result stands only for a valid predicate
+        assert!(s.execute("SELECT * FROM t WHERE zz = 1 AND zz = 2;").is_err());
+        assert!(s.execute("SELECT * FROM t WHERE b = 'x' AND b = 'y';").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_columns() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, a int, b int, f float, c varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 1, 1, 1.0, 'x'), (2, 1, 2, 2.5, 'y'), (3, NULL, 3, 3.0, NULL), (4, 4, NULL, NULL, 'z'), (5, NULL, NULL, 0.5, 'x');")?;
+        let ids = |rows: Vec<Row>| rows.into_iter().map(|row| row[0].clone()).collect::<Vec<_>>();
+
+        // NULL on either side is unknown, never a match
+        assert_eq!(ids(query_rows(&mut s, "SELECT * FROM t WHERE a = b;")?), vec![1.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id FROM t WHERE a < b;")?), vec![2.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id FROM t WHERE a != b;")?), vec![2.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id FROM t WHERE c <> 'x';")?), vec![2.into(), 4.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id FROM t WHERE t.b >= a OR a = id;")?), vec![1.into(), 2.into(), 4.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id FROM t WHERE b = f;")?), vec![1.into(), 3.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT id FROM t WHERE b + 1 > id * 1 AND a <= b;")?), vec![1.into(), 2.into()]);
+        assert_eq!(ids(query_rows(&mut s, "SELECT COUNT(*) FROM t WHERE id = b;")?), vec![3.into()]);
+
+        // Types are checked before any row is read
+        s.execute("DELETE FROM t;")?;
+        match s.execute("SELECT * FROM t WHERE a = c;") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "Cannot compare column a (Integer) with column c (String)"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("DELETE FROM t WHERE id = 1 AND c > f;").is_err());
+        assert!(s.execute("SELECT COUNT(*) FROM t WHERE c = b;").is_err());
+        // A column and a constant as well
+        match s.execute("SELECT * FROM t WHERE b = 'x';") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "Cannot compare column b (Integer) with 'x'"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("SELECT * FROM t WHERE 1.5 < c;").is_err());
+        assert!(s.execute("SELECT * FROM t WHERE f = 1 AND c = 'x' AND a = NULL;").is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_expressions() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar, c float null);")?;
+
+        s.execute("INSERT INTO t VALUES (1 + 1, UPPER('a'), 3 * 0.5), (10 / 5 * 2, lower('MiX'), null);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![
+            vec![2.into(), "A".into(), Value::Float(1.5)],
+            vec![4.into(), "mix".into(), Value::Null],
+        ]);
+
+        assert!(s.execute("INSERT INTO t VALUES (5, UPPER(1), null);").is_err());
+        assert!(s.execute("INSERT INTO t VALUES (1 / 0, 'x', null);").is_err());
+        assert!(s.execute("INSERT INTO t VALUES (a, 'x', null);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_batch_atomic() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        let results = s.execute_batch_atomic("
+            CREATE TABLE t (a int primary key, b int);
+            INSERT INTO t VALUES (1, 1), (2, 2);
+            SELECT COUNT(*) FROM t
+        ")?;
+        assert!(matches!(results[..], [ResultSet::CreateTable { .. }, ResultSet::Insert { count: 2 }, ResultSet::Scan { .. }]));
+
+        // The duplicate key fails the third statement, the first two are undone
+        assert!(s.execute_batch_atomic("
+            INSERT INTO t VALUES (3, 3);
+            CREATE TABLE t2 (a int primary key);
+            INSERT INTO t VALUES (1, 4);
+            INSERT INTO t VALUES (5, 5);
+        ").is_err());
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t;")?, vec![vec![1.into()], vec![2.into()]]);
+        assert!(s.execute("SELECT * FROM t2;").is_err());
+
+        // A parse error runs nothing
+        assert!(s.execute_batch_atomic("INSERT INTO t VALUES (6, 6); INSERT INTO;").is_err());
+        assert!(s.execute_batch_atomic("SELECT * FROM t AS OF VERSION 1;").is_err());
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?, vec![vec![2.into()]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_order_by_expression() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, a int, b int, name varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 5, 1, 'b'), (2, 1, 1, 'C'), (3, 2, 9, 'a'), (4, 3, 0, 'D');")?;
+
+        let ids = |r: ResultSet| match r {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns.len(), 4);
+                rows.into_iter().map(|row| row[0].clone()).collect::<Vec<_>>()
+            }
+            r => panic!("unexpected result {:?}", r),
+        };
+        // sums: 6, 2, 11, 3
+        assert_eq!(ids(s.execute("SELECT * FROM t ORDER BY a + b;")?), vec![2.into(), 4.into(), 1.into(), 3.into()]);
+        assert_eq!(ids(s.execute("SELECT * FROM t ORDER BY a + b DESC LIMIT 2;")?), vec![3.into(), 1.into()]);
+        assert_eq!(ids(s.execute("SELECT * FROM t ORDER BY b, a * -1;")?), vec![4.into(), 1.into(), 2.into(), 3.into()]);
+        assert_eq!(ids(s.execute("SELECT * FROM t ORDER BY LOWER(name);")?), vec![3.into(), 1.into(), 2.into(), 4.into()]);
+
+        assert!(s.execute("SELECT * FROM t ORDER BY a + z;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_delete() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 'x'), (2, 'y'), (3, 'x'), (4, 'z'), (5, 'x');")?;
+
+        let keys = |rows: Vec<Row>| rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        assert!(matches!(s.execute("DELETE FROM t WHERE a = 2;")?, ResultSet::Delete { count: 1 }));
+        assert!(matches!(s.execute("DELETE FROM t WHERE a = 9;")?, ResultSet::Delete { count: 0 }));
+        assert_eq!(keys(query_rows(&mut s, "SELECT a FROM t;")?), vec![1.into(), 3.into(), 4.into(), 5.into()]);
+        assert!(matches!(s.execute("DELETE FROM t WHERE 4 < a;")?, ResultSet::Delete { count: 1 }));
+        assert!(matches!(s.execute("DELETE FROM t WHERE b = 'x' AND a > 1 OR a = 9;")?, ResultSet::Delete { count: 1 }));
+        assert!(matches!(s.execute("DELETE FROM t WHERE b = 'x';")?, ResultSet::Delete { count: 1 }));
+        assert_eq!(keys(query_rows(&mut s, "SELECT a FROM t;")?), vec![4.into()]);
+        assert!(matches!(s.execute("DELETE FROM t;")?, ResultSet::Delete { count: 1 }));
+        assert_eq!(keys(query_rows(&mut s, "SELECT a FROM t;")?), vec![]);
+        assert!(s.execute("DELETE FROM missing;").is_err());
+
+        // Rolled back deletes leave the rows, a committed one frees its unique value
+        s.execute("CREATE TABLE u (a int primary key, b varchar unique);")?;
+        s.execute("INSERT INTO u VALUES (1, 'x'), (2, 'y');")?;
+        s.execute("BEGIN;")?;
+        assert!(matches!(s.execute("DELETE FROM u;")?, ResultSet::Delete { count: 2 }));
+        s.execute("ROLLBACK;")?;
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM u;")?, vec![vec![2.into()]]);
+        assert!(matches!(s.execute("DELETE FROM u WHERE b = 'x';")?, ResultSet::Delete { count: 1 }));
+        s.execute("INSERT INTO u VALUES (3, 'x');")?;
+        assert!(s.execute("INSERT INTO u VALUES (4, 'y');").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, a int, b varchar unique);")?;
+        s.execute("INSERT INTO t VALUES (1, 0, 'x'), (2, 0, 'y'), (3, 0, 'x2'), (4, 7, 'z');")?;
+
+        // WHERE reads b, which is not set
+        assert!(matches!(s.execute("UPDATE t SET a = 1 WHERE b = 'x' OR b = 'z';")?, ResultSet::Update { count: 2 }));
+        assert!(matches!(s.execute("UPDATE t SET a = 5 WHERE b = 'none';")?, ResultSet::Update { count: 0 }));
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?.iter().map(|r| r[1].clone()).collect::<Vec<_>>(), vec![1.into(), 0.into(), 0.into(), 1.into()]);
+
+        // The expressions see the old row
+        s.execute("UPDATE t SET a = id * 10, id = id + 10 WHERE id = 2;")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?[3], vec![Value::Integer(12), Value::Integer(20), Value::String("y".into())]);
+
+        // The new row is checked as an inserted one
+        assert!(s.execute("UPDATE t SET b = 'x' WHERE id = 3;").is_err());
+        assert!(s.execute("UPDATE t SET id = 1 WHERE id = 3;").is_err());
+        assert!(s.execute("UPDATE t SET id = NULL WHERE id = 3;").is_err());
+        assert!(s.execute("UPDATE t SET c = 1;").is_err());
+        assert!(s.execute("UPDATE t SET a = 1, a = 2;").is_err());
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?.len(), 4);
+
+        // Keys only have to be unique once every row is updated
+        s.execute("UPDATE t SET id = id + 1;")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?.iter().map(|r| r[0].clone()).collect::<Vec<_>>(), vec![2.into(), 4.into(), 5.into(), 13.into()]);
+        assert!(s.execute("UPDATE t SET id = 1;").is_err());
+        assert!(s.execute("UPDATE t SET b = 'same' WHERE id < 5;").is_err());
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?.iter().map(|r| r[0].clone()).collect::<Vec<_>>(), vec![2.into(), 4.into(), 5.into(), 13.into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_limit_pushdown() -> Result<()> {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut s = KVEngine::new(CountingEngine { inner: MemoryEngine::new(), reads: reads.clone(), cancel_at: None }).session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        let values = (0..10000).map(|i| format!("({}, {})", i, i % 7)).collect::<Vec<_>>().join(", ");
+        s.execute(&format!("INSERT INTO t VALUES {};", values))?;
+        // Older versions and deleted rows are skipped, not counted
+        s.execute("UPDATE t SET b = 100 WHERE a < 3;")?;
+        s.execute("DELETE FROM t WHERE a = 1;")?;
+
+        let mut run = |sql: &str| -> Result<(Vec<Row>, usize)> {
+            reads.store(0, Ordering::Relaxed);
+            Ok((query_rows(&mut s, sql)?, reads.load(Ordering::Relaxed)))
+        };
+        let (rows, count) = run("SELECT * FROM t LIMIT 10;")?;
+        assert!(count < 30, "{} reads", count);
+        assert_eq!(rows.len(), 10);
+        assert_eq!(rows[0], vec![Value::Integer(0), Value::Integer(100)]);
+        assert_eq!(rows[1], vec![Value::Integer(2), Value::Integer(100)]);
+        assert_eq!(rows[9][0], Value::Integer(10));
+
+        let (rows, count) = run("SELECT b, a FROM t LIMIT 2 OFFSET 5;")?;
+        assert!(count < 30, "{} reads", count);
+        assert_eq!(rows, vec![vec![Value::Integer(6), Value::Integer(6)], vec![Value::Integer(0), Value::Integer(7)]]);
+
+        // A filter or an order needs every row
+        let (rows, count) = run("SELECT * FROM t WHERE b = 6 LIMIT 1;")?;
+        assert!(count >= 10000, "{} reads", count);
+        assert_eq!(rows, vec![vec![Value::Integer(6), Value::Integer(6)]]);
+        let (rows, count) = run("SELECT * FROM t ORDER BY a DESC LIMIT 1;")?;
+        assert!(count >= 10000, "{} reads", count);
+        assert_eq!(rows[0][0], Value::Integer(9999));
+        Ok(())
+    }
+
+    #[test]
+    fn test_cancel() -> Result<()> {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let token = Arc::new(AtomicBool::new(false));
+        // The engine sets the token once a scan has read 100 entries
+        let engine = CountingEngine { inner: MemoryEngine::new(), reads: reads.clone(), cancel_at: Some((100, token.clone())) };
+        let mut s = KVEngine::new(engine).session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        let values = (0..1000).map(|i| format!("({}, {})", i, i % 7)).collect::<Vec<_>>().join(", ");
+        s.execute(&format!("INSERT INTO t VALUES {};", values))?;
+        let is_cancelled = |result: Result<ResultSet>| matches!(result, Err(Error::Internal(e)) if e == "query cancelled");
+        let mut run = |sql: &str| {
+            reads.store(0, Ordering::Relaxed);
+            token.store(false, Ordering::Relaxed);
+            s.execute_cancellable(sql, &token)
+        };
+
+        // Set mid-scan, the statement stops instead of returning rows,
+        // the storage is not read to the end of the table
+        assert!(is_cancelled(run("SELECT * FROM t;")));
+        assert!(reads.load(Ordering::Relaxed) < 200, "{} reads", reads.load(Ordering::Relaxed));
+        assert!(is_cancelled(run("SELECT COUNT(*) FROM t WHERE b = 3;")));
+        assert!(is_cancelled(run("DELETE FROM t WHERE b = 1;")));
+        // Fewer reads than that, it is never set
+        assert!(matches!(run("SELECT * FROM t LIMIT 10;")?, ResultSet::Scan { rows, .. } if rows.len() == 10));
+
+        // Set before the statement, inside a transaction it is rolled back
+        s.execute("BEGIN;")?;
+        token.store(true, Ordering::Relaxed);
+        assert!(is_cancelled(s.execute_cancellable("UPDATE t SET b = 0 WHERE a = 1;", &token)));
+        s.execute("ROLLBACK;")?;
+        // The token only applies to execute_cancellable
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t WHERE b = 1;")?, vec![vec![Value::Integer(143)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_primary_key_pushdown() -> Result<()> {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut s = KVEngine::new(CountingEngine { inner: MemoryEngine::new(), reads: reads.clone(), cancel_at: None }).session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        let values = (0..100).map(|i| format!("({}, {})", i, i % 7)).collect::<Vec<_>>().join(", ");
+        s.execute(&format!("INSERT INTO t VALUES {};", values))?;
+
+        let mut count_reads = |sql: &str| -> Result<usize> {
+            reads.store(0, Ordering::Relaxed);
+            s.execute(sql)?;
+            Ok(reads.load(Ordering::Relaxed))
+        };
+        // The point lookups read a handful of entries, the full scans all 100 rows
+        let point = count_reads("DELETE FROM t WHERE a = 50;")?;
+        assert!(point < 10, "{} reads", point);
+        let point = count_reads("SELECT * FROM t WHERE a = 51;")?;
+        assert!(point < 10, "{} reads", point);
+        let range = count_reads("DELETE FROM t WHERE a > 95;")?;
+        assert!(range < 30, "{} reads", range);
+        let range = count_reads("SELECT * FROM t WHERE a >= 20 AND b = 1 AND a < 25;")?;
+        assert!(range < 20, "{} reads", range);
+        // An empty intersection reads nothing
+        let empty = count_reads("SELECT * FROM t WHERE a > 30 AND a < 30;")?;
+        assert!(empty < 5, "{} reads", empty);
+        let full = count_reads("DELETE FROM t WHERE b = 3;")?;
+        assert!(full >= 95, "{} reads", full);
+        // A key compared with another type is not pushed down
+        let full = count_reads("SELECT * FROM t WHERE a = 10.0;")?;
+        assert!(full >= 80, "{} reads", full);
+        // Contradicting equalities read no row, as a false constant
+        let none = count_reads("SELECT * FROM t WHERE 1 = 0;")?;
+        assert_eq!(count_reads("SELECT * FROM t WHERE b = 1 AND b + 1 > 0 AND t.b = 2;")?, none);
+        assert!(none < 5, "{} reads", none);
+
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?, vec![vec![(100 - 1 - 4 - 14).into()]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregate() -> Result<()> {
+        let engine = KVEngine::memory();
+        let mut s = engine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b float, c string);")?;
+
+        // One row even without any input rows
+        match s.execute("SELECT COUNT(*), SUM(a) FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["count".to_string(), "sum".to_string()]);
+                assert_eq!(rows, vec![vec![Value::Integer(0), Value::Null]]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(query_rows(&mut s, "SELECT MIN(a), MAX(c), AVG(b), COUNT(b) FROM t;")?,
+            vec![vec![Value::Null, Value::Null, Value::Null, Value::Integer(0)]]);
+
+        s.execute("INSERT INTO t VALUES (1, 1.5, 'x'), (2, null, 'z'), (3, 2.5, null), (4, 2.0, 'y');")?;
+        match s.execute("SELECT COUNT(*), COUNT(b) AS n, SUM(a), MIN(c), MAX(c), AVG(b), SUM(b) FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns[1], "n");
+                assert_eq!(rows, vec![vec![
+                    Value::Integer(4), Value::Integer(3), Value::Integer(10),
+                    Value::String("x".into()), Value::String("z".into()), Value::Float(2.0), Value::Float(6.0),
+                ]]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert_eq!(query_rows(&mut s, "SELECT SUM(a), AVG(a) FROM t WHERE a > 10;")?, vec![vec![Value::Null, Value::Null]]);
+
+        assert!(s.execute("SELECT SUM(c) FROM t;").is_err());
+        assert!(s.execute("SELECT a, SUM(a) FROM t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ident_case() -> Result<()> {
+        let engine = KVEngine::memory();
+        let mut s = engine.session()?;
+        s.set_ident_case(IdentCase::Preserve);
+        s.execute("CREATE TABLE T (A int primary key, Bb int);")?;
+        s.execute("insert into T values (1, 2);")?;
+        match s.execute("SELECT A, Bb FROM T;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["A".to_string(), "Bb".to_string()]);
+                assert_eq!(rows, vec![vec![Value::Integer(1), Value::Integer(2)]]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("SELECT a FROM t;").is_err());
+        assert!(s.execute("SELECT a FROM T;").is_err());
+        s.execute("CREATE TABLE N (A int primary key, S varchar);")?;
+        s.execute("INSERT INTO N VALUES (1, 'x'), (2, 'y');")?;
+
+        // Function names match in any case, whatever the folding
+        for ident_case in [IdentCase::Preserve, IdentCase::Upper] {
+            s.set_ident_case(ident_case);
+            assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM N;")?, vec![vec![Value::Integer(2)]]);
+            assert_eq!(query_rows(&mut s, "SELECT UPPER(S), Lower(S) FROM N WHERE A = 1;")?, vec![vec![Value::String("X".into()), Value::String("x".into())]]);
+        }
+        s.set_ident_case(IdentCase::Preserve);
+
+        // The default folds to lower case, the stored names no longer match
+        let mut s = engine.session()?;
+        assert!(s.execute("SELECT A FROM T;").is_err());
+        s.set_ident_case(IdentCase::Upper);
+        s.execute("create table u (a int primary key);")?;
+        assert!(engine.session()?.execute("SELECT * FROM U;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_explicit_transaction() -> Result<()> {
+        let engine = KVEngine::memory();
+        let mut s = engine.session()?;
+        let mut other = engine.session()?;
+        s.execute("CREATE TABLE t (a int primary key);")?;
+
+        assert!(matches!(s.execute("BEGIN;")?, ResultSet::Begin));
+        s.execute("INSERT INTO t VALUES (1);")?;
+        s.execute("INSERT INTO t VALUES (2);")?;
+        // Uncommitted, only the session itself sees the rows
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 0.into());
+        let result = s.execute("COMMIT;")?;
+        assert!(matches!(result, ResultSet::Commit));
+        assert_eq!(result.to_string(), "COMMIT");
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+
+        s.execute("BEGIN;")?;
+        s.execute("INSERT INTO t VALUES (3);")?;
+        assert!(matches!(s.execute("ROLLBACK;")?, ResultSet::Rollback));
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+
+        // A failing statement rolls back the transaction, nothing but
+        // ROLLBACK runs until the user ends it
+        s.execute("BEGIN;")?;
+        s.execute("INSERT INTO t VALUES (4);")?;
+        assert!(s.execute("INSERT INTO t VALUES (5), (1);").is_err());
+        for sql in ["INSERT INTO t VALUES (7);", "SELECT * FROM t;", "COMMIT;", "BEGIN;"] {
+            match s.execute(sql) {
+                Err(Error::Internal(msg)) => assert_eq!(msg, "transaction aborted, only ROLLBACK is allowed"),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        assert!(s.execute_batch_atomic("INSERT INTO t VALUES (7);").is_err());
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+        assert!(matches!(s.execute("ROLLBACK;")?, ResultSet::Rollback));
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+
+        for sql in ["COMMIT;", "ROLLBACK;"] {
+            match s.execute(sql) {
+                Err(Error::Internal(msg)) => assert_eq!(msg, "no transaction in progress"),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        s.execute("BEGIN;")?;
+        assert!(s.execute("BEGIN;").is_err());
+        assert!(s.execute("SELECT * FROM t AS OF VERSION 1;").is_err());
+        s.execute("INSERT INTO t VALUES (6);")?;
+        // Dropping the session rolls back
+        drop(s);
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+        Ok(())
+    }
+
+    #[test]
+    fn test_autocommit_off() -> Result<()> {
+        let engine = KVEngine::memory();
+        let mut s = engine.session()?;
+        let mut other = engine.session()?;
+        s.execute("CREATE TABLE t (a int primary key);")?;
+
+        // Statements pile up in an implicit transaction until COMMIT
+        s.set_autocommit(false)?;
+        s.execute("INSERT INTO t VALUES (1);")?;
+        s.execute("INSERT INTO t VALUES (2);")?;
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 0.into());
+        assert!(matches!(s.execute("COMMIT;")?, ResultSet::Commit));
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+
+        // The next statement opens the next one
+        s.execute("INSERT INTO t VALUES (3);")?;
+        assert!(matches!(s.execute("ROLLBACK;")?, ResultSet::Rollback));
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+        s.execute("COMMIT;")?;
+        assert!(s.execute("COMMIT;").is_err());
+        assert!(s.execute("ROLLBACK;").is_err());
+
+        // A failed statement aborts the transaction, it is not silently
+        // replaced by a new one and COMMIT reports the loss
+        s.execute("DELETE FROM t;")?;
+        s.execute("COMMIT;")?;
+        s.execute("INSERT INTO t VALUES (1);")?;
+        s.execute("INSERT INTO t VALUES (2);")?;
+        assert!(s.execute("INSERT INTO t VALUES (2);").is_err());
+        assert!(s.execute("INSERT INTO t VALUES (3);").is_err());
+        assert!(s.execute("COMMIT;").is_err());
+        assert!(s.set_autocommit(true).is_err());
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 0.into());
+        s.execute("ROLLBACK;")?;
+        s.execute("INSERT INTO t VALUES (1), (2);")?;
+        s.execute("COMMIT;")?;
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+
+        // Turning autocommit back on commits what is open
+        s.execute("INSERT INTO t VALUES (4);")?;
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 2.into());
+        s.set_autocommit(true)?;
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 3.into());
+        s.execute("INSERT INTO t VALUES (5);")?;
+        assert_eq!(query_rows(&mut other, "SELECT COUNT(*) FROM t;")?[0][0], 4.into());
+        assert!(s.execute("COMMIT;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_insert() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar, c int default 7);")?;
+
+        let rows = (0..1000).map(|i| vec![Value::Integer(i), Value::String(format!("row{}", i))]).collect::<Vec<Row>>();
+        let mut txn = kvengine.begin()?;
+        assert_eq!(txn.bulk_insert("t", rows.clone())?, 1000);
+        txn.commit()?;
+
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t WHERE a = 999;")?, vec![vec![Value::Integer(999), Value::String("row999".into()), Value::Integer(7)]]);
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?, vec![vec![Value::Integer(1000)]]);
+
+        // Not empty any more, the safe path finds the duplicate
+        let mut txn = kvengine.begin()?;
+        assert!(txn.bulk_insert("t", vec![vec![Value::Integer(5), Value::String("dup".into())]]).is_err());
+        txn.rollback()?;
+
+        // With another writer active the fast path is refused,
+        // so its uncommitted row still conflicts
+        s.execute("CREATE TABLE t2 (a int primary key);")?;
+        let mut other = kvengine.begin()?;
+        other.insert("t2", vec![vec![Value::Integer(1)]])?;
+        let mut txn = kvengine.begin()?;
+        assert_eq!(txn.bulk_insert("t2", vec![vec![Value::Integer(1)]]), Err(crate::error::Error::WriteConflict));
+        txn.rollback()?;
+        other.commit()?;
+
+        // Duplicates among the loaded rows and bad rows are rejected
+        s.execute("CREATE TABLE t3 (a int primary key);")?;
+        let mut txn = kvengine.begin()?;
+        assert!(txn.bulk_insert("t3", vec![vec![Value::Integer(1)], vec![Value::Integer(1)]]).is_err());
+        assert!(txn.bulk_insert("t3", vec![vec![Value::String("x".into())]]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_session_status() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        assert_eq!(s.last_error(), None);
+        assert_eq!(s.executed_count(), 0);
+
+        s.execute("CREATE TABLE t (a int primary key);")?;
+        s.execute("INSERT INTO t VALUES (1);")?;
+        let err = s.execute("INSERT INTO t VALUES (1);").unwrap_err();
+        assert_eq!(s.last_error(), Some(&err));
+        assert_eq!(s.executed_count(), 2);
+
+        // Kept after a success, replaced by the next failure
+        s.execute("SELECT * FROM t;")?;
+        assert_eq!(s.last_error(), Some(&err));
+        assert!(s.execute("SELEC * FROM t;").is_err());
+        assert!(matches!(s.last_error(), Some(Error::Parse(_))));
+        assert_eq!(s.executed_count(), 3);
+
+        // Every statement of a batch counts
+        s.execute_batch_atomic("INSERT INTO t VALUES (2); INSERT INTO t VALUES (3);")?;
+        assert_eq!(s.executed_count(), 5);
+        assert!(s.execute_batch_atomic("INSERT INTO t VALUES (4); INSERT INTO t VALUES (1);").is_err());
+        assert_eq!(s.executed_count(), 5);
+        assert!(matches!(s.last_error(), Some(Error::Internal(msg)) if msg.contains("Duplicate")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_boolean_order() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b bool);")?;
+        s.execute("INSERT INTO t VALUES (1, true), (2, false), (3, null), (4, true), (5, false);")?;
+        let keys = |rows: Vec<Row>| rows.into_iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+
+        // NULL first, then false before true
+        assert_eq!(keys(query_rows(&mut s, "SELECT a, b FROM t ORDER BY b, a;")?), vec![3.into(), 2.into(), 5.into(), 1.into(), 4.into()]);
+        assert_eq!(keys(query_rows(&mut s, "SELECT a, b FROM t ORDER BY b DESC, a LIMIT 3;")?), vec![1.into(), 4.into(), 2.into()]);
+        assert_eq!(keys(query_rows(&mut s, "SELECT a FROM t WHERE b > false;")?), vec![1.into(), 4.into()]);
+        assert_eq!(keys(query_rows(&mut s, "SELECT a FROM t WHERE b <= false;")?), vec![2.into(), 5.into()]);
+        assert!(s.execute("SELECT a FROM t WHERE b > 0;").is_err());
+
+        // A boolean primary key is scanned in the same order
+        s.execute("CREATE TABLE f (b bool primary key, n int);")?;
+        s.execute("INSERT INTO f VALUES (true, 1), (false, 0);")?;
+        assert_eq!(keys(query_rows(&mut s, "SELECT * FROM f;")?), vec![false.into(), true.into()]);
+        assert_eq!(keys(query_rows(&mut s, "SELECT * FROM f WHERE b > false;")?), vec![true.into()]);
+        assert_eq!(keys(query_rows(&mut s, "SELECT * FROM f WHERE b < true;")?), vec![false.into()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_comparison() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, name varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, 'zoe'), (2, 'Mia'), (3, 'm'), (4, 'mia'), (5, 'émile'), (6, 'ana'), (7, 'Zed'), (8, 'mi');")?;
+        let names = |rows: Vec<Row>| rows.into_iter().map(|r| r[0].to_string()).collect::<Vec<_>>();
+
+        // Byte order: upper case before lower case, non-ASCII after ASCII
+        let sorted = names(query_rows(&mut s, "SELECT name FROM t ORDER BY name;")?);
+        assert_eq!(sorted, vec!["Mia", "Zed", "ana", "m", "mi", "mia", "zoe", "émile"]);
+        // WHERE agrees with ORDER BY
+        for (sql, skip) in [("name > 'm'", 4), ("name >= 'm'", 3), ("name > 'zz'", 7)] {
+            let filtered = names(query_rows(&mut s, &format!("SELECT name FROM t WHERE {} ORDER BY name;", sql))?);
+            assert_eq!(filtered, sorted[skip..], "{}", sql);
+        }
+        for (sql, take) in [("name < 'm'", 3), ("name <= 'm'", 4), ("'mi' >= name", 5)] {
+            let filtered = names(query_rows(&mut s, &format!("SELECT name FROM t WHERE {} ORDER BY name;", sql))?);
+            assert_eq!(filtered, sorted[..take], "{}", sql);
+        }
+        let filtered = names(query_rows(&mut s, "SELECT name FROM t WHERE name >= 'mi' AND name <= 'zoe' ORDER BY name DESC;")?);
+        assert_eq!(filtered, vec!["zoe", "mia", "mi"]);
+        assert!(s.execute("SELECT name FROM t WHERE name > 1;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_omitted_columns() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, d int default 7, n int, r int not null);")?;
+
+        // Default, NULL for a nullable column, error for a required one
+        match s.execute("INSERT INTO t (a) VALUES (1);") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 0: Missing value for required column r"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        s.execute("INSERT INTO t (a, r) VALUES (1, 0);")?;
+        assert!(s.execute("INSERT INTO t VALUES (2);").is_err());
+        s.execute("INSERT INTO t (r, a) VALUES (5, 2);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![
+            vec![Value::Integer(1), Value::Integer(7), Value::Null, Value::Integer(0)],
+            vec![Value::Integer(2), Value::Integer(7), Value::Null, Value::Integer(5)],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_table() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key autoincrement);")?;
+        s.execute("INSERT INTO t VALUES (null), (null);")?;
+
+        let result = s.execute("DROP TABLE t;")?;
+        assert!(matches!(&result, ResultSet::DropTable { table_name } if table_name == "t"));
+        assert_eq!(result.to_string(), "DROP TABLE t");
+        assert!(s.execute("SELECT * FROM t;").is_err());
+        match s.execute("DROP TABLE t;") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "table t does not exist"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(matches!(s.execute("DROP TABLE IF EXISTS t;")?, ResultSet::DropTable { .. }));
+
+        // A new table of the same name starts empty, ids included
+        s.execute("CREATE TABLE t (a int primary key autoincrement);")?;
+        s.execute("INSERT INTO t VALUES (null);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![vec![Value::Integer(1)]]);
+
+        // Tables sharing the name as a prefix keep their rows, a rolled back drop keeps all
+        s.execute("CREATE TABLE t2 (a int primary key);")?;
+        s.execute("INSERT INTO t2 VALUES (5);")?;
+        s.execute("BEGIN;")?;
+        s.execute("DROP TABLE t;")?;
+        s.execute("ROLLBACK;")?;
+        s.execute("DROP TABLE t2;")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![vec![Value::Integer(1)]]);
+        s.execute("CREATE TABLE t2 (a int primary key);")?;
+        s.execute("INSERT INTO t2 VALUES (6);")?;
+        s.execute("DROP TABLE t;")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t2;")?, vec![vec![Value::Integer(6)]]);
+
+        s.disallow_ddl();
+        assert!(s.execute("DROP TABLE IF EXISTS t2;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_grid() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, name varchar, score float);")?;
+        s.execute("INSERT INTO t VALUES (1, 'ray', 9.5), (100, 'db', null);")?;
+
+        let result = s.execute("SELECT * FROM t;")?;
+        assert_eq!(result.render_grid(), [
+            "+-----+------+-------+",
+            "| id  | name | score |",
+            "+-----+------+-------+",
+            "|   1 | ray  |   9.5 |",
+            "| 100 | db   |  NULL |",
+            "+-----+------+-------+",
+        ].join("\n"));
+
+        // Computed columns are typed by their values
+        let result = s.execute("SELECT upper(name) AS n, id + 1000 FROM t;")?;
+        assert!(matches!(&result, ResultSet::Scan { types, .. }
+            if *types == vec![Some(DataTypes::String), Some(DataTypes::Integer)]));
+        assert_eq!(result.render_grid().lines().nth(3), Some("| RAY |     1001 |"));
+        assert_eq!(s.execute("DELETE FROM t;")?.render_grid(), "DELETE 2 rows");
+        Ok(())
+    }
+
+    #[test]
+    fn test_projection_order() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int, c varchar);")?;
+        // Unknown columns fail on an empty table as well, named in the error
+        match s.execute("SELECT c, d FROM t;") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "Column d not found"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match s.execute("SELECT b, a FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["b".to_string(), "a".to_string()]);
+                assert!(rows.is_empty());
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        s.execute("INSERT INTO t VALUES (1, 2, 'x'), (3, 4, 'y');")?;
+        match s.execute("SELECT c, a, t.b, a FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["c".to_string(), "a".to_string(), "t.b".to_string(), "a".to_string()]);
+                assert_eq!(rows, vec![
+                    vec![Value::String("x".into()), Value::Integer(1), Value::Integer(2), Value::Integer(1)],
+                    vec![Value::String("y".into()), Value::Integer(3), Value::Integer(4), Value::Integer(3)],
+                ]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The qualifier has to name the table
+        for sql in ["SELECT x.b FROM t;", "SELECT * FROM t WHERE x.a = 1;", "SELECT * FROM t ORDER BY x.a;",
+            "UPDATE t SET b = x.b;", "DELETE FROM t WHERE x.a = 1;", "EXPLAIN SELECT upper(x.c) FROM t;"] {
+            match s.execute(sql) {
+                Err(Error::Internal(msg)) => assert!(msg.starts_with("Column x."), "{}", msg),
+                r => panic!("unexpected result {:?} for {}", r, sql),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_scalar_functions_in_select() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar);")?;
+        s.execute("INSERT INTO t VALUES (1, null), (2, 'x');")?;
+        match s.execute("SELECT coalesce(b, 'none') AS b, nullif(a, 1), upper(b) FROM t;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["b".to_string(), "nullif".to_string(), "upper".to_string()]);
+                assert_eq!(rows, vec![
+                    vec![Value::String("none".into()), Value::Null, Value::Null],
+                    vec![Value::String("x".into()), Value::Integer(2), Value::String("X".into())],
+                ]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("SELECT coalesce(b, 'none'), COUNT(a) FROM t;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_defaults() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar default 'x', c int, ts int default current_timestamp);")?;
+
+        // Nothing is resolved when the table is read
+        let txn = kvengine.begin()?;
+        let table = txn.must_get_table("t".to_string())?;
+        txn.commit()?;
+        assert!(table.defaults.is_empty());
+
+        // CURRENT_TIMESTAMP is evaluated once for all rows of one INSERT
+        let values = (0..500).map(|i| format!("({})", i)).collect::<Vec<_>>().join(", ");
+        s.execute(&format!("INSERT INTO t (a) VALUES {};", values))?;
+        s.execute("INSERT INTO t (a, c) VALUES (1000, 5);")?;
+        let rows = query_rows(&mut s, "SELECT b, c, ts FROM t;")?;
+        assert_eq!(rows[0][..2], [Value::String("x".into()), Value::Null]);
+        assert_eq!(rows[500][..2], [Value::String("x".into()), Value::Integer(5)]);
+        assert!(rows[..500].iter().all(|r| r[2] == rows[0][2]));
+        assert!(matches!(rows[500][2], Value::Integer(ts) if ts > 0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_describe_default() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, c varchar default 'apple', ts int default current_timestamp);")?;
+
+        match s.execute("DESCRIBE t;")? {
+            ResultSet::Describe { schema } => {
+                assert!(schema.contains("c VARCHAR NULL DEFAULT 'apple'"), "{}", schema);
+                assert!(schema.contains("ts INTEGER NULL DEFAULT CURRENT_TIMESTAMP"), "{}", schema);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // The computed default is evaluated on insert
+        s.execute("INSERT INTO t (a) VALUES (1);")?;
+        let rows = query_rows(&mut s, "SELECT * FROM t;")?;
+        assert_eq!(rows[0][1], Value::String("apple".into()));
+        assert!(matches!(rows[0][2], Value::Integer(ts) if ts > 0));
+
+        assert!(s.execute("DESCRIBE missing;").is_err());
+        assert!(s.execute("CREATE TABLE t2 (a int primary key, b int default count(*));").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_statement_timestamp() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, ts int default current_timestamp);")?;
+
+        // One value for every row of the statement
+        s.execute("INSERT INTO t (a) VALUES (1), (2), (3);")?;
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        s.execute("INSERT INTO t (a) VALUES (4), (5);")?;
+        let rows = query_rows(&mut s, "SELECT ts FROM t;")?;
+        let ts = rows.iter().map(|r| r[0].clone()).collect::<Vec<_>>();
+        assert!(matches!(ts[0], Value::Integer(t) if t > 0));
+        assert_eq!(ts[..3], [ts[0].clone(), ts[0].clone(), ts[0].clone()]);
+        assert_eq!(ts[3], ts[4]);
+        assert_ne!(ts[0], ts[3]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_filter_on_unprojected_column() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        s.execute("INSERT INTO t VALUES (1, 1), (2, 0), (3, 1);")?;
+
+        match s.execute("SELECT a FROM t WHERE b = 1;")? {
+            ResultSet::Scan { columns, rows, .. } => {
+                assert_eq!(columns, vec!["a".to_string()]);
+                assert_eq!(rows, vec![vec![Value::Integer(1)], vec![Value::Integer(3)]]);
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_error_row_index() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key);")?;
+
+        match s.execute("INSERT INTO t VALUES (1), (2, 3), (4);") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 1: expected 1 value, got 2"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match s.execute("INSERT INTO t (a) VALUES (1), (2), ('x');") {
+            Err(Error::Internal(msg)) => assert!(msg.starts_with("row 2: "), "{}", msg),
+            r => panic!("unexpected result {:?}", r),
+        }
+        match s.execute("INSERT INTO t (a) VALUES (5), (6, 7);") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 1: expected 1 value, got 2"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // The statement is rolled back as a whole
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?, vec![vec![Value::Integer(0)]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert_reordered_columns() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar, c int default 0);")?;
+
+        s.execute("INSERT INTO t (b, a) VALUES ('x', 1), ('y', 2), ('z', 3);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![
+            vec![1.into(), "x".into(), 0.into()],
+            vec![2.into(), "y".into(), 0.into()],
+            vec![3.into(), "z".into(), 0.into()],
+        ]);
+
+        match s.execute("INSERT INTO t (c, a) VALUES (1, 4), (5), (6, 7);") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 1: expected 2 values, got 1"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(s.execute("INSERT INTO t (a, z) VALUES (4, 1);").is_err());
+        assert!(s.execute("INSERT INTO t (a, a) VALUES (4, 5);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_distinct_groups_nulls() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, b varchar, c int);")?;
+        s.execute("INSERT INTO t VALUES (1, NULL, 1), (2, 'x', 1), (3, NULL, 1), (4, 'y', 2), (5, NULL, 1), (6, 'x', 1);")?;
+
+        assert_eq!(query_rows(&mut s, "SELECT DISTINCT b FROM t ORDER BY b;")?, vec![
+            vec![Value::Null],
+            vec![Value::String("x".into())],
+            vec![Value::String("y".into())],
+        ]);
+        // NULL in one column of a multi-column group
+        assert_eq!(query_rows(&mut s, "SELECT DISTINCT b, c FROM t;")?, vec![
+            vec![Value::Null, Value::Integer(1)],
+            vec![Value::String("x".into()), Value::Integer(1)],
+            vec![Value::String("y".into()), Value::Integer(2)],
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_constraint() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (id int primary key, a int, b varchar, c int unique, UNIQUE (a, b));")?;
+        s.execute("INSERT INTO t VALUES (1, 1, 'x', 1);")?;
+        // Differing in either column is fine
+        s.execute("INSERT INTO t VALUES (2, 1, 'y', 2), (3, 2, 'x', 3);")?;
+
+        match s.execute("INSERT INTO t VALUES (4, 1, 'x', 4);") {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "row 0: Duplicate data for unique (a, b) in table t: (id=4, a=1, b=x, c=4)"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Duplicates within one statement, and on the single column set
+        assert!(s.execute("INSERT INTO t VALUES (5, 5, 'z', 5), (6, 5, 'z', 6);").is_err());
+        assert!(s.execute("INSERT INTO t VALUES (7, 7, 'z', 1);").is_err());
+
+        // A set that is all NULL never conflicts, a partly NULL one does
+        s.execute("INSERT INTO t VALUES (8, NULL, NULL, NULL), (9, NULL, NULL, NULL);")?;
+        s.execute("INSERT INTO t VALUES (10, NULL, 'x', 10);")?;
+        assert!(s.execute("INSERT INTO t VALUES (11, NULL, 'x', 11);").is_err());
+
+        assert_eq!(query_rows(&mut s, "SELECT COUNT(*) FROM t;")?, vec![vec![Value::Integer(6)]]);
+        assert!(s.execute("CREATE TABLE u (id int primary key, UNIQUE (nope));").is_err());
+
+        // Deleted and updated rows free their values
+        s.execute("DELETE FROM t WHERE id = 1;")?;
+        s.execute("INSERT INTO t VALUES (12, 1, 'x', 1);")?;
+        s.execute("UPDATE t SET c = c + 1 WHERE c > 0;")?;
+        s.execute("INSERT INTO t VALUES (13, 13, 'x', 1);")?;
+        assert!(s.execute("INSERT INTO t VALUES (14, 14, 'x', 2);").is_err());
+        s.execute("DROP TABLE t;")?;
+        s.execute("CREATE TABLE t (id int primary key, c int unique);")?;
+        s.execute("INSERT INTO t VALUES (1, 2);")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_unique_reads() -> Result<()> {
+        let reads = Arc::new(AtomicUsize::new(0));
+        let mut s = KVEngine::new(CountingEngine { inner: MemoryEngine::new(), reads: reads.clone(), cancel_at: None }).session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int unique);")?;
+        let values = (0..1000).map(|i| format!("({}, {})", i, i)).collect::<Vec<_>>().join(", ");
+        s.execute(&format!("INSERT INTO t VALUES {};", values))?;
+
+        // The unique value is looked up, not checked against every row
+        reads.store(0, Ordering::Relaxed);
+        s.execute("INSERT INTO t VALUES (1000, 1000);")?;
+        assert!(reads.load(Ordering::Relaxed) < 100, "{} reads", reads.load(Ordering::Relaxed));
+        assert!(s.execute("INSERT INTO t VALUES (1001, 500);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_sessions_share_engine() -> Result<()> {
+        let engine = KVEngine::memory();
+        let mut sessions = (0..8).map(|_| engine.session()).collect::<Result<Vec<_>>>()?;
+        sessions[0].execute("CREATE TABLE t (a int primary key, b int);")?;
+
+        // Each session sees the table and the rows committed by the others
+        for (i, s) in sessions.iter_mut().enumerate() {
+            s.execute(&format!("INSERT INTO t VALUES ({}, {});", i, i * 10))?;
+            assert_eq!(query_rows(s, "SELECT COUNT(*) FROM t;")?, vec![vec![Value::Integer(i as i64 + 1)]]);
+        }
+        // Including sessions opened from a clone of the engine
+        let mut late = engine.clone().session()?;
+        assert_eq!(query_rows(&mut late, "SELECT * FROM t WHERE a = 7;")?, vec![vec![Value::Integer(7), Value::Integer(70)]]);
+        assert!(sessions[3].execute("INSERT INTO t VALUES (7, 0);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_then_delete() -> Result<()> {
+        let engine = KVEngine::memory();
+        let mut s = engine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int);")?;
+        s.execute("INSERT INTO t VALUES (1, 10), (2, 20);")?;
+
+        let mut txn = engine.begin()?;
+        let mut other = engine.begin()?;
+        let pk = Value::Integer(1);
+        assert_eq!(txn.scan_table_range("t".into(), Some(pk.clone()), Some(pk.clone()))?, vec![vec![1.into(), 10.into()]]);
+        txn.delete_row("t".into(), &pk)?;
+
+        // Gone for the rest of the transaction, point lookup and scans alike
+        assert!(txn.scan_table_range("t".into(), Some(pk.clone()), Some(pk.clone()))?.is_empty());
+        assert_eq!(txn.scan_table("t".into())?, vec![vec![2.into(), 20.into()]]);
+        assert_eq!(txn.count_rows_matching("t".into(), None)?, 1);
+        // The key is free again
+        txn.create_row("t".into(), vec![1.into(), 11.into()])?;
+        assert_eq!(txn.scan_table("t".into())?.len(), 2);
+        txn.delete_row("t".into(), &pk)?;
+        txn.commit()?;
+
+        // Others only see it once committed
+        assert_eq!(other.scan_table("t".into())?.len(), 2);
+        other.commit()?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![vec![2.into(), 20.into()]]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_disallow_ddl() -> Result<()> {
+        let engine = KVEngine::memory();
+        engine.session()?.execute("CREATE TABLE t (a int primary key);")?;
+
+        let mut s = engine.session()?;
+        s.disallow_ddl();
+        for sql in ["CREATE TABLE u (a int primary key);", "CREATE TABLE u AS SELECT * FROM t;", "CREATE OR REPLACE TABLE t (b int primary key);"] {
+            match s.execute(sql) {
+                Err(Error::Unsupported(msg)) => assert_eq!(msg, "DDL not allowed in this session"),
+                r => panic!("unexpected result {:?}", r),
+            }
+        }
+        s.execute("INSERT INTO t VALUES (1);")?;
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![vec![1.into()]]);
+        assert!(matches!(s.execute("DESCRIBE t;")?, ResultSet::Describe { .. }));
+        assert!(s.execute("SELECT * FROM u;").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_finite_only() -> Result<()> {
+        for finite_only in [false, true] {
+            let engine = KVEngine::memory().finite_only(finite_only);
+            engine.session()?.execute("CREATE TABLE t (a int primary key, b float);")?;
+
+            let mut txn = engine.begin()?;
+            for (i, f) in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY].into_iter().enumerate() {
+                match txn.create_row("t".into(), vec![(i as i64).into(), f.into()]) {
+                    Ok(()) => assert!(!finite_only),
+                    Err(Error::Internal(msg)) => {
+                        assert!(finite_only);
+                        assert_eq!(msg, format!("Column b cannot be {}", Value::Float(f).to_canonical_string()));
+                    }
+                    r => panic!("unexpected result {:?}", r),
+                }
+            }
+            assert_eq!(txn.bulk_insert("t", vec![vec![9.into(), f64::NAN.into()]]).is_err(), finite_only);
+            txn.create_row("t".into(), vec![10.into(), 1.5.into()])?;
+            txn.create_row("t".into(), vec![11.into(), Value::Null])?;
+            assert_eq!(txn.count_rows_matching("t".into(), None)?, if finite_only { 2 } else { 6 });
+            txn.commit()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_where_exists() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, y int);")?;
+        s.execute("CREATE TABLE other (id int primary key, x int);")?;
+        s.execute("INSERT INTO t VALUES (1, 10), (2, 20), (3, 30), (4, NULL);")?;
+        s.execute("INSERT INTO other VALUES (1, 10), (2, 30), (3, 30), (4, NULL);")?;
+
+        // Correlated, each outer row looks for a match, NULL never matches
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t WHERE EXISTS (SELECT * FROM other WHERE other.x = t.y);")?,
+            vec![vec![1.into()], vec![3.into()]]);
+        // Unqualified: x is inner, y is only in the outer table
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t WHERE EXISTS (SELECT id FROM other WHERE x = y + 10);")?,
+            vec![vec![2.into()]]);
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t WHERE EXISTS (SELECT * FROM other WHERE x = y) = false;")?,
+            vec![vec![2.into()], vec![4.into()]]);
+        // Uncorrelated
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t WHERE EXISTS (SELECT * FROM other WHERE x > 100);")?.len(), 0);
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t WHERE EXISTS (SELECT * FROM other);")?.len(), 4);
+
+        assert!(s.execute("SELECT * FROM t WHERE EXISTS (SELECT * FROM other WHERE t.nope = 1);").is_err());
+        assert!(s.execute("SELECT * FROM t WHERE EXISTS (SELECT * FROM other WHERE zz.y = x);").is_err());
+        assert!(s.execute("SELECT * FROM t WHERE EXISTS (SELECT * FROM other WHERE EXISTS (SELECT * FROM t WHERE zz.x = 1));").is_err());
+        assert_eq!(query_rows(&mut s, "SELECT a FROM t WHERE EXISTS (SELECT * FROM other WHERE EXISTS (SELECT * FROM t WHERE other.x = 30));")?.len(), 4);
+
+        // Each qualifier reads the row of the table it names, however deep
+        s.execute("CREATE TABLE t1 (id int primary key, a int);")?;
+        s.execute("CREATE TABLE t2 (id int primary key, a int);")?;
+        s.execute("CREATE TABLE t3 (id int primary key, c int);")?;
+        s.execute("INSERT INTO t1 VALUES (1, 100), (2, 7);")?;
+        s.execute("INSERT INTO t2 VALUES (1, 5);")?;
+        s.execute("INSERT INTO t3 VALUES (1, 100);")?;
+        assert_eq!(query_rows(&mut s, "SELECT id FROM t1 WHERE EXISTS (SELECT * FROM t2 WHERE EXISTS (SELECT * FROM t3 WHERE t3.c = t1.a));")?,
+            vec![vec![1.into()]]);
+        assert_eq!(query_rows(&mut s, "SELECT id FROM t1 WHERE EXISTS (SELECT * FROM t3 WHERE EXISTS (SELECT * FROM t2 WHERE c = t1.a));")?,
+            vec![vec![1.into()]]);
+        assert!(s.execute("SELECT * FROM t WHERE EXISTS (SELECT * FROM nope);").is_err());
+        assert!(matches!(s.execute("SELECT COUNT(*) FROM t WHERE EXISTS (SELECT * FROM other);"), Err(Error::Unsupported(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_alter_column_default() -> Result<()> {
+        let mut s = KVEngine::memory().session()?;
+        s.execute("CREATE TABLE t (a int primary key, c varchar default 'apple', n int not null);")?;
+        s.execute("INSERT INTO t (a, n) VALUES (1, 0);")?;
+
+        assert!(matches!(s.execute("ALTER TABLE t ALTER COLUMN c SET DEFAULT 'x';")?, ResultSet::AlterTable { .. }));
+        s.execute("INSERT INTO t (a, n) VALUES (2, 0);")?;
+        s.execute("ALTER TABLE t ALTER COLUMN c DROP DEFAULT;")?;
+        s.execute("INSERT INTO t (a, n) VALUES (3, 0);")?;
+        s.execute("ALTER TABLE t ALTER COLUMN n SET DEFAULT 7;")?;
+        s.execute("INSERT INTO t (a) VALUES (4);")?;
+
+        // Rows written before keep their values
+        assert_eq!(query_rows(&mut s, "SELECT * FROM t;")?, vec![
+            vec![1.into(), "apple".into(), 0.into()],
+            vec![2.into(), "x".into(), 0.into()],
+            vec![3.into(), Value::Null, 0.into()],
+            vec![4.into(), Value::Null, 7.into()],
+        ]);
+        match s.execute("DESCRIBE t;")? {
+            ResultSet::Describe { schema } => assert!(schema.contains("n INTEGER NOT NULL DEFAULT 7")),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        // Wrong type, NULL for NOT NULL, unknown column
+        assert!(s.execute("ALTER TABLE t ALTER COLUMN n SET DEFAULT 'x';").is_err());
+        assert!(s.execute("ALTER TABLE t ALTER COLUMN n SET DEFAULT NULL;").is_err());
+        assert!(s.execute("ALTER TABLE t ALTER COLUMN z SET DEFAULT 1;").is_err());
+        assert!(s.execute("ALTER TABLE nope ALTER COLUMN n SET DEFAULT 1;").is_err());
+        s.execute("ALTER TABLE t ALTER COLUMN n DROP DEFAULT;")?;
+        assert!(s.execute("INSERT INTO t (a) VALUES (5);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_schema_version() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        let version = |name: &str| -> Result<u64> {
+            let txn = kvengine.begin()?;
+            let version = txn.table_schema_version(name);
+            txn.commit()?;
+            version
+        };
+        s.execute("CREATE TABLE t (a int primary key, c varchar);")?;
+        assert_eq!(version("t")?, 1);
+
+        // Reads and writes of rows keep the version
+        s.execute("INSERT INTO t VALUES (1, 'x');")?;
+        s.execute("SELECT * FROM t;")?;
+        s.execute("DESCRIBE t;")?;
+        assert_eq!(version("t")?, 1);
+
+        s.execute("ALTER TABLE t ALTER COLUMN c SET DEFAULT 'y';")?;
+        assert_eq!(version("t")?, 2);
+        s.execute("ALTER TABLE t ALTER COLUMN c DROP DEFAULT;")?;
+        assert_eq!(version("t")?, 3);
+        // A failed ALTER changes nothing
+        assert!(s.execute("ALTER TABLE t ALTER COLUMN c SET DEFAULT 1;").is_err());
+        assert_eq!(version("t")?, 3);
+
+        // A table created again under the same name gets a newer version
+        s.execute("DROP TABLE t;")?;
+        assert!(version("t").is_err());
+        s.execute("CREATE TABLE t (a int primary key);")?;
+        assert_eq!(version("t")?, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_integrity() -> Result<()> {
+        let kvengine = KVEngine::memory();
+        let mut s = kvengine.session()?;
+        s.execute("CREATE TABLE t (a int primary key, b int not null, c varchar unique);")?;
+        s.execute("CREATE TABLE t2 (a int primary key);")?;
+        s.execute("INSERT INTO t VALUES (1, 1, 'x'), (2, 2, 'y');")?;
+        s.execute("INSERT INTO t2 VALUES (1);")?;
+        assert_eq!(s.check_integrity()?, vec![]);
+
+        // Rows written under the SQL layer skip all its checks
+        let txn = kvengine.kv.begin()?;
+        let write = |pk: i64, row: Row| -> Result<()> {
+            txn.set(Key::Row("t".into(), pk.into()).encode()?, encode_row(&BincodeCodec, &row)?)
+        };
+        write(3, vec![3.into(), "z".into(), Value::Null])?;
+        write(4, vec![4.into(), Value::Null, Value::Null])?;
+        write(5, vec![5.into(), 5.into()])?;
+        write(6, vec![6.into(), 6.into(), "x".into()])?;
+        txn.commit()?;
+
+        let reasons = s.check_integrity()?.into_iter()
+            .map(|v| (v.table_name, v.row[0].clone(), v.reason))
+            .collect::<Vec<_>>();
+        assert_eq!(reasons, vec![
+            ("t".to_string(), 3.into(), "Column b datatype mismatch".to_string()),
+            ("t".to_string(), 4.into(), "Column b cannot be null".to_string()),
+            ("t".to_string(), 5.into(), "Expected 3 values, got 2".to_string()),
+            ("t".to_string(), 6.into(), "Duplicate data for unique (c)".to_string()),
+        ]);
+        Ok(())
+    }
+
+    // Fixed workload through the Session API, prints the time per engine.
+    // Run with: cargo test --release bench_engines -- --ignored --nocapture
+    fn bench_workload<E: Engine>(name: &str, engine: E) -> Result<()> {
+        const INSERTS: i64 = 1000;
+        const BATCH: usize = 100;
+        const GETS: i64 = 100;
+        const SCANS: usize = 10;
+        let mut s = engine.session()?;
+        s.execute("CREATE TABLE bench (id int primary key, name varchar, score float);")?;
+
+        let start = std::time::Instant::now();
+        for batch in (0..INSERTS).collect::<Vec<_>>().chunks(BATCH) {
+            let values = batch
+                .iter()
+                .map(|i| format!("({}, 'name{}', {}.5)", i, i, i % 100))
+                .collect::<Vec<_>>()
+                .join(", ");
+            s.execute(&format!("INSERT INTO bench VALUES {};", values))?;
+        }
+        let inserted = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for i in 0..GETS {
+            let id = (i * 7) % INSERTS;
+            assert_eq!(query_rows(&mut s, &format!("SELECT * FROM bench WHERE id = {};", id))?[0][1], Value::String(format!("name{}", id)));
+        }
+        let got = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for _ in 0..SCANS {
+            assert_eq!(query_rows(&mut s, "SELECT * FROM bench;")?.len(), INSERTS as usize);
+        }
+        let scanned = start.elapsed();
+
+        println!("{:<8} {} inserts: {:?}, {} gets: {:?}, {} scans: {:?}",
+            name, INSERTS, inserted, GETS, got, SCANS, scanned);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore]
+    fn bench_engines() -> Result<()> {
+        bench_workload("memory", KVEngine::memory())?;
+
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        bench_workload("disk", KVEngine::disk(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+}
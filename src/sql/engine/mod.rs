@@ -1,17 +1,37 @@
-use crate::error::{Result, Error};
-use super::{executor::ResultSet, parser::Parser, plan::Plan, schema::Table, types::Row};
+use std::{collections::HashSet, ops::Bound, sync::{atomic::AtomicBool, Arc}};
+
+use crate::{error::{Result, Error}, storage::mvcc::VacuumStats};
+use super::{executor::{mutation::pad_row, ResultSet}, parser::{ast::{Expression, Statement}, IdentCase, Parser}, plan::Plan, schema::Table, types::{Row, Value}};
 
 pub mod kv;
 
+// An engine is a cheap handle to shared storage: clones must share the same
+// data, so that a session (which owns a clone) sees what the others commit.
 pub trait Engine: Clone {
-    type Transaction: Transaction;
+    type Transaction: Transaction + 'static;
 
     fn begin(&self) -> Result<Self::Transaction>;
 
+    // Read-only transaction pinned at a past version
+    fn begin_as_of(&self, version: u64) -> Result<Self::Transaction>;
+
+    // Drop the versions no transaction can see anymore and
+    // compact the storage, fails while transactions are active
+    fn vacuum(&self) -> Result<VacuumStats>;
+
+    // Any number of sessions can be opened on one engine
     fn session(&self) -> Result<Session<Self>> {
         Ok(
             Session {
                 engine: self.clone(),
+                allow_ddl: true,
+                ident_case: IdentCase::default(),
+                txn: None,
+                autocommit: true,
+                aborted: false,
+                last_error: None,
+                executed: 0,
+                cancel: None,
             }
         )
     }
@@ -29,16 +49,97 @@ pub trait Transaction {
     // Create row
     fn create_row(&mut self, table: String, row: Row) -> Result<()>;
 
+    // Delete the row with the primary key, if any
+    fn delete_row(&mut self, table_name: String, pk: &Value) -> Result<()>;
+
+    // Replace the rows with the primary keys, the new rows are checked
+    // like inserted ones and may have other primary keys. All old rows
+    // go first, so keys are only unique in the final state
+    fn update_rows(&mut self, table_name: String, rows: Vec<(Value, Row)>) -> Result<()> {
+        for (pk, _) in rows.iter() {
+            self.delete_row(table_name.clone(), pk)?;
+        }
+        for (_, row) in rows {
+            self.create_row(table_name.clone(), row)?;
+        }
+        Ok(())
+    }
+
     // Scan table
     fn scan_table(&mut self, table_name: String) -> Result<Vec<Row>>;
 
+    // Scan the first rows of the table by primary key
+    fn scan_table_first(&mut self, table_name: String, limit: usize) -> Result<Vec<Row>> {
+        let mut rows = self.scan_table(table_name)?;
+        rows.truncate(limit);
+        Ok(rows)
+    }
+
+    // Scan rows with primary key in [start, end], None for unbounded
+    fn scan_table_range(&mut self, table_name: String, start: Option<Value>, end: Option<Value>) -> Result<Vec<Row>> {
+        let bound = |v: Option<Value>| v.map_or(Bound::Unbounded, Bound::Included);
+        self.scan_table_value_range(table_name, bound(start), bound(end))
+    }
+
+    // Scan rows with primary key within the bounds, an empty range gives no rows
+    fn scan_table_value_range(&mut self, table_name: String, start: Bound<Value>, end: Bound<Value>) -> Result<Vec<Row>>;
+
+    // Scan the rows of a WITH INSERTION ORDER table, the first inserted first
+    fn scan_table_insertion_order(&mut self, table_name: String) -> Result<Vec<Row>>;
+
+    // Count the rows matching the predicate, without collecting them
+    fn count_rows_matching(&mut self, table_name: String, predicate: Option<&Expression>) -> Result<usize>;
+
     // DDL related transaction
     fn create_table(&mut self, table: Table) -> Result<()>;
 
+    // Replace the schema of an existing table, the rows are not touched
+    fn update_table(&mut self, table: Table) -> Result<()>;
+
+    // Delete the table and all its rows
+    fn drop_table(&mut self, table_name: String) -> Result<()>;
+
+    // Every table with all its rows, for maintenance checks
+    fn scan_all_tables(&mut self) -> Result<Vec<(Table, Vec<Row>)>>;
+
     // Get information
     fn get_table(&self, table_name: String) -> Result<Option<Table>>;
 
+    // Insert rows without SQL, missing trailing columns take the defaults
+    fn insert(&mut self, table_name: &str, rows: Vec<Row>) -> Result<usize> {
+        let table = self.must_get_table(table_name.to_string())?;
+        let count = rows.len();
+        for row in rows {
+            self.create_row(table_name.to_string(), pad_row(&table, &row)?)?;
+        }
+        Ok(count)
+    }
+
+    // Load rows into a table, engines may skip the conflict checks
+    // when the table is empty and nobody else is writing
+    fn bulk_insert(&mut self, table_name: &str, rows: Vec<Row>) -> Result<usize> {
+        self.insert(table_name, rows)
+    }
+
     // Check information
+    // Only checks for the schema, without decoding it
+    fn table_exists(&self, table_name: &str) -> Result<bool> {
+        Ok(self.get_table(table_name.to_string())?.is_some())
+    }
+
+    // The schema version of the table, it only changes with the schema
+    fn table_schema_version(&self, table_name: &str) -> Result<u64> {
+        Ok(self.must_get_table(table_name.to_string())?.schema_version)
+    }
+
+    // Token of the running statement, None when it cannot be cancelled
+    fn set_cancel(&mut self, _token: Option<Arc<AtomicBool>>) {}
+
+    // Checked between rows, fails once the statement is cancelled
+    fn check_cancelled(&self) -> Result<()> {
+        Ok(())
+    }
+
     fn must_get_table(&self, table_name: String) -> Result<Table> {
         self.get_table(table_name.clone())?
         .ok_or(Error::Internal(format!("table {} does not exist", table_name)))
@@ -46,27 +147,274 @@ pub trait Transaction {
 
 }
 
+// A stored row that does not fit the current schema of its table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub table_name: String,
+    pub row: Row,
+    pub reason: String,
+}
+
+// Check arity, datatypes, primary key and unique sets of the rows
+fn check_table_rows(table: &Table, rows: &[Row]) -> Result<Vec<Violation>> {
+    let mut violations = Vec::new();
+    let mut report = |row: &Row, reason: String| violations.push(Violation {
+        table_name: table.name.clone(),
+        row: row.clone(),
+        reason,
+    });
+
+    let mut valid = Vec::with_capacity(rows.len());
+    for row in rows {
+        if row.len() != table.columns.len() {
+            report(row, format!("Expected {} values, got {}", table.columns.len(), row.len()));
+            continue;
+        }
+        let mut ok = true;
+        for (col, value) in table.columns.iter().zip(row) {
+            match value.datatype() {
+                Some(dt) if dt != col.datatype => report(row, format!("Column {} datatype mismatch", col.name)),
+                None if col.primary_key => report(row, format!("Primary key {} is null", col.name)),
+                None if !col.nullable => report(row, format!("Column {} cannot be null", col.name)),
+                _ => continue,
+            }
+            ok = false;
+        }
+        if ok {
+            valid.push(row);
+        }
+    }
+
+    for set in table.unique.iter() {
+        let mut seen = HashSet::new();
+        for row in valid.iter() {
+            if let Some(key) = table.unique_key(set, row)? {
+                if !seen.insert(key) {
+                    report(row, format!("Duplicate data for unique ({})", set.join(", ")));
+                }
+            }
+        }
+    }
+    Ok(violations)
+}
+
 pub struct Session<E: Engine> {
     engine: E,
+    // Query-only sessions reject CREATE/DROP/ALTER
+    allow_ddl: bool,
+    // Folding of table and column names, names are stored and matched as folded
+    ident_case: IdentCase,
+    // Opened by BEGIN, the statements run in it until COMMIT or ROLLBACK
+    txn: Option<E::Transaction>,
+    // Off: a statement outside a transaction opens one, as BEGIN would
+    autocommit: bool,
+    // A statement failed in the transaction and it was rolled back,
+    // everything but ROLLBACK is refused until the user ends it
+    aborted: bool,
+    // Status for front-ends: the last failure, kept until the next one,
+    // and the number of statements that succeeded
+    last_error: Option<Error>,
+    executed: usize,
+    // Token of the statement run by execute_cancellable
+    cancel: Option<Arc<AtomicBool>>,
+}
+
+// A transaction left open is rolled back
+impl<E: Engine> Drop for Session<E> {
+    fn drop(&mut self) {
+        if let Some(txn) = self.txn.take() {
+            let _ = txn.rollback();
+        }
+    }
 }
 
 impl<E: Engine> Session<E> {
+    // For untrusted endpoints: only queries and DML are run, no VACUUM
+    pub fn disallow_ddl(&mut self) {
+        self.allow_ddl = false;
+    }
+
+    pub fn set_ident_case(&mut self, ident_case: IdentCase) {
+        self.ident_case = ident_case;
+    }
+
+    // Turning it back on commits the open transaction,
+    // an aborted one has to be rolled back first
+    pub fn set_autocommit(&mut self, autocommit: bool) -> Result<()> {
+        if autocommit && self.aborted {
+            return Err(Error::Internal("transaction aborted, only ROLLBACK is allowed".into()));
+        }
+        if autocommit {
+            if let Some(txn) = self.txn.take() {
+                txn.commit()?;
+            }
+        }
+        self.autocommit = autocommit;
+        Ok(())
+    }
+
+    pub fn last_error(&self) -> Option<&Error> {
+        self.last_error.as_ref()
+    }
+
+    pub fn executed_count(&self) -> usize {
+        self.executed
+    }
+
+    fn record<T>(&mut self, result: &Result<T>, statements: impl FnOnce(&T) -> usize) {
+        match result {
+            Ok(value) => self.executed += statements(value),
+            Err(err) => self.last_error = Some(err.clone()),
+        }
+    }
+
+    // Validate every stored row against the current schema of its table
+    pub fn check_integrity(&mut self) -> Result<Vec<Violation>> {
+        let mut txn = self.engine.begin()?;
+        let mut violations = Vec::new();
+        for (table, rows) in txn.scan_all_tables()? {
+            violations.extend(check_table_rows(&table, &rows)?);
+        }
+        txn.commit()?;
+        Ok(violations)
+    }
+
+    // Run a whole script in one transaction, committed once at the end.
+    // An error in any statement rolls back all of them.
+    pub fn execute_batch_atomic(&mut self, sql: &str) -> Result<Vec<ResultSet>> {
+        let result = self.execute_batch(sql);
+        self.record(&result, Vec::len);
+        result
+    }
+
+    fn execute_batch(&mut self, sql: &str) -> Result<Vec<ResultSet>> {
+        if self.txn.is_some() || self.aborted {
+            return Err(Error::Internal("transaction already in progress".into()));
+        }
+        let mut plans = Vec::new();
+        for stmt in Parser::with_ident_case(sql, self.ident_case).parse_script()? {
+            let plan = self.build_plan(stmt)?;
+            if plan.as_of().is_some() {
+                return Err(Error::Unsupported("AS OF VERSION in an atomic batch".into()));
+            }
+            plans.push(plan);
+        }
+
+        let mut txn = self.engine.begin()?;
+        let mut results = Vec::with_capacity(plans.len());
+        for plan in plans {
+            match plan.execute(&mut txn) {
+                Ok(result) => results.push(result),
+                Err(err) => {
+                    txn.rollback()?;
+                    return Err(err);
+                }
+            }
+        }
+        txn.commit()?;
+        Ok(results)
+    }
+
+    fn build_plan(&self, stmt: Statement) -> Result<Plan> {
+        if stmt.is_ddl() && !self.allow_ddl {
+            return Err(Error::Unsupported("DDL not allowed in this session".into()));
+        }
+        Plan::build(stmt)
+    }
+
     pub fn execute(&mut self, sql: &str) -> Result<ResultSet> {
-        match Parser::new(sql).parse()? {
+        let result = self.execute_statement(sql);
+        self.record(&result, |_| 1);
+        result
+    }
+
+    // Like execute, but the statement stops with "query cancelled"
+    // once the token is set, checked while rows are read and filtered
+    pub fn execute_cancellable(&mut self, sql: &str, token: &Arc<AtomicBool>) -> Result<ResultSet> {
+        self.cancel = Some(token.clone());
+        let result = self.execute(sql);
+        self.cancel = None;
+        result
+    }
+
+    fn execute_statement(&mut self, sql: &str) -> Result<ResultSet> {
+        let stmt = Parser::with_ident_case(sql, self.ident_case).parse()?;
+        if self.aborted {
+            return match stmt {
+                Statement::Rollback => {
+                    self.aborted = false;
+                    Ok(ResultSet::Rollback)
+                }
+                _ => Err(Error::Internal("transaction aborted, only ROLLBACK is allowed".into())),
+            };
+        }
+        match stmt {
+            Statement::Begin if self.txn.is_some() => Err(Error::Internal("transaction already in progress".into())),
+            Statement::Begin => {
+                self.txn = Some(self.engine.begin()?);
+                Ok(ResultSet::Begin)
+            }
+            Statement::Commit => match self.txn.take() {
+                Some(txn) => txn.commit().map(|_| ResultSet::Commit),
+                None => Err(Error::Internal("no transaction in progress".into())),
+            },
+            Statement::Rollback => match self.txn.take() {
+                Some(txn) => txn.rollback().map(|_| ResultSet::Rollback),
+                None => Err(Error::Internal("no transaction in progress".into())),
+            },
+            Statement::Vacuum if !self.allow_ddl => Err(Error::Unsupported("VACUUM not allowed in this session".into())),
+            Statement::Vacuum if self.txn.is_some() => Err(Error::Internal("VACUUM cannot run inside a transaction".into())),
+            Statement::Vacuum => self.engine.vacuum().map(|stats| ResultSet::Vacuum { versions: stats.versions, bytes: stats.bytes }),
             stmt => {
-                let mut txn = self.engine.begin()?;
                 // construct the plan
-                match Plan::build(stmt).execute(&mut txn) {
-                    Ok(result) => {
-                        txn.commit()?;
-                        Ok(result)
-                    },
-                    Err(err) => {
-                        txn.rollback()?;
-                        Err(err)
+                let plan = self.build_plan(stmt)?;
+                let txn = match self.txn.take() {
+                    None if !self.autocommit && plan.as_of().is_none() => Some(self.engine.begin()?),
+                    txn => txn,
+                };
+                match txn {
+                    Some(txn) if plan.as_of().is_some() => {
+                        self.txn = Some(txn);
+                        Err(Error::Unsupported("AS OF VERSION inside a transaction".into()))
                     }
+                    // A failed statement may have written part of its rows,
+                    // so the whole transaction is rolled back and left aborted
+                    Some(mut txn) => {
+                        txn.set_cancel(self.cancel.clone());
+                        match plan.execute(&mut txn) {
+                            Ok(result) => {
+                                self.txn = Some(txn);
+                                Ok(result)
+                            }
+                            Err(err) => {
+                                self.aborted = true;
+                                txn.rollback()?;
+                                Err(err)
+                            }
+                        }
+                    }
+                    None => self.execute_autocommit(plan),
                 }
             }
         }
     }
+
+    // The statement runs in a transaction of its own
+    fn execute_autocommit(&mut self, plan: Plan) -> Result<ResultSet> {
+        let mut txn = match plan.as_of() {
+            Some(version) => self.engine.begin_as_of(version)?,
+            None => self.engine.begin()?,
+        };
+        txn.set_cancel(self.cancel.clone());
+        match plan.execute(&mut txn) {
+            Ok(result) => {
+                txn.commit()?;
+                Ok(result)
+            },
+            Err(err) => {
+                txn.rollback()?;
+                Err(err)
+            }
+        }
+    }
 }
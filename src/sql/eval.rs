@@ -0,0 +1,197 @@
+use std::{cmp::Ordering, collections::HashMap, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::error::{Error, Result};
+use super::{parser::ast::{Expression, Operation, CURRENT_TIMESTAMP}, types::{Row, Value}};
+
+// Column positions by name, built once and used for every row.
+// table_name.column_name falls back to column_name, the planner has
+// rejected other tables' names by then. The first of two columns with
+// the same name is used.
+pub struct ColumnMap {
+    positions: HashMap<String, usize>,
+}
+
+impl ColumnMap {
+    pub fn new(columns: &[String]) -> Self {
+        let mut positions = HashMap::with_capacity(columns.len());
+        for (i, column) in columns.iter().enumerate() {
+            positions.entry(column.clone()).or_insert(i);
+        }
+        Self { positions }
+    }
+
+    // No columns, only constants can be evaluated
+    pub fn empty() -> Self {
+        Self { positions: HashMap::new() }
+    }
+
+    pub fn resolve(&self, name: &str) -> Option<usize> {
+        self.positions.get(name).or_else(|| {
+            let (_, column) = name.rsplit_once('.')?;
+            self.positions.get(column)
+        }).copied()
+    }
+}
+
+// Evaluate the expression on a row, fields are resolved through the column map
+pub fn evaluate(expr: &Expression, row: &Row, schema: &ColumnMap) -> Result<Value> {
+    match expr {
+        Expression::Consts(_) => Value::from_expression_to_value(expr.clone()),
+        Expression::Field(name) => match schema.resolve(name) {
+            Some(i) => Ok(row[i].clone()),
+            None => Err(Error::Internal(format!("Column {} not found", name))),
+        },
+        Expression::Operation(op) => {
+            let (l, r, expect): (_, _, fn(Ordering) -> bool) = match op {
+                Operation::Equal(l, r) => (l, r, |o| o == Ordering::Equal),
+                Operation::NotEqual(l, r) => (l, r, |o| o != Ordering::Equal),
+                Operation::GreaterThan(l, r) => (l, r, |o| o == Ordering::Greater),
+                Operation::LessThan(l, r) => (l, r, |o| o == Ordering::Less),
+                Operation::GreaterThanOrEqual(l, r) => (l, r, |o| o != Ordering::Less),
+                Operation::LessThanOrEqual(l, r) => (l, r, |o| o != Ordering::Greater),
+                Operation::Add(l, r) => return evaluate(l, row, schema)?.add(&evaluate(r, row, schema)?),
+                Operation::Subtract(l, r) => return evaluate(l, row, schema)?.subtract(&evaluate(r, row, schema)?),
+                Operation::Multiply(l, r) => return evaluate(l, row, schema)?.multiply(&evaluate(r, row, schema)?),
+                Operation::Divide(l, r) => return evaluate(l, row, schema)?.divide(&evaluate(r, row, schema)?),
+                // Three-valued: a decided operand wins over NULL
+                Operation::And(l, r) => return Ok(match (evaluate_predicate(l, row, schema)?, evaluate_predicate(r, row, schema)?) {
+                    (Some(false), _) | (_, Some(false)) => Value::Boolean(false),
+                    (Some(true), Some(true)) => Value::Boolean(true),
+                    _ => Value::Null,
+                }),
+                Operation::Or(l, r) => return Ok(match (evaluate_predicate(l, row, schema)?, evaluate_predicate(r, row, schema)?) {
+                    (Some(true), _) | (_, Some(true)) => Value::Boolean(true),
+                    (Some(false), Some(false)) => Value::Boolean(false),
+                    _ => Value::Null,
+                }),
+            };
+            let (l, r) = (evaluate(l, row, schema)?, evaluate(r, row, schema)?);
+            if l == Value::Null || r == Value::Null {
+                return Ok(Value::Null);
+            }
+            match l.partial_cmp(&r) {
+                Some(o) => Ok(Value::Boolean(expect(o))),
+                None => Err(Error::Internal(format!("Cannot compare {} and {}", l, r))),
+            }
+        }
+        Expression::Function(name, args) if name == CURRENT_TIMESTAMP && args.is_empty() => Ok(current_timestamp()),
+        Expression::Function(name, args) if matches!(name.as_str(), "upper" | "lower") && args.len() == 1 => {
+            match evaluate(&args[0], row, schema)? {
+                Value::Null => Ok(Value::Null),
+                Value::String(s) if name == "upper" => Ok(Value::String(s.to_uppercase())),
+                Value::String(s) => Ok(Value::String(s.to_lowercase())),
+                v => Err(Error::Internal(format!("{}() expects a string, got {}", name.to_uppercase(), v))),
+            }
+        }
+        // The first argument that is not NULL
+        Expression::Function(name, args) if name == "coalesce" && !args.is_empty() => {
+            for arg in args {
+                let value = evaluate(arg, row, schema)?;
+                if value != Value::Null {
+                    return Ok(value);
+                }
+            }
+            Ok(Value::Null)
+        }
+        // NULL if both are equal as for =, else the first
+        Expression::Function(name, args) if name == "nullif" && args.len() == 2 => {
+            let (l, r) = (evaluate(&args[0], row, schema)?, evaluate(&args[1], row, schema)?);
+            match l.partial_cmp(&r) {
+                Some(Ordering::Equal) => Ok(Value::Null),
+                _ => Ok(l),
+            }
+        }
+        Expression::Function(name, _) => Err(Error::Internal(format!("Function {} is not allowed here", name))),
+        Expression::Exists { .. } => Err(Error::Internal("EXISTS is not allowed here".into())),
+    }
+}
+
+// Evaluate a predicate: true, false or unknown (NULL).
+// Only rows where it is true pass a WHERE.
+pub fn evaluate_predicate(expr: &Expression, row: &Row, schema: &ColumnMap) -> Result<Option<bool>> {
+    match evaluate(expr, row, schema)? {
+        Value::Boolean(b) => Ok(Some(b)),
+        Value::Null => Ok(None),
+        v => Err(Error::Internal(format!("Filter predicate must be boolean, got {}", v))),
+    }
+}
+
+// Microseconds since the unix epoch
+fn current_timestamp() -> Value {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    Value::Integer(now.as_micros() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Result, sql::{parser::Parser, parser::ast::{Expression, Statement}, types::Value}};
+    use super::{evaluate, evaluate_predicate, ColumnMap};
+
+    fn where_clause(sql: &str) -> Result<Expression> {
+        match Parser::new(&format!("SELECT * FROM t WHERE {};", sql)).parse()? {
+            Statement::Select { filter: Some(filter), .. } => Ok(filter),
+            stmt => panic!("unexpected statement {:?}", stmt),
+        }
+    }
+
+    #[test]
+    fn test_evaluate() -> Result<()> {
+        let schema = ColumnMap::new(&["a".to_string(), "b".to_string(), "c".to_string()]);
+        let row = vec![Value::Integer(3), Value::Float(1.5), Value::Null];
+        let eval = |sql: &str| evaluate(&where_clause(sql)?, &row, &schema);
+
+        assert_eq!(eval("a * 2 + 1")?, Value::Integer(7));
+        assert_eq!(eval("t.a + b")?, Value::Float(4.5));
+        assert_eq!(eval("a - c")?, Value::Null);
+        assert_eq!(eval("a > b")?, Value::Boolean(true));
+        assert_eq!(eval("a = 4")?, Value::Boolean(false));
+        assert_eq!(eval("a != 4")?, Value::Boolean(true));
+        assert_eq!(eval("a <> 3.0")?, Value::Boolean(false));
+        assert_eq!(eval("c != 1")?, Value::Null);
+        assert_eq!(eval("a >= 3")?, Value::Boolean(true));
+        assert_eq!(eval("a<=b")?, Value::Boolean(false));
+        assert_eq!(eval("c < 1")?, Value::Null);
+        assert!(eval("d = 1").is_err());
+        assert!(eval("a > 'x'").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_null_functions() -> Result<()> {
+        let schema = ColumnMap::new(&["a".to_string(), "n".to_string()]);
+        let row = vec![Value::Integer(3), Value::Null];
+        let eval = |sql: &str| evaluate(&where_clause(sql)?, &row, &schema);
+
+        assert_eq!(eval("coalesce(n, 'default')")?, Value::String("default".into()));
+        assert_eq!(eval("COALESCE(n, NULL, a + 1, 0)")?, Value::Integer(4));
+        assert_eq!(eval("coalesce(n, null)")?, Value::Null);
+        assert_eq!(eval("nullif(a, a)")?, Value::Null);
+        assert_eq!(eval("nullif(a, 3.0)")?, Value::Null);
+        assert_eq!(eval("nullif(a, 4)")?, Value::Integer(3));
+        assert_eq!(eval("nullif(n, 1)")?, Value::Null);
+        assert_eq!(eval("nullif(a, n)")?, Value::Integer(3));
+        assert!(eval("nullif(a)").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate_predicate() -> Result<()> {
+        let schema = ColumnMap::new(&["a".to_string(), "c".to_string()]);
+        let row = vec![Value::Integer(3), Value::Null];
+        let eval = |sql: &str| evaluate_predicate(&where_clause(sql)?, &row, &schema);
+
+        // AND binds tighter than OR
+        assert_eq!(eval("a = 3 AND a > 1")?, Some(true));
+        assert_eq!(eval("a = 1 OR a = 2 AND a = 2")?, Some(false));
+        assert_eq!(eval("a = 3 OR a = 1 AND a = 2")?, Some(true));
+        // NULL is unknown unless the other side decides
+        assert_eq!(eval("c = 1")?, None);
+        assert_eq!(eval("c = 1 AND a = 3")?, None);
+        assert_eq!(eval("c = 1 AND a = 1")?, Some(false));
+        assert_eq!(eval("c = 1 OR a = 3")?, Some(true));
+        assert_eq!(eval("c = 1 OR a = 1")?, None);
+        assert!(eval("a + 1").is_err());
+        assert!(eval("a AND true").is_err());
+        Ok(())
+    }
+}
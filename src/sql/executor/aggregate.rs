@@ -0,0 +1,110 @@
+use std::cmp::Ordering;
+
+use crate::{error::{Error, Result}, sql::{engine::Transaction, eval::{evaluate, ColumnMap}, parser::ast::Expression, types::{DataTypes, Value}}};
+
+use super::{Executor, ResultSet};
+
+// COUNT(*) without grouping, the transaction counts the rows
+// so the matching rows are never materialized
+pub struct Count {
+    table_name: String,
+    predicate: Option<Expression>,
+    alias: Option<String>,
+}
+
+impl Count {
+    pub fn new(table_name: String, predicate: Option<Expression>, alias: Option<String>) -> Box<Self> {
+        Box::new(Self { table_name, predicate, alias })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Count {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let count = txn.count_rows_matching(self.table_name, self.predicate.as_ref())?;
+        Ok(ResultSet::Scan {
+            columns: vec![self.alias.unwrap_or_else(|| "count".to_string())],
+            types: vec![Some(DataTypes::Integer)],
+            rows: vec![vec![Value::Integer(count as i64)]],
+        })
+    }
+}
+
+// Aggregates without grouping always give exactly one row,
+// over no rows COUNT is 0 and the others are NULL
+pub struct Aggregate<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    aggregates: Vec<(Expression, Option<String>)>,
+}
+
+impl<T: Transaction> Aggregate<T> {
+    pub fn new(source: Box<dyn Executor<T>>, aggregates: Vec<(Expression, Option<String>)>) -> Box<Self> {
+        Box::new(Self { source, aggregates })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Aggregate<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, rows, .. } => {
+                let mut result = Vec::new();
+                let schema = ColumnMap::new(&columns);
+                for (expr, _) in &self.aggregates {
+                    let (name, args) = match expr {
+                        Expression::Function(name, args) => (name, args),
+                        e => return Err(Error::Internal(format!("{} is not an aggregate", e))),
+                    };
+                    // COUNT(*) counts every row, otherwise NULLs are skipped
+                    let values = match args.first() {
+                        None => vec![Value::Boolean(true); rows.len()],
+                        Some(arg) => rows.iter()
+                            .map(|row| evaluate(arg, row, &schema))
+                            .filter(|v| !matches!(v, Ok(Value::Null)))
+                            .collect::<Result<Vec<_>>>()?,
+                    };
+                    result.push(aggregate(name, values)?);
+                }
+                let columns = self.aggregates.into_iter().map(|(e, alias)| match (e, alias) {
+                    (_, Some(alias)) => alias,
+                    (Expression::Function(name, _), None) => name,
+                    _ => "?column?".to_string(),
+                }).collect();
+                let types = result.iter().map(Value::datatype).collect();
+                Ok(ResultSet::Scan { columns, types, rows: vec![result] })
+            }
+            _ => Err(Error::Internal("Unexpected result set for aggregate".into())),
+        }
+    }
+}
+
+fn aggregate(name: &str, values: Vec<Value>) -> Result<Value> {
+    let numeric = |v: &Value| match v {
+        Value::Integer(_) | Value::Float(_) => Ok(()),
+        v => Err(Error::Internal(format!("{}() expects a number, got {}", name.to_uppercase(), v))),
+    };
+    // Keeps the value ordered first, the values must compare
+    let extreme = |keep: Ordering| values.iter().try_fold(Value::Null, |acc, v| match acc.partial_cmp(v) {
+        _ if acc == Value::Null => Ok(v.clone()),
+        Some(o) if o == keep => Ok(acc),
+        Some(_) => Ok(v.clone()),
+        None => Err(Error::Internal(format!("Cannot compare {} and {}", acc, v))),
+    });
+    match name {
+        "count" => Ok(Value::Integer(values.len() as i64)),
+        "sum" => values.iter().try_fold(Value::Null, |acc, v| {
+            numeric(v)?;
+            if acc == Value::Null { Ok(v.clone()) } else { acc.add(v) }
+        }),
+        "avg" => {
+            values.iter().try_for_each(numeric)?;
+            if values.is_empty() {
+                return Ok(Value::Null);
+            }
+            // Summed as floats, integers can't overflow here
+            let sum = values.iter().try_fold(Value::Float(0.0), |acc, v| acc.add(v))?;
+            sum.divide(&Value::Integer(values.len() as i64))
+        }
+        "min" => extreme(Ordering::Less),
+        "max" => extreme(Ordering::Greater),
+        name => Err(Error::Internal(format!("Unknown aggregate {}", name))),
+    }
+}
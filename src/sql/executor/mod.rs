@@ -1,24 +1,65 @@
-use mutation::Insert;
-use query::Scan;
-use schema::CreateTable;
+use std::fmt::Display;
+
+use aggregate::{Aggregate, Count};
+use mutation::{Delete, Insert, Update};
+use query::{Distinct, Explain, Filter, InsertionScan, Limit, Nothing, Offset, Order, Projection, Scan, TopN};
+use schema::{AlterColumnDefault, CreateTable, CreateTableAs, Describe, DropTable};
 
 use crate::error::Result;
-use super::{engine::Transaction, plan::Node, types::Row};
+use super::{engine::Transaction, plan::Node, types::{DataTypes, Row}};
 pub mod schema;
 pub mod mutation;
 pub mod query;
+pub mod aggregate;
 
 pub trait Executor<T: Transaction> {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet>;
 }
 
 
-impl<T: Transaction> dyn Executor<T> {
+impl<T: Transaction + 'static> dyn Executor<T> {
     pub fn build(node: Node) -> Box<dyn Executor<T>> {
         match node {
-            Node::CreateTable { schema } => CreateTable::new(schema),
+            Node::CreateTable { schema, or_replace } => CreateTable::new(schema, or_replace),
+            Node::DropTable { table_name, if_exists } => DropTable::new(table_name, if_exists),
+            Node::CreateTableAs { table_name, source_table, source } => CreateTableAs::new(table_name, source_table, Self::build(*source)),
+            Node::AlterColumnDefault { table_name, column_name, default } => AlterColumnDefault::new(table_name, column_name, default),
+            Node::Describe { table_name } => Describe::new(table_name),
             Node::Insert { table_name, columns, values } => Insert::new(table_name, columns, values),
+            Node::Delete { table_name, source } => Delete::new(table_name, Self::build(*source)),
+            Node::Update { table_name, source, columns } => Update::new(table_name, Self::build(*source), columns),
             Node::Scan { table_name } => Scan::new(table_name),
+            Node::InsertionScan { table_name, direction } => InsertionScan::new(table_name, direction),
+            // The scan is narrowed by the predicate when it can be
+            Node::Filter { source, predicate } => match *source {
+                Node::Scan { table_name } => Filter::new(Some(table_name.clone()), Scan::with_filter(table_name, predicate.clone()), predicate),
+                Node::InsertionScan { table_name, direction } =>
+                    Filter::new(Some(table_name.clone()), InsertionScan::new(table_name, direction), predicate),
+                source => Filter::new(None, Self::build(source), predicate),
+            },
+            Node::Nothing { table_name } => Nothing::new(table_name),
+            Node::Projection { source, expressions } => Projection::new(Self::build(*source), expressions),
+            Node::Distinct { source } => Distinct::new(Self::build(*source)),
+            Node::Order { source, order_by } => Order::new(Self::build(*source), order_by),
+            Node::Limit { source, limit } => Limit::new(Self::build_limited(*source, limit), limit),
+            Node::Offset { source, offset } => Offset::new(Self::build(*source), offset),
+            Node::TopN { source, order_by, limit, offset } => TopN::new(Self::build(*source), order_by, limit, offset),
+            Node::Count { table_name, predicate, alias } => Count::new(table_name, predicate, alias),
+            Node::Aggregate { source, aggregates } => Aggregate::new(Self::build(*source), aggregates),
+            Node::Explain { source } => Explain::new(source.to_string()),
+            // The session has already pinned the transaction
+            Node::AsOf { source, .. } => Self::build(*source),
+        }
+    }
+
+    // The source of a limit, a scan reached without dropping or
+    // reordering rows on the way only reads the rows needed
+    fn build_limited(node: Node, limit: usize) -> Box<dyn Executor<T>> {
+        match node {
+            Node::Scan { table_name } => Scan::with_limit(table_name, limit),
+            Node::Offset { source, offset } => Offset::new(Self::build_limited(*source, limit.saturating_add(offset)), offset),
+            Node::Projection { source, expressions } => Projection::new(Self::build_limited(*source, limit), expressions),
+            node => Self::build(node),
         }
     }
 }
@@ -26,9 +67,82 @@ impl<T: Transaction> dyn Executor<T> {
 # [derive(Debug)]
 pub enum ResultSet {
     CreateTable { table_name: String },
+    DropTable { table_name: String },
+    AlterTable { table_name: String },
+    Describe { schema: String },
     Insert { count: usize },
+    Delete { count: usize },
+    Update { count: usize },
     Scan {
         columns: Vec<String>,
+        // Column types where known, used to align the values
+        types: Vec<Option<DataTypes>>,
         rows: Vec<Row>,
+    },
+    Explain { plan_text: String },
+    Begin,
+    Commit,
+    Rollback,
+    Vacuum { versions: usize, bytes: u64 },
+}
+
+impl ResultSet {
+    // Render a scan as an ASCII table, numbers are right-aligned
+    // and everything else left-aligned. Other results render as text.
+    pub fn render_grid(&self) -> String {
+        let (columns, types, rows) = match self {
+            ResultSet::Scan { columns, types, rows } => (columns, types, rows),
+            result => return result.to_string(),
+        };
+        let cells = rows.iter()
+            .map(|row| row.iter().map(|v| v.to_string()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let widths = columns.iter().enumerate().map(|(i, c)| {
+            cells.iter().map(|row| row[i].chars().count()).fold(c.chars().count(), usize::max)
+        }).collect::<Vec<_>>();
+        let numeric = (0..columns.len())
+            .map(|i| matches!(types.get(i), Some(Some(DataTypes::Integer | DataTypes::Float))))
+            .collect::<Vec<_>>();
+
+        let border = widths.iter().fold("+".to_string(), |s, w| s + &"-".repeat(w + 2) + "+");
+        let line = |values: &[String], align: bool| {
+            values.iter().enumerate().fold("|".to_string(), |s, (i, v)| {
+                let pad = " ".repeat(widths[i] - v.chars().count());
+                match align && numeric[i] {
+                    true => format!("{} {}{} |", s, pad, v),
+                    false => format!("{} {}{} |", s, v, pad),
+                }
+            })
+        };
+        let mut lines = vec![border.clone(), line(columns, false), border.clone()];
+        lines.extend(cells.iter().map(|row| line(row, true)));
+        lines.push(border);
+        lines.join("\n")
+    }
+}
+
+impl Display for ResultSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResultSet::CreateTable { table_name } => write!(f, "CREATE TABLE {}", table_name),
+            ResultSet::DropTable { table_name } => write!(f, "DROP TABLE {}", table_name),
+            ResultSet::AlterTable { table_name } => write!(f, "ALTER TABLE {}", table_name),
+            ResultSet::Describe { schema } => write!(f, "{}", schema),
+            ResultSet::Insert { count } => write!(f, "INSERT {} rows", count),
+            ResultSet::Delete { count } => write!(f, "DELETE {} rows", count),
+            ResultSet::Update { count } => write!(f, "UPDATE {} rows", count),
+            ResultSet::Scan { columns, rows, .. } => {
+                write!(f, "{}", columns.join(" | "))?;
+                for row in rows {
+                    write!(f, "\n{}", row.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(" | "))?;
+                }
+                Ok(())
+            }
+            ResultSet::Explain { plan_text } => write!(f, "{}", plan_text),
+            ResultSet::Begin => write!(f, "BEGIN"),
+            ResultSet::Commit => write!(f, "COMMIT"),
+            ResultSet::Rollback => write!(f, "ROLLBACK"),
+            ResultSet::Vacuum { versions, bytes } => write!(f, "VACUUM {} versions, {} bytes", versions, bytes),
+        }
     }
 }
\ No newline at end of file
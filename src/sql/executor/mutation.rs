@@ -1,6 +1,4 @@
-use std::collections::HashMap;
-
-use crate::{error::{Error, Result}, sql::{engine::Transaction, parser::ast::Expression, schema::Table, types::{Row, Value}}};
+use crate::{error::{Error, Result}, sql::{engine::Transaction, eval::{evaluate, ColumnMap}, parser::ast::Expression, schema::Table, types::{Row, Value}}};
 
 use super::{Executor, ResultSet};
 
@@ -25,21 +23,39 @@ impl<T: Transaction> Executor<T> for Insert {
     fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
         let mut count = 0;
         // Read the table first
-        let table = txn.must_get_table(self.table_name.clone())?;
-        for exprs in self.values {
-            // Convert the expression into value
-            let row = exprs.into_iter().map(|e| Value::from_expression_to_value(e))
-            .collect::<Vec<_>>();
-            
-            // if no column specified
-            let insert_row = if self.columns.is_empty() {
-                pad_row(&table, &row)?
-            } else {
-                make_row(&table, &self.columns, &row)?
-            };
+        let mut table = txn.must_get_table(self.table_name.clone())?;
+        // Defaults are resolved once per statement, so every row gets the
+        // same CURRENT_TIMESTAMP. A required column has none, a row that
+        // omits it reports the error
+        table.defaults = (0..table.columns.len())
+            .map(|i| if table.columns[i].required() { Ok(None) } else { column_default(&table, i).map(Some) })
+            .collect::<Result<_>>()?;
+        // The named columns are mapped once, every tuple reuses the positions
+        let positions = if self.columns.is_empty() {
+            None
+        } else {
+            Some(column_positions(&table, &self.columns)?)
+        };
+        for (i, exprs) in self.values.into_iter().enumerate() {
+            let insert = |txn: &mut T| -> Result<()> {
+                // Evaluate the expressions, there is no row to read columns from
+                let row = exprs.iter().map(|e| evaluate(e, &Vec::new(), &ColumnMap::empty()))
+                .collect::<Result<Vec<_>>>()?;
 
-            // Insert the data
-            txn.create_row(self.table_name.clone(), insert_row)?;
+                // if no column specified
+                let insert_row = match &positions {
+                    None => pad_row(&table, &row)?,
+                    Some(positions) => make_row(&table, positions, &row)?,
+                };
+
+                // Insert the data
+                txn.create_row(self.table_name.clone(), insert_row)
+            };
+            // Name the failing tuple, zero-based
+            insert(txn).map_err(|err| match err {
+                Error::Internal(msg) => Error::Internal(format!("row {}: {}", i, msg)),
+                err => err,
+            })?;
             count += 1;
         }
 
@@ -47,48 +63,178 @@ impl<T: Transaction> Executor<T> for Insert {
     }
 }
 
+pub struct Delete<T: Transaction> {
+    table_name: String,
+    source: Box<dyn Executor<T>>,
+}
+
+impl<T: Transaction> Delete<T> {
+    pub fn new(table_name: String, source: Box<dyn Executor<T>>) -> Box<Self> {
+        Box::new(Self { table_name, source })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Delete<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name.clone())?;
+        match self.source.execute(txn)? {
+            ResultSet::Scan { rows, .. } => {
+                for row in rows.iter() {
+                    txn.delete_row(self.table_name.clone(), &table.get_primary_key(row)?)?;
+                }
+                Ok(ResultSet::Delete { count: rows.len() })
+            }
+            _ => Err(Error::Internal("Unexpected result set for delete".into())),
+        }
+    }
+}
+
+pub struct Update<T: Transaction> {
+    table_name: String,
+    source: Box<dyn Executor<T>>,
+    columns: Vec<(String, Expression)>,
+}
+
+impl<T: Transaction> Update<T> {
+    pub fn new(table_name: String, source: Box<dyn Executor<T>>, columns: Vec<(String, Expression)>) -> Box<Self> {
+        Box::new(Self { table_name, source, columns })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Update<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name.clone())?;
+        let names = self.columns.iter().map(|(c, _)| c.clone()).collect::<Vec<_>>();
+        let positions = column_positions(&table, &names)?;
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, rows, .. } => {
+                let schema = ColumnMap::new(&columns);
+                let mut updates = Vec::with_capacity(rows.len());
+                for row in rows.iter() {
+                    // Every expression sees the row as it was stored
+                    let mut new_row = row.clone();
+                    for (pos, (_, expr)) in positions.iter().zip(&self.columns) {
+                        new_row[*pos] = evaluate(expr, row, &schema)?;
+                    }
+                    updates.push((table.get_primary_key(row)?, new_row));
+                }
+                let count = updates.len();
+                txn.update_rows(self.table_name.clone(), updates)?;
+                Ok(ResultSet::Update { count })
+            }
+            _ => Err(Error::Internal("Unexpected result set for update".into())),
+        }
+    }
+}
+
 // Line up in correct order:
 // tbl:
 // Insert into tbl values (1,2,3);
 // a    b   c   d
 // 1    2   3   default
 pub fn pad_row(table: &Table, row: &Row) -> Result<Row> {
+    if row.len() > table.columns.len() {
+        return Err(arity_error(table.columns.len(), row.len()));
+    }
+    check_required(table, |i| i < row.len())?;
     let mut results= row.clone();
-    for column in table.columns.iter().skip(row.len()) {
-        if let Some(default) = &column.default {
-            results.push(default.clone());
-        } else {
-            return Err(Error::Internal(format!("No default value for column {}", column.name)));
-        }
+    for i in row.len()..table.columns.len() {
+        results.push(column_default(table, i)?);
     }
     Ok(results)
 }
 
+// The value of the omitted i-th column: its default if it has one,
+// NULL if it is nullable, else an error. Values INSERT filled in for
+// the statement are used first, else computed ones are evaluated now
+fn column_default(table: &Table, i: usize) -> Result<Value> {
+    if let Some(Some(value)) = table.defaults.get(i) {
+        return Ok(value.clone());
+    }
+    let column = &table.columns[i];
+    match (&column.default, &column.default_expr) {
+        (Some(value), _) => Ok(value.clone()),
+        (None, Some(expr)) => evaluate(expr, &Vec::new(), &ColumnMap::empty()),
+        (None, None) if column.nullable => Ok(Value::Null),
+        (None, None) => Err(Error::Internal(format!("Column {} is not nullable and has no default", column.name))),
+    }
+}
+
+// Position in the table of each named column
+// Insert into tbl(c,b) ... => [2, 1]
+pub fn column_positions(table: &Table, columns: &[String]) -> Result<Vec<usize>> {
+    let mut positions = Vec::with_capacity(columns.len());
+    for col_name in columns {
+        let pos = table.column_index(col_name)
+            .ok_or_else(|| Error::Internal(format!("Column {} not found in table {}", col_name, table.name)))?;
+        if positions.contains(&pos) {
+            return Err(Error::Internal(format!("Column {} specified more than once", col_name)));
+        }
+        positions.push(pos);
+    }
+    Ok(positions)
+}
+
 // tbl:
 // Insert into tbl(c,b) values (4,3);
 // a          b   c   d
 // default    3   4   default
-pub fn make_row(table: &Table, columns: &Vec<String>, values: &Row) -> Result<Row> {
+pub fn make_row(table: &Table, positions: &[usize], values: &Row) -> Result<Row> {
     // Check if the amount of values and rows match
-    if columns.len() != values.len() {
-        return Err(Error::Internal(format!("columns and values num mismatch")));
+    if positions.len() != values.len() {
+        return Err(arity_error(positions.len(), values.len()));
     }
 
-    let mut inputs = HashMap::new();
-    for (i, col_name) in columns.iter().enumerate() {
-        inputs.insert(col_name, values[i].clone());
+    let mut inputs = vec![None; table.columns.len()];
+    for (&pos, value) in positions.iter().zip(values) {
+        inputs[pos] = Some(value.clone());
     }
+    check_required(table, |i| inputs[i].is_some())?;
 
-    let mut results = Vec::new();
-    for col in table.columns.iter() {
-        if let Some(value) = inputs.get(&col.name) {
-            results.push(value.clone());
-        } else if let Some(value) = &col.default {
-            results.push(value.clone());
-        } else  {
-            return Err(Error::Internal(format!("No value given for the column {}", col.name)));
-        }
+    inputs.into_iter().enumerate().map(|(i, input)| match input {
+        Some(value) => Ok(value),
+        None => column_default(table, i),
+    }).collect()
+}
+
+// Omitted columns are filled by their default, NULL or the next id.
+// Only the required ones that were not given are an error, all named
+fn check_required(table: &Table, given: impl Fn(usize) -> bool) -> Result<()> {
+    let missing = table.required_columns().into_iter()
+        .filter(|&i| !given(i))
+        .map(|i| table.columns[i].name.as_str())
+        .collect::<Vec<_>>();
+    match missing.len() {
+        0 => Ok(()),
+        1 => Err(Error::Internal(format!("Missing value for required column {}", missing[0]))),
+        _ => Err(Error::Internal(format!("Missing values for required columns {}", missing.join(", ")))),
     }
+}
 
-    Ok(results)
-}
\ No newline at end of file
+fn arity_error(expected: usize, got: usize) -> Error {
+    Error::Internal(format!("expected {} value{}, got {}", expected, if expected == 1 { "" } else { "s" }, got))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::Result, sql::{parser::Parser, plan::{Node, Plan}, types::Value}};
+    use super::{make_row, pad_row};
+
+    #[test]
+    fn test_statement_defaults() -> Result<()> {
+        let mut table = match Plan::build(Parser::new("CREATE TABLE t (a int primary key, b varchar default 'x', c int, ts int default current_timestamp);").parse()?)?.0 {
+            Node::CreateTable { schema, .. } => schema,
+            n => panic!("unexpected plan {:?}", n),
+        };
+        let row = make_row(&table, &[0], &vec![Value::Integer(1)])?;
+        assert_eq!(row[..3], [Value::Integer(1), Value::String("x".into()), Value::Null]);
+
+        // Omitted columns take the values INSERT resolved for the statement
+        table.defaults = vec![None, Some(Value::String("resolved".into())), Some(Value::Null), Some(Value::Integer(7))];
+        let row = make_row(&table, &[0], &vec![Value::Integer(1)])?;
+        assert_eq!(row, vec![Value::Integer(1), Value::String("resolved".into()), Value::Null, Value::Integer(7)]);
+        let row = pad_row(&table, &vec![Value::Integer(2)])?;
+        assert_eq!(row, vec![Value::Integer(2), Value::String("resolved".into()), Value::Null, Value::Integer(7)]);
+        Ok(())
+    }
+}
@@ -1,24 +1,588 @@
-use crate::sql::engine::Transaction;
+use std::{cmp::Ordering, collections::{BinaryHeap, HashSet}, ops::Bound};
+
+use crate::{error::{Error, Result}, sql::{engine::Transaction, eval::{evaluate, evaluate_predicate, ColumnMap}, schema::Table, parser::ast::{Consts, Expression, Operation, OrderDirection}, types::{Row, Value}}};
 
 use super::{Executor, ResultSet};
 
 pub struct Scan {
     table_name: String,
+    // The predicate of the filter above, only used to narrow the scan
+    filter: Option<Expression>,
+    // The rows needed by the limit above, the scan stops after them
+    limit: Option<usize>,
 }
 
 impl Scan {
     pub fn new(table_name: String) -> Box<Self> {
-        Box::new(Self{table_name})
+        Box::new(Self{table_name, filter: None, limit: None})
+    }
+
+    pub fn with_filter(table_name: String, filter: Expression) -> Box<Self> {
+        Box::new(Self{table_name, filter: Some(filter), limit: None})
+    }
+
+    pub fn with_limit(table_name: String, limit: usize) -> Box<Self> {
+        Box::new(Self{table_name, filter: None, limit: Some(limit)})
     }
 }
 
 impl<T: Transaction> Executor<T> for Scan {
     fn execute(self:Box<Self>, txn:&mut T) -> crate::error::Result<super::ResultSet> {
         let table = txn.must_get_table(self.table_name.clone())?;
-        let rows = txn.scan_table(self.table_name.clone())?;
+        // Point lookup or range scan on the primary key, else a full scan
+        let rows = match (self.filter.as_ref().and_then(|f| primary_key_bounds(&table, f)), self.limit) {
+            (Some((start, end)), _) => txn.scan_table_value_range(self.table_name.clone(), start, end)?,
+            (None, Some(limit)) => txn.scan_table_first(self.table_name.clone(), limit)?,
+            (None, None) => txn.scan_table(self.table_name.clone())?,
+        };
         Ok(ResultSet::Scan { 
+            types: table.columns.iter().map(|c| Some(c.datatype.clone())).collect(),
             columns: table.columns.into_iter().map(|c| c.name.clone()).collect(), 
             rows,
          })
     }
-}
\ No newline at end of file
+}
+// The rows of a WITH INSERTION ORDER table in the order they were inserted
+pub struct InsertionScan {
+    table_name: String,
+    direction: OrderDirection,
+}
+
+impl InsertionScan {
+    pub fn new(table_name: String, direction: OrderDirection) -> Box<Self> {
+        Box::new(Self { table_name, direction })
+    }
+}
+
+impl<T: Transaction> Executor<T> for InsertionScan {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name.clone())?;
+        let mut rows = txn.scan_table_insertion_order(self.table_name)?;
+        if self.direction == OrderDirection::Desc {
+            rows.reverse();
+        }
+        Ok(ResultSet::Scan {
+            types: table.columns.iter().map(|c| Some(c.datatype.clone())).collect(),
+            columns: table.columns.into_iter().map(|c| c.name).collect(),
+            rows,
+        })
+    }
+}
+
+pub struct Filter<T: Transaction> {
+    // The table the rows come from, subqueries may name it
+    table_name: Option<String>,
+    source: Box<dyn Executor<T>>,
+    predicate: Expression,
+}
+
+impl<T: Transaction> Filter<T> {
+    pub fn new(table_name: Option<String>, source: Box<dyn Executor<T>>, predicate: Expression) -> Box<Self> {
+        Box::new(Self { table_name, source, predicate })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Filter<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, rows } => {
+                let mut results = Vec::new();
+                let correlated = self.predicate.has_subquery();
+                let schema = ColumnMap::new(&columns);
+                for row in rows {
+                    txn.check_cancelled()?;
+                    let matched = if correlated {
+                        let scope = Scope { table_name: self.table_name.as_deref(), columns: &columns, row: &row };
+                        evaluate_predicate(&bind_subqueries(&self.predicate, txn, &[scope])?, &row, &schema)?
+                    } else {
+                        evaluate_predicate(&self.predicate, &row, &schema)?
+                    };
+                    if matched == Some(true) {
+                        results.push(row);
+                    }
+                }
+                Ok(ResultSet::Scan { columns, types, rows: results })
+            }
+            _ => Err(Error::Internal("Unexpected result set for filter".into())),
+        }
+    }
+}
+
+pub struct Projection<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    expressions: Vec<(Expression, Option<String>)>,
+}
+
+impl<T: Transaction> Projection<T> {
+    pub fn new(source: Box<dyn Executor<T>>, expressions: Vec<(Expression, Option<String>)>) -> Box<Self> {
+        Box::new(Self { source, expressions })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Projection<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, rows } => {
+                let schema = ColumnMap::new(&columns);
+                // Columns are resolved to their position once, so an
+                // unknown one fails even without rows
+                let positions = self.expressions.iter().map(|(e, _)| match e {
+                    Expression::Field(name) => schema.resolve(name).map(Some)
+                        .ok_or_else(|| Error::Internal(format!("Column {} not found", name))),
+                    _ => Ok(None),
+                }).collect::<Result<Vec<_>>>()?;
+                // Values in the order of the SELECT list
+                let mut results = Vec::with_capacity(rows.len());
+                for row in rows {
+                    results.push(self.expressions.iter().zip(&positions).map(|((e, _), pos)| match pos {
+                        Some(i) => Ok(row[*i].clone()),
+                        None => evaluate(e, &row, &schema),
+                    }).collect::<Result<Vec<_>>>()?);
+                }
+                // The alias if any, else a column keeps its name
+                // and a function is named after it
+                let columns = self.expressions.into_iter().map(|(e, alias)| match (e, alias) {
+                    (_, Some(alias)) => alias,
+                    (Expression::Field(name), None) => name,
+                    (Expression::Function(name, _), None) => name,
+                    _ => "?column?".to_string(),
+                }).collect();
+                // A column keeps its type, a computed one takes the
+                // type of its first value that is not NULL
+                let types = positions.iter().enumerate().map(|(j, pos)| match pos {
+                    Some(i) => types[*i].clone(),
+                    None => results.iter().find_map(|row| row[j].datatype()),
+                }).collect();
+                Ok(ResultSet::Scan { columns, types, rows: results })
+            }
+            _ => Err(Error::Internal("Unexpected result set for projection".into())),
+        }
+    }
+}
+
+pub struct Distinct<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+}
+
+impl<T: Transaction> Distinct<T> {
+    pub fn new(source: Box<dyn Executor<T>>) -> Box<Self> {
+        Box::new(Self { source })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Distinct<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, mut rows } => {
+                // Keeps the first row of each group, NULLs group together
+                let mut seen = HashSet::new();
+                rows.retain(|row| seen.insert(row.iter().map(Value::hash_key).collect::<Vec<_>>()));
+                Ok(ResultSet::Scan { columns, types, rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set for distinct".into())),
+        }
+    }
+}
+
+pub struct Order<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    order_by: Vec<(Expression, OrderDirection)>,
+}
+
+impl<T: Transaction> Order<T> {
+    pub fn new(source: Box<dyn Executor<T>>, order_by: Vec<(Expression, OrderDirection)>) -> Box<Self> {
+        Box::new(Self { source, order_by })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Order<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, rows } => {
+                let (keys, computed) = sort_keys(&columns, &self.order_by)?;
+                let mut rows = append_sort_values(&columns, &computed, rows)?;
+                rows.sort_by(|a, b| compare_rows(&keys, a, b));
+                rows.iter_mut().for_each(|row| row.truncate(columns.len()));
+                Ok(ResultSet::Scan { columns, types, rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set for order".into())),
+        }
+    }
+}
+
+pub struct Limit<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    limit: usize,
+}
+
+impl<T: Transaction> Limit<T> {
+    pub fn new(source: Box<dyn Executor<T>>, limit: usize) -> Box<Self> {
+        Box::new(Self { source, limit })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Limit<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, mut rows } => {
+                rows.truncate(self.limit);
+                Ok(ResultSet::Scan { columns, types, rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set for limit".into())),
+        }
+    }
+}
+
+pub struct Offset<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    offset: usize,
+}
+
+impl<T: Transaction> Offset<T> {
+    pub fn new(source: Box<dyn Executor<T>>, offset: usize) -> Box<Self> {
+        Box::new(Self { source, offset })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Offset<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, rows } => Ok(ResultSet::Scan {
+                columns,
+                types,
+                rows: rows.into_iter().skip(self.offset).collect(),
+            }),
+            _ => Err(Error::Internal("Unexpected result set for offset".into())),
+        }
+    }
+}
+
+pub struct TopN<T: Transaction> {
+    source: Box<dyn Executor<T>>,
+    order_by: Vec<(Expression, OrderDirection)>,
+    limit: usize,
+    offset: usize,
+}
+
+impl<T: Transaction> TopN<T> {
+    pub fn new(source: Box<dyn Executor<T>>, order_by: Vec<(Expression, OrderDirection)>, limit: usize, offset: usize) -> Box<Self> {
+        Box::new(Self { source, order_by, limit, offset })
+    }
+}
+
+impl<T: Transaction> Executor<T> for TopN<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        match self.source.execute(txn)? {
+            ResultSet::Scan { columns, types, rows } => {
+                let (keys, computed) = sort_keys(&columns, &self.order_by)?;
+                let rows = append_sort_values(&columns, &computed, rows)?;
+                let rows = top_n(&keys, rows, self.limit.saturating_add(self.offset))
+                    .into_iter().skip(self.offset)
+                    .map(|mut row| { row.truncate(columns.len()); row })
+                    .collect();
+                Ok(ResultSet::Scan { columns, types, rows })
+            }
+            _ => Err(Error::Internal("Unexpected result set for top n".into())),
+        }
+    }
+}
+
+pub struct Nothing {
+    table_name: String,
+}
+
+impl Nothing {
+    pub fn new(table_name: String) -> Box<Self> {
+        Box::new(Self { table_name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Nothing {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        // Only the schema is read, for the column names
+        let table = txn.must_get_table(self.table_name)?;
+        Ok(ResultSet::Scan {
+            types: table.columns.iter().map(|c| Some(c.datatype.clone())).collect(),
+            columns: table.columns.into_iter().map(|c| c.name).collect(),
+            rows: Vec::new(),
+        })
+    }
+}
+
+// The plan is rendered when built, storage is never touched
+pub struct Explain {
+    plan_text: String,
+}
+
+impl Explain {
+    pub fn new(plan_text: String) -> Box<Self> {
+        Box::new(Self { plan_text })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Explain {
+    fn execute(self: Box<Self>, _txn: &mut T) -> Result<ResultSet> {
+        Ok(ResultSet::Explain { plan_text: self.plan_text })
+    }
+}
+
+type KeyBounds = (Bound<Value>, Bound<Value>);
+
+// The primary key range the predicate is limited to. Only the key column
+// compared with a constant of its type narrows the scan, alone or in an
+// AND, the filter above still checks every row:
+// a = 5 => [5, 5]    a > 5 => (5, ..)    5 >= a => (.., 5]
+// a >= 2 AND a < 5 => [2, 5)
+fn primary_key_bounds(table: &Table, predicate: &Expression) -> Option<KeyBounds> {
+    let pk = &table.columns[table.primary_key_index().ok()?];
+    let is_pk = |e: &Expression| matches!(e, Expression::Field(name)
+        if *name == pk.name || name.rsplit_once('.').is_some_and(|(_, c)| c == pk.name));
+    let constant = |e: &Expression| match e {
+        Expression::Consts(_) => Value::from_expression_to_value(e.clone()).ok()
+            .filter(|v| v.datatype().as_ref() == Some(&pk.datatype)),
+        _ => None,
+    };
+
+    let Expression::Operation(op) = predicate else { return None };
+    if let Operation::And(l, r) = op {
+        return match (primary_key_bounds(table, l), primary_key_bounds(table, r)) {
+            (Some((s1, e1)), Some((s2, e2))) => Some((tighter(s1, s2, Ordering::Greater), tighter(e1, e2, Ordering::Less))),
+            (bounds, None) | (None, bounds) => bounds,
+        };
+    }
+    let (l, r) = op.operands();
+    let (value, flipped) = match (is_pk(l), is_pk(r)) {
+        (true, false) => (constant(r)?, false),
+        (false, true) => (constant(l)?, true),
+        _ => return None,
+    };
+    match (op, flipped) {
+        (Operation::Equal(..), _) => Some((Bound::Included(value.clone()), Bound::Included(value))),
+        (Operation::GreaterThan(..), false) | (Operation::LessThan(..), true) => Some((Bound::Excluded(value), Bound::Unbounded)),
+        (Operation::GreaterThanOrEqual(..), false) | (Operation::LessThanOrEqual(..), true) => Some((Bound::Included(value), Bound::Unbounded)),
+        (Operation::LessThan(..), false) | (Operation::GreaterThan(..), true) => Some((Bound::Unbounded, Bound::Excluded(value))),
+        (Operation::LessThanOrEqual(..), false) | (Operation::GreaterThanOrEqual(..), true) => Some((Bound::Unbounded, Bound::Included(value))),
+        _ => None,
+    }
+}
+
+// The bound further in the direction, on a tie the excluded one
+fn tighter(a: Bound<Value>, b: Bound<Value>, direction: Ordering) -> Bound<Value> {
+    let order = match (&a, &b) {
+        (Bound::Unbounded, _) => return b,
+        (_, Bound::Unbounded) => return a,
+        (Bound::Included(x) | Bound::Excluded(x), Bound::Included(y) | Bound::Excluded(y)) => x.partial_cmp(y),
+    };
+    match order {
+        Some(Ordering::Equal) if matches!(a, Bound::Included(_)) => b,
+        Some(o) if o == direction || o == Ordering::Equal => a,
+        _ => b,
+    }
+}
+
+// A table around a subquery and its current row
+#[derive(Clone, Copy)]
+struct Scope<'a> {
+    table_name: Option<&'a str>,
+    columns: &'a [String],
+    row: &'a Row,
+}
+
+// Replace the EXISTS subqueries of the predicate by their result for the row.
+// Nested loop: the inner table is scanned again for every outer row.
+// The scopes go from the outermost table to the innermost.
+fn bind_subqueries<T: Transaction>(expr: &Expression, txn: &mut T, scopes: &[Scope]) -> Result<Expression> {
+    expr.clone().transform(&mut |expr| match expr {
+        Expression::Exists { table_name, filter } => {
+            let inner = txn.must_get_table(table_name.clone())?
+                .columns.into_iter().map(|c| c.name).collect::<Vec<_>>();
+            let filter = filter.map(|f| correlate(*f, &table_name, &inner, scopes)).transpose()?;
+            let schema = ColumnMap::new(&inner);
+            for inner_row in txn.scan_table(table_name.clone())? {
+                let matched = match &filter {
+                    Some(f) if f.has_subquery() => {
+                        let scope = Scope { table_name: Some(&table_name), columns: &inner, row: &inner_row };
+                        let scopes = [scopes, &[scope]].concat();
+                        evaluate_predicate(&bind_subqueries(f, txn, &scopes)?, &inner_row, &schema)?
+                    }
+                    Some(f) => evaluate_predicate(f, &inner_row, &schema)?,
+                    None => Some(true),
+                };
+                if matched == Some(true) {
+                    return Ok(Consts::Boolean(true).into());
+                }
+            }
+            Ok(Consts::Boolean(false).into())
+        }
+        expr => Ok(expr),
+    })
+}
+
+// Outer references in a subquery filter become constants of the outer rows:
+// columns qualified with another table, or columns the inner table lacks.
+// A qualified column is taken from the table it names, an unqualified
+// one from the nearest table that has it.
+fn correlate(filter: Expression, table_name: &str, inner: &[String], scopes: &[Scope]) -> Result<Expression> {
+    filter.transform(&mut |expr| {
+        let Expression::Field(name) = &expr else {
+            return Ok(expr);
+        };
+        let (table, column) = match name.split_once('.') {
+            Some((table, column)) => (Some(table), column),
+            None => (None, name.as_str()),
+        };
+        let is_inner = match table {
+            Some(table) => table == table_name,
+            None => inner.iter().any(|c| c == column),
+        };
+        if is_inner {
+            return Ok(Expression::Field(column.to_string()));
+        }
+        let value = scopes.iter().rev()
+            .filter(|scope| table.is_none() || scope.table_name == table)
+            .find_map(|scope| scope.columns.iter().position(|c| c == column).map(|i| scope.row[i].clone()));
+        match value {
+            Some(value) => Ok(value.into()),
+            None => Err(Error::Internal(format!("Column {} not found", name))),
+        }
+    })
+}
+
+// -+------------------------+-
+//      Sorting Part
+// -+------------------------+-
+
+type SortKeys = Vec<(usize, OrderDirection)>;
+
+// Resolve ORDER BY keys into row positions. A key other than an output
+// column is computed, its value goes after the columns of each row:
+// ORDER BY b, a + b  =>  row [a, b, a + b], keys [1, 2]
+fn sort_keys(columns: &[String], order_by: &[(Expression, OrderDirection)]) -> Result<(SortKeys, Vec<Expression>)> {
+    let mut computed = Vec::new();
+    let mut keys = Vec::with_capacity(order_by.len());
+    let schema = ColumnMap::new(columns);
+    for (expr, direction) in order_by {
+        let i = match expr {
+            Expression::Field(name) => schema.resolve(name)
+                .ok_or_else(|| Error::Internal(format!("Column {} not found", name)))?,
+            expr => {
+                computed.push(expr.clone());
+                columns.len() + computed.len() - 1
+            }
+        };
+        keys.push((i, *direction));
+    }
+    Ok((keys, computed))
+}
+
+// Evaluate the computed sort keys and append them to each row
+fn append_sort_values(columns: &[String], computed: &[Expression], rows: Vec<Row>) -> Result<Vec<Row>> {
+    if computed.is_empty() {
+        return Ok(rows);
+    }
+    let schema = ColumnMap::new(columns);
+    rows.into_iter().map(|mut row| {
+        for expr in computed {
+            let value = evaluate(expr, &row, &schema)?;
+            row.push(value);
+        }
+        Ok(row)
+    }).collect()
+}
+
+// NULL sorts first, mixed types fall back to the type rank
+fn compare_rows(keys: &SortKeys, a: &Row, b: &Row) -> Ordering {
+    for (i, direction) in keys {
+        let ordering = a[*i].sort_cmp(&b[*i]);
+        let ordering = match direction {
+            OrderDirection::Asc => ordering,
+            OrderDirection::Desc => ordering.reverse(),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
+
+// Rows with equal keys go by their position in the input, the same
+// order the stable sort of ORDER BY gives them
+struct HeapRow<'a> {
+    keys: &'a SortKeys,
+    seq: usize,
+    row: Row,
+}
+
+impl PartialEq for HeapRow<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapRow<'_> {}
+
+impl PartialOrd for HeapRow<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HeapRow<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare_rows(self.keys, &self.row, &other.row).then(self.seq.cmp(&other.seq))
+    }
+}
+
+// The first n rows in order, the heap never holds more than n rows:
+// its top is the last one kept, and it is dropped for anything smaller
+fn top_n(keys: &SortKeys, rows: impl IntoIterator<Item = Row>, n: usize) -> Vec<Row> {
+    if n == 0 {
+        return Vec::new();
+    }
+    // Sized by the rows there are, a huge n allocates nothing up front
+    let rows = rows.into_iter();
+    let mut heap = BinaryHeap::with_capacity(n.min(rows.size_hint().0).saturating_add(1));
+    for (seq, row) in rows.enumerate() {
+        heap.push(HeapRow { keys, seq, row });
+        if heap.len() > n {
+            heap.pop();
+        }
+    }
+    heap.into_sorted_vec().into_iter().map(|r| r.row).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sql::{parser::ast::OrderDirection, types::Value};
+    use super::{compare_rows, top_n};
+
+    #[test]
+    fn test_top_n() {
+        // Rows are produced lazily, only the heap holds any of them
+        let rows = (0..10_000i64).map(|i| vec![Value::Integer((i * 7919) % 10_000), Value::Integer(i % 2)]);
+        let keys = vec![(0, OrderDirection::Asc)];
+        assert_eq!(top_n(&keys, rows.clone(), 3), vec![
+            vec![Value::Integer(0), Value::Integer(0)],
+            vec![Value::Integer(1), Value::Integer(1)],
+            vec![Value::Integer(2), Value::Integer(0)],
+        ]);
+
+        let keys = vec![(1, OrderDirection::Desc), (0, OrderDirection::Desc)];
+        let top = top_n(&keys, rows.clone(), 2);
+        assert_eq!(top.iter().map(|r| r[0].clone()).collect::<Vec<_>>(), vec![Value::Integer(9999), Value::Integer(9997)]);
+
+        assert!(top_n(&keys, rows, 0).is_empty());
+    }
+
+    #[test]
+    fn test_top_n_ties() {
+        // A prefix of the stable sort, ties keep the input order
+        let rows = (0..50i64).map(|i| vec![Value::Integer(i % 3), Value::Integer(i)]).collect::<Vec<_>>();
+        let keys = vec![(0, OrderDirection::Desc)];
+        let mut sorted = rows.clone();
+        sorted.sort_by(|a, b| compare_rows(&keys, a, b));
+        for n in [1, 3, 17, 50, 60] {
+            assert_eq!(top_n(&keys, rows.clone(), n), sorted[..n.min(50)]);
+        }
+    }
+}
@@ -1,22 +1,138 @@
 use crate::sql::engine::Transaction;
-use crate::sql::schema::Table;
-use crate::error::Result;
+use crate::sql::schema::{Column, Table};
+use crate::sql::parser::ast::Expression;
+use crate::error::{Error, Result};
 use super::{Executor, ResultSet};
 
 pub struct CreateTable {
     schema: Table,
+    or_replace: bool,
 }
 
 impl CreateTable {
-    pub fn new(schema: Table) -> Box<Self> {
-        Box::new(Self {schema})
+    pub fn new(schema: Table, or_replace: bool) -> Box<Self> {
+        Box::new(Self {schema, or_replace})
     }
 }
 
 impl<T:Transaction> Executor<T> for CreateTable {
     fn execute(self: Box<Self>, txn:&mut T) -> Result<ResultSet> {
         let table_name = self.schema.name.clone();
+        // Drop the old table with its rows first, in the same transaction
+        if self.or_replace && txn.get_table(table_name.clone())?.is_some() {
+            txn.drop_table(table_name.clone())?;
+        }
         txn.create_table(self.schema)?;
         Ok(ResultSet::CreateTable { table_name })
     }
-}
\ No newline at end of file
+}
+
+pub struct DropTable {
+    table_name: String,
+    if_exists: bool,
+}
+
+impl DropTable {
+    pub fn new(table_name: String, if_exists: bool) -> Box<Self> {
+        Box::new(Self { table_name, if_exists })
+    }
+}
+
+impl<T: Transaction> Executor<T> for DropTable {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        if !self.if_exists || txn.table_exists(&self.table_name)? {
+            txn.drop_table(self.table_name.clone())?;
+        }
+        Ok(ResultSet::DropTable { table_name: self.table_name })
+    }
+}
+
+// CREATE TABLE t2 AS SELECT * FROM t1:
+// Columns keep the definitions of the source table, including the primary key,
+// so the query must select the primary key column of the source.
+pub struct CreateTableAs<T: Transaction> {
+    table_name: String,
+    source_table: String,
+    source: Box<dyn Executor<T>>,
+}
+
+impl<T: Transaction> CreateTableAs<T> {
+    pub fn new(table_name: String, source_table: String, source: Box<dyn Executor<T>>) -> Box<Self> {
+        Box::new(Self { table_name, source_table, source })
+    }
+}
+
+impl<T: Transaction> Executor<T> for CreateTableAs<T> {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let source_table = txn.must_get_table(self.source_table.clone())?;
+        let (columns, rows) = match self.source.execute(txn)? {
+            ResultSet::Scan { columns, rows, .. } => (columns, rows),
+            _ => return Err(Error::Internal("CREATE TABLE AS expects a query".into())),
+        };
+
+        let columns = columns.iter().map(|name| {
+            source_table.column(name).cloned()
+                .ok_or(Error::Internal(format!("Cannot infer the datatype of column {}", name)))
+        }).collect::<Result<Vec<Column>>>()?;
+
+        let table_name = self.table_name.clone();
+        // Each column keeps its primary key, NOT NULL, DEFAULT and
+        // AUTOINCREMENT. UNIQUE sets and insertion order are not copied
+        txn.create_table(Table::new(self.table_name, columns))?;
+        for row in rows {
+            txn.create_row(table_name.clone(), row)?;
+        }
+        Ok(ResultSet::CreateTable { table_name })
+    }
+}
+
+// Only the stored schema changes, rows already written keep their values
+pub struct AlterColumnDefault {
+    table_name: String,
+    column_name: String,
+    default: Option<Expression>,
+}
+
+impl AlterColumnDefault {
+    pub fn new(table_name: String, column_name: String, default: Option<Expression>) -> Box<Self> {
+        Box::new(Self { table_name, column_name, default })
+    }
+}
+
+impl<T: Transaction> Executor<T> for AlterColumnDefault {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let mut table = txn.must_get_table(self.table_name.clone())?;
+        let column = table.columns.iter_mut().find(|c| c.name == self.column_name)
+            .ok_or(Error::Internal(format!("Column {} not found in table {}", self.column_name, self.table_name)))?;
+        column.set_default(self.default)?;
+        if let Some(default) = &column.default {
+            match default.datatype() {
+                Some(dt) if dt != column.datatype =>
+                    return Err(Error::Internal(format!("Column {} datatype mismatch for default {}", column.name, default))),
+                None if !column.nullable =>
+                    return Err(Error::Internal(format!("Column {} cannot default to null", column.name))),
+                _ => {}
+            }
+        }
+
+        txn.update_table(table)?;
+        Ok(ResultSet::AlterTable { table_name: self.table_name })
+    }
+}
+
+pub struct Describe {
+    table_name: String,
+}
+
+impl Describe {
+    pub fn new(table_name: String) -> Box<Self> {
+        Box::new(Self { table_name })
+    }
+}
+
+impl<T: Transaction> Executor<T> for Describe {
+    fn execute(self: Box<Self>, txn: &mut T) -> Result<ResultSet> {
+        let table = txn.must_get_table(self.table_name)?;
+        Ok(ResultSet::Describe { schema: table.to_string() })
+    }
+}
@@ -3,4 +3,5 @@ pub mod types;
 pub mod plan;
 pub mod schema;
 pub mod executor;
-pub mod engine;
\ No newline at end of file
+pub mod engine;
+pub mod eval;
\ No newline at end of file
@@ -1,15 +1,152 @@
-use crate::sql::types::DataTypes;
-use std::convert::From;
+use crate::{error::Result, sql::types::DataTypes};
+use serde::{Deserialize, Serialize};
+use std::{convert::From, fmt::Display};
 
 #[derive(Debug, PartialEq)]
 pub enum Statement {
-    CreateTable {name: String, columns: Vec<Column>},
+    // unique: the table level UNIQUE (a, b) column sets
+    // insertion_order: WITH INSERTION ORDER, the rows can be read in the order they were inserted
+    CreateTable {name: String, columns: Vec<Column>, unique: Vec<Vec<String>>, or_replace: bool, insertion_order: bool},
+    CreateTableAs {name: String, query: Box<Statement>},
+    // DROP TABLE [ IF EXISTS ] name
+    DropTable {name: String, if_exists: bool},
     Insert {
         table_name: String,
         columns: Option<Vec<String>>,
         values: Vec<Vec<Expression>>
     },
-    Select { table_name: String},
+    // DELETE FROM table_name [ WHERE filter ]
+    Delete {
+        table_name: String,
+        filter: Option<Expression>,
+    },
+    // UPDATE table_name SET column = expr, ... [ WHERE filter ]
+    Update {
+        table_name: String,
+        columns: Vec<(String, Expression)>,
+        filter: Option<Expression>,
+    },
+    Select {
+        // SELECT DISTINCT, duplicate rows are dropped
+        distinct: bool,
+        // Empty means *, each item may carry an AS alias
+        columns: Vec<(Expression, Option<String>)>,
+        table_name: String,
+        filter: Option<Expression>,
+        // AS OF VERSION n: read the snapshot of a past version
+        as_of: Option<u64>,
+        // ORDER BY expr [ ASC | DESC ], ...
+        order_by: Vec<(Expression, OrderDirection)>,
+        // ORDER BY INSERTION [ ASC | DESC ], instead of order_by
+        insertion_order: Option<OrderDirection>,
+        limit: Option<usize>,
+        offset: Option<usize>,
+    },
+    Describe {
+        table_name: String,
+    },
+    // Explicit transaction, handled by the session
+    Begin,
+    Commit,
+    Rollback,
+    // Drop the old versions and compact the storage, handled by the session
+    Vacuum,
+    // EXPLAIN statement: show the plan, nothing is run
+    Explain {
+        statement: Box<Statement>,
+    },
+    // ALTER TABLE t ALTER COLUMN c { SET DEFAULT expr | DROP DEFAULT }
+    AlterColumnDefault {
+        table_name: String,
+        column_name: String,
+        // None for DROP DEFAULT
+        default: Option<Expression>,
+    },
+}
+
+impl Statement {
+    // Statements that change the schema
+    pub fn is_ddl(&self) -> bool {
+        matches!(self, Statement::CreateTable { .. } | Statement::CreateTableAs { .. } | Statement::DropTable { .. } | Statement::AlterColumnDefault { .. })
+    }
+}
+
+// Canonical SQL, parsing it gives back the same statement
+// insert into t values (1,'a') => INSERT INTO t VALUES (1, 'a')
+impl Statement {
+    pub fn to_sql(&self) -> String {
+        let list = |exprs: &[Expression]| exprs.iter().map(|e| e.to_string()).collect::<Vec<_>>().join(", ");
+        let filter_sql = |filter: &Option<Expression>| filter.as_ref().map(|f| format!(" WHERE {}", f)).unwrap_or_default();
+        match self {
+            Statement::CreateTable { name, columns, unique, or_replace, insertion_order } => {
+                let mut items = columns.iter().map(|c| c.to_sql()).collect::<Vec<_>>();
+                items.extend(unique.iter().map(|set| format!("UNIQUE ({})", set.join(", "))));
+                format!("CREATE {}TABLE {} ({}){}", if *or_replace { "OR REPLACE " } else { "" }, name, items.join(", "),
+                    if *insertion_order { " WITH INSERTION ORDER" } else { "" })
+            }
+            Statement::CreateTableAs { name, query } => format!("CREATE TABLE {} AS {}", name, query.to_sql()),
+            Statement::DropTable { name, if_exists } => format!("DROP TABLE {}{}", if *if_exists { "IF EXISTS " } else { "" }, name),
+            Statement::Insert { table_name, columns, values } => format!("INSERT INTO {}{} VALUES {}",
+                table_name,
+                columns.as_ref().map(|c| format!(" ({})", c.join(", "))).unwrap_or_default(),
+                values.iter().map(|row| format!("({})", list(row))).collect::<Vec<_>>().join(", ")),
+            Statement::Delete { table_name, filter } => format!("DELETE FROM {}{}", table_name, filter_sql(filter)),
+            Statement::Update { table_name, columns, filter } => format!("UPDATE {} SET {}{}",
+                table_name,
+                columns.iter().map(|(c, e)| format!("{} = {}", c, e)).collect::<Vec<_>>().join(", "),
+                filter_sql(filter)),
+            Statement::Select { distinct, columns, table_name, filter, as_of, order_by, insertion_order, limit, offset } => {
+                let mut sql = format!("SELECT {}", if *distinct { "DISTINCT " } else { "" });
+                sql += &match columns.is_empty() {
+                    true => "*".to_string(),
+                    false => columns.iter().map(|(e, alias)| match alias {
+                        Some(alias) => format!("{} AS {}", e, alias),
+                        None => e.to_string(),
+                    }).collect::<Vec<_>>().join(", "),
+                };
+                sql += &format!(" FROM {}", table_name);
+                if let Some(version) = as_of {
+                    sql += &format!(" AS OF VERSION {}", version);
+                }
+                sql += &filter_sql(filter);
+                if !order_by.is_empty() {
+                    sql += &format!(" ORDER BY {}", order_by.iter().map(|(e, d)| match d {
+                        OrderDirection::Asc => format!("{} ASC", e),
+                        OrderDirection::Desc => format!("{} DESC", e),
+                    }).collect::<Vec<_>>().join(", "));
+                }
+                match insertion_order {
+                    Some(OrderDirection::Asc) => sql += " ORDER BY INSERTION ASC",
+                    Some(OrderDirection::Desc) => sql += " ORDER BY INSERTION DESC",
+                    None => {}
+                }
+                if let Some(limit) = limit {
+                    sql += &format!(" LIMIT {}", limit);
+                }
+                if let Some(offset) = offset {
+                    sql += &format!(" OFFSET {}", offset);
+                }
+                sql
+            }
+            Statement::Describe { table_name } => format!("DESCRIBE {}", table_name),
+            Statement::Begin => "BEGIN".to_string(),
+            Statement::Commit => "COMMIT".to_string(),
+            Statement::Rollback => "ROLLBACK".to_string(),
+            Statement::Vacuum => "VACUUM".to_string(),
+            Statement::Explain { statement } => format!("EXPLAIN {}", statement.to_sql()),
+            Statement::AlterColumnDefault { table_name, column_name, default } => format!("ALTER TABLE {} ALTER COLUMN {} {}",
+                table_name, column_name, match default {
+                    Some(expr) => format!("SET DEFAULT {}", expr),
+                    None => "DROP DEFAULT".to_string(),
+                }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OrderDirection {
+    Asc,
+    Desc,
 }
 
 #[derive(Debug, PartialEq)]
@@ -19,11 +156,78 @@ pub struct Column {
     pub nullable: Option<bool>,
     pub default: Option<Expression>,
     pub primary_key: bool,
+    pub unique: bool,
+    pub autoincrement: bool,
 }
 
-#[derive(Debug, PartialEq)]
+impl Column {
+    // name TYPE [ NULL | NOT NULL ] [ DEFAULT expr ] [ PRIMARY KEY ] [ UNIQUE ] [ AUTOINCREMENT ]
+    pub fn to_sql(&self) -> String {
+        let mut sql = format!("{} {}", self.name, match self.datatype {
+            DataTypes::Boolean => "BOOLEAN",
+            DataTypes::Integer => "INTEGER",
+            DataTypes::Float => "FLOAT",
+            DataTypes::String => "VARCHAR",
+        });
+        match self.nullable {
+            Some(true) => sql += " NULL",
+            Some(false) => sql += " NOT NULL",
+            None => {},
+        }
+        if let Some(expr) = &self.default {
+            sql += &format!(" DEFAULT {}", expr);
+        }
+        if self.primary_key {
+            sql += " PRIMARY KEY";
+        }
+        if self.unique {
+            sql += " UNIQUE";
+        }
+        if self.autoincrement {
+            sql += " AUTOINCREMENT";
+        }
+        sql
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Expression {
     Consts(Consts),
+    Field(String),
+    Operation(Operation),
+    // name(args), COUNT(*) has no args
+    Function(String, Vec<Expression>),
+    // EXISTS (SELECT ... FROM table_name [ WHERE filter ])
+    Exists {
+        table_name: String,
+        filter: Option<Box<Expression>>,
+    },
+}
+
+impl Expression {
+    // Rewrite bottom-up, the children before the expression itself.
+    // A subquery is a scope of its own, its filter is not entered.
+    pub fn transform(self, f: &mut impl FnMut(Expression) -> Result<Expression>) -> Result<Expression> {
+        let expr = match self {
+            Expression::Operation(op) => Expression::Operation(op.map_operands(|e| e.transform(f))?),
+            Expression::Function(name, args) =>
+                Expression::Function(name, args.into_iter().map(|a| a.transform(f)).collect::<Result<_>>()?),
+            expr => expr,
+        };
+        f(expr)
+    }
+
+    pub fn has_subquery(&self) -> bool {
+        match self {
+            Expression::Exists { .. } => true,
+            Expression::Operation(op) => {
+                let (l, r) = op.operands();
+                l.has_subquery() || r.has_subquery()
+            }
+            Expression::Function(_, args) => args.iter().any(|a| a.has_subquery()),
+            Expression::Consts(_) | Expression::Field(_) => false,
+        }
+    }
 }
 
 impl From<Consts> for Expression {
@@ -32,7 +236,70 @@ impl From<Consts> for Expression {
     }
 }
 
-#[derive(Debug, PartialEq)]
+impl From<Operation> for Expression {
+    fn from(value: Operation) -> Self {
+        Self::Operation(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Operation {
+    Equal(Box<Expression>, Box<Expression>),
+    NotEqual(Box<Expression>, Box<Expression>),
+    GreaterThan(Box<Expression>, Box<Expression>),
+    LessThan(Box<Expression>, Box<Expression>),
+    GreaterThanOrEqual(Box<Expression>, Box<Expression>),
+    LessThanOrEqual(Box<Expression>, Box<Expression>),
+    Add(Box<Expression>, Box<Expression>),
+    Subtract(Box<Expression>, Box<Expression>),
+    Multiply(Box<Expression>, Box<Expression>),
+    Divide(Box<Expression>, Box<Expression>),
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+}
+
+// Builds a binary operation node from its two operands
+pub type BinaryOp = fn(Box<Expression>, Box<Expression>) -> Operation;
+
+impl Operation {
+    pub fn operands(&self) -> (&Expression, &Expression) {
+        match self {
+            Operation::Equal(l, r)
+            | Operation::NotEqual(l, r)
+            | Operation::GreaterThan(l, r)
+            | Operation::LessThan(l, r)
+            | Operation::GreaterThanOrEqual(l, r)
+            | Operation::LessThanOrEqual(l, r)
+            | Operation::Add(l, r)
+            | Operation::Subtract(l, r)
+            | Operation::Multiply(l, r)
+            | Operation::Divide(l, r)
+            | Operation::And(l, r)
+            | Operation::Or(l, r) => (l, r),
+        }
+    }
+
+    // The same operation over the rewritten operands
+    pub fn map_operands(self, mut f: impl FnMut(Expression) -> Result<Expression>) -> Result<Operation> {
+        let (op, l, r): (BinaryOp, _, _) = match self {
+            Operation::Equal(l, r) => (Operation::Equal, l, r),
+            Operation::NotEqual(l, r) => (Operation::NotEqual, l, r),
+            Operation::GreaterThan(l, r) => (Operation::GreaterThan, l, r),
+            Operation::LessThan(l, r) => (Operation::LessThan, l, r),
+            Operation::GreaterThanOrEqual(l, r) => (Operation::GreaterThanOrEqual, l, r),
+            Operation::LessThanOrEqual(l, r) => (Operation::LessThanOrEqual, l, r),
+            Operation::Add(l, r) => (Operation::Add, l, r),
+            Operation::Subtract(l, r) => (Operation::Subtract, l, r),
+            Operation::Multiply(l, r) => (Operation::Multiply, l, r),
+            Operation::Divide(l, r) => (Operation::Divide, l, r),
+            Operation::And(l, r) => (Operation::And, l, r),
+            Operation::Or(l, r) => (Operation::Or, l, r),
+        };
+        Ok(op(Box::new(f(*l)?), Box::new(f(*r)?)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Consts {
     Null,
     Boolean(bool),
@@ -41,3 +308,43 @@ pub enum Consts {
     String(String),
 }
 
+// Render the expression back as SQL
+impl Display for Expression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Consts(Consts::Null) => write!(f, "NULL"),
+            Expression::Consts(Consts::Boolean(b)) => write!(f, "{}", if *b { "TRUE" } else { "FALSE" }),
+            Expression::Consts(Consts::Integer(i)) => write!(f, "{}", i),
+            Expression::Consts(Consts::Float(v)) => write!(f, "{:?}", v),
+            Expression::Consts(Consts::String(s)) => write!(f, "'{}'", s),
+            Expression::Field(name) => write!(f, "{}", name),
+            Expression::Operation(op) => {
+                let (l, r, op) = match op {
+                    Operation::Equal(l, r) => (l, r, "="),
+                    Operation::NotEqual(l, r) => (l, r, "!="),
+                    Operation::GreaterThan(l, r) => (l, r, ">"),
+                    Operation::LessThan(l, r) => (l, r, "<"),
+                    Operation::GreaterThanOrEqual(l, r) => (l, r, ">="),
+                    Operation::LessThanOrEqual(l, r) => (l, r, "<="),
+                    Operation::Add(l, r) => (l, r, "+"),
+                    Operation::Subtract(l, r) => (l, r, "-"),
+                    Operation::Multiply(l, r) => (l, r, "*"),
+                    Operation::Divide(l, r) => (l, r, "/"),
+                    Operation::And(l, r) => (l, r, "AND"),
+                    Operation::Or(l, r) => (l, r, "OR"),
+                };
+                write!(f, "({} {} {})", l, op, r)
+            }
+            Expression::Function(name, _) if name == CURRENT_TIMESTAMP => write!(f, "CURRENT_TIMESTAMP"),
+            Expression::Function(name, args) if args.is_empty() => write!(f, "{}(*)", name.to_uppercase()),
+            Expression::Function(name, args) => write!(f, "{}({})",
+                name.to_uppercase(), args.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(", ")),
+            Expression::Exists { table_name, filter: None } => write!(f, "EXISTS (SELECT * FROM {})", table_name),
+            Expression::Exists { table_name, filter: Some(filter) } =>
+                write!(f, "EXISTS (SELECT * FROM {} WHERE {})", table_name, filter),
+        }
+    }
+}
+
+// CURRENT_TIMESTAMP is a function without arguments
+pub const CURRENT_TIMESTAMP: &str = "current_timestamp";
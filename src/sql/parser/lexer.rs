@@ -17,6 +17,13 @@ pub enum Token {
     Plus,
     Minus,
     Slash,
+    Equal,
+    GreaterThan,
+    LessThan,
+    GreaterThanOrEqual,
+    LessThanOrEqual,
+    NotEqual,
+    Period,
 }
 
 
@@ -35,6 +42,13 @@ impl Display for Token {
             Token::Plus => "+",
             Token::Minus => "-",
             Token::Slash => "/",
+            Token::Equal => "=",
+            Token::GreaterThan => ">",
+            Token::LessThan => "<",
+            Token::GreaterThanOrEqual => ">=",
+            Token::LessThanOrEqual => "<=",
+            Token::NotEqual => "!=",
+            Token::Period => ".",
         })
     }
 }
@@ -65,6 +79,40 @@ pub enum Keyword {
     Null,
     Primary,
     Key,
+    As,
+    Or,
+    And,
+    Replace,
+    Where,
+    Of,
+    Version,
+    Order,
+    By,
+    Asc,
+    Desc,
+    Limit,
+    Offset,
+    All,
+    Describe,
+    Delete,
+    CurrentTimestamp,
+    Distinct,
+    Unique,
+    Autoincrement,
+    If,
+    Exists,
+    Explain,
+    Begin,
+    Commit,
+    Rollback,
+    Alter,
+    Column,
+    Set,
+    Drop,
+    Update,
+    Vacuum,
+    With,
+    Insertion,
 }
 
 impl Keyword {
@@ -93,17 +141,71 @@ impl Keyword {
             "NULL" => Keyword::Null,
             "PRIMARY" => Keyword::Primary,
             "KEY" => Keyword::Key,
+            "AS" => Keyword::As,
+            "OR" => Keyword::Or,
+            "AND" => Keyword::And,
+            "REPLACE" => Keyword::Replace,
+            "WHERE" => Keyword::Where,
+            "OF" => Keyword::Of,
+            "VERSION" => Keyword::Version,
+            "ORDER" => Keyword::Order,
+            "BY" => Keyword::By,
+            "ASC" => Keyword::Asc,
+            "DESC" => Keyword::Desc,
+            "LIMIT" => Keyword::Limit,
+            "OFFSET" => Keyword::Offset,
+            "ALL" => Keyword::All,
+            "DESCRIBE" => Keyword::Describe,
+            "DELETE" => Keyword::Delete,
+            "UPDATE" => Keyword::Update,
+            "VACUUM" => Keyword::Vacuum,
+            "WITH" => Keyword::With,
+            "INSERTION" => Keyword::Insertion,
+            "CURRENT_TIMESTAMP" => Keyword::CurrentTimestamp,
+            "DISTINCT" => Keyword::Distinct,
+            "UNIQUE" => Keyword::Unique,
+            "AUTOINCREMENT" => Keyword::Autoincrement,
+            "IF" => Keyword::If,
+            "EXISTS" => Keyword::Exists,
+            "EXPLAIN" => Keyword::Explain,
+            "BEGIN" => Keyword::Begin,
+            "COMMIT" => Keyword::Commit,
+            "ROLLBACK" => Keyword::Rollback,
+            "ALTER" => Keyword::Alter,
+            "COLUMN" => Keyword::Column,
+            "SET" => Keyword::Set,
+            "DROP" => Keyword::Drop,
             _ => return None,
         })
     }
 
     pub fn to_str(&self) -> &str {
         match self {
+            Keyword::All => "ALL",
+            Keyword::Alter => "ALTER",
+            Keyword::As => "AS",
+            Keyword::Asc => "ASC",
+            Keyword::Begin => "BEGIN",
             Keyword::Bool => "BOOL",
             Keyword::Boolean => "BOOLEAN",
+            Keyword::By => "BY",
+            Keyword::Column => "COLUMN",
+            Keyword::Commit => "COMMIT",
             Keyword::Create => "CREATE",
+            Keyword::CurrentTimestamp => "CURRENT_TIMESTAMP",
             Keyword::Default => "DEFAULT",
+            Keyword::Delete => "DELETE",
+            Keyword::Desc => "DESC",
+            Keyword::Describe => "DESCRIBE",
+            Keyword::Distinct => "DISTINCT",
             Keyword::Double => "DOUBLE",
+            Keyword::Drop => "DROP",
+            Keyword::Update => "UPDATE",
+            Keyword::Vacuum => "VACUUM",
+            Keyword::With => "WITH",
+            Keyword::Insertion => "INSERTION",
+            Keyword::Exists => "EXISTS",
+            Keyword::Explain => "EXPLAIN",
             Keyword::False => "FALSE",
             Keyword::Float => "FLOAT",
             Keyword::From => "FROM",
@@ -112,16 +214,30 @@ impl Keyword {
             Keyword::Integer => "INTEGER",
             Keyword::Into => "INTO",
             Keyword::Key => "KEY",
+            Keyword::Limit => "LIMIT",
             Keyword::Not => "NOT",
             Keyword::Null => "NULL",
+            Keyword::Of => "OF",
+            Keyword::Offset => "OFFSET",
+            Keyword::Or => "OR",
+            Keyword::And => "AND",
+            Keyword::Order => "ORDER",
             Keyword::Primary => "PRIMARY",
+            Keyword::Replace => "REPLACE",
+            Keyword::Rollback => "ROLLBACK",
             Keyword::Select => "SELECT",
+            Keyword::Set => "SET",
             Keyword::String => "STRING",
             Keyword::Table => "TABLE",
             Keyword::Text => "TEXT",
             Keyword::True => "TRUE",
+            Keyword::Unique => "UNIQUE",
+            Keyword::Autoincrement => "AUTOINCREMENT",
+            Keyword::If => "IF",
             Keyword::Values => "VALUES",
             Keyword::Varchar => "VARCHAR",
+            Keyword::Version => "VERSION",
+            Keyword::Where => "WHERE",
         }
     }
 }
@@ -137,10 +253,11 @@ impl Display for Keyword {
 //
 // 1. Create Table
 // ---------------------------
-// CREATE TABLE table_name (
+// CREATE [ OR REPLACE ] TABLE table_name (
 //      [ column_name data_type [ column_constraints [...] ] ]
 //      [, ...]
-//      );
+//      [, UNIQUE ( column_name [, ...] ) ]
+//      ) [ WITH INSERTION ORDER ];
 //
 //      where data_type is:
 //      - BOOLEAN(BOOL): type | false
@@ -149,7 +266,14 @@ impl Display for Keyword {
 //      - STRING(TEXT, VARCHAR)
 //
 //      where column_constraints is:
-//      [ NOT NULL | NULL | DEFAULT expr ]
+//      [ NOT NULL | NULL | DEFAULT expr | DEFAULT CURRENT_TIMESTAMP | UNIQUE | AUTOINCREMENT ]
+//
+// CREATE TABLE table_name AS SELECT ...;
+//
+// ALTER TABLE table_name ALTER COLUMN column_name
+//      { SET DEFAULT expr | DROP DEFAULT };
+//
+// DROP TABLE [ IF EXISTS ] table_name;
 //
 // 2. Insert Into
 // ---------------------------
@@ -159,10 +283,58 @@ impl Display for Keyword {
 //
 // 3. Select * From
 // ---------------------------
-// SELECT * FROM table_name;
+// SELECT [ DISTINCT ] { * | expr [ AS alias ] [, ...] } FROM table_name
+//      [ AS OF VERSION n ] [ WHERE expr ]
+//      [ ORDER BY { expr [ ASC | DESC ] [, ...] | INSERTION [ ASC | DESC ] } ]
+//      [ LIMIT { count | ALL } ] [ OFFSET count ];
+// SELECT aggregate [ AS alias ] [, ...] FROM table_name [ WHERE expr ];
+//      where aggregate is COUNT(*) or { COUNT | SUM | MIN | MAX | AVG } ( expr )
+//
+// 4. Describe
+// ---------------------------
+// DESCRIBE table_name;
+//
+// 5. Explain
+// ---------------------------
+// EXPLAIN statement;
+//
+// 6. Delete
+// ---------------------------
+// DELETE FROM table_name [ WHERE expr ];
+//
+// 7. Transaction
+// ---------------------------
+// BEGIN; COMMIT; ROLLBACK;
+//
+// 8. Update
+// ---------------------------
+// UPDATE table_name SET column_name = expr [, ...] [ WHERE expr ];
+//
+// 9. Vacuum
+// ---------------------------
+// VACUUM;
+//
+//      where expr is a constant, a column name, or
+//      table_name.column_name
+//      expr { + | - | * | / } expr
+//      expr { = | > | < | >= | <= } expr
+//      expr { AND | OR } expr
+//      ( expr )
+//      { UPPER | LOWER } ( expr ), COALESCE ( expr [, ...] ), NULLIF ( expr, expr )
+//      EXISTS ( SELECT ... FROM table_name [ WHERE expr ] )
+
+// How unquoted identifiers are folded, keywords are matched regardless
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum IdentCase {
+    #[default]
+    Lower,
+    Preserve,
+    Upper,
+}
 
 pub struct Lexer<'a> {
     iter: Peekable<Chars<'a>>,
+    ident_case: IdentCase,
 }
 
 impl<'a> Iterator for Lexer<'a> {
@@ -180,8 +352,13 @@ impl<'a> Iterator for Lexer<'a> {
 
 impl<'a> Lexer<'a> {
     pub fn new(sql_text:&'a str) -> Self {
+        Self::with_ident_case(sql_text, IdentCase::default())
+    }
+
+    pub fn with_ident_case(sql_text:&'a str, ident_case: IdentCase) -> Self {
         Self {
             iter: sql_text.chars().peekable(),
+            ident_case,
         }
     }
 
@@ -263,6 +440,20 @@ impl<'a> Lexer<'a> {
             }
         }
 
+        // exponent part: e[+-]digits, only when digits follow
+        let mut ahead = self.iter.clone();
+        if let Some(e) = ahead.next_if(|c| *c == 'e' || *c == 'E') {
+            let sign = ahead.next_if(|c| *c == '+' || *c == '-');
+            if ahead.peek().is_some_and(|c| c.is_ascii_digit()) {
+                self.iter = ahead;
+                num.push(e);
+                num.extend(sign);
+                while let Some(c) = self.next_if(|c| c.is_ascii_digit()) {
+                    num.push(c);
+                }
+            }
+        }
+
         Some(Token::Number(num))
     }
 
@@ -272,11 +463,26 @@ impl<'a> Lexer<'a> {
             value.push(c);
         }
 
-        Some(Keyword::from_str(&value).map_or(Token::Ident(value.to_lowercase()), Token::Keyword))
+        if let Some(keyword) = Keyword::from_str(&value) {
+            return Some(Token::Keyword(keyword));
+        }
+        Some(Token::Ident(match self.ident_case {
+            IdentCase::Lower => value.to_lowercase(),
+            IdentCase::Preserve => value,
+            IdentCase::Upper => value.to_uppercase(),
+        }))
     }
 
     fn scan_symbol(&mut self) -> Option<Token> {
-        self.next_if_token(|c| match c {
+        // ! only appears in !=
+        if self.iter.peek() == Some(&'!') {
+            let mut ahead = self.iter.clone();
+            ahead.next();
+            ahead.next().filter(|&c| c == '=')?;
+            self.iter = ahead;
+            return Some(Token::NotEqual);
+        }
+        let token = self.next_if_token(|c| match c {
             '*' => Some(Token::Asterisk),
             '(' => Some(Token::OpenParen),
             ')' => Some(Token::CloseParen),
@@ -285,7 +491,18 @@ impl<'a> Lexer<'a> {
             '+' => Some(Token::Plus),
             '-' => Some(Token::Minus),
             '/' => Some(Token::Slash),
+            '=' => Some(Token::Equal),
+            '>' => Some(Token::GreaterThan),
+            '<' => Some(Token::LessThan),
+            '.' => Some(Token::Period),
             _ => None,
+        })?;
+        // >=, <= and <> are one token
+        Some(match token {
+            Token::GreaterThan if self.next_if(|c| c == '=').is_some() => Token::GreaterThanOrEqual,
+            Token::LessThan if self.next_if(|c| c == '=').is_some() => Token::LessThanOrEqual,
+            Token::LessThan if self.next_if(|c| c == '>').is_some() => Token::NotEqual,
+            token => token,
         })
     }
     
@@ -1,5 +1,5 @@
 use std::iter::Peekable;
-use ast::Column;
+use ast::{BinaryOp, Column};
 use lexer::{Keyword, Lexer, Token};
 use crate::error::{Error, Result};
 use super::types::DataTypes;
@@ -7,6 +7,11 @@ use super::types::DataTypes;
 mod lexer;
 pub mod ast;
 
+pub use lexer::IdentCase;
+
+// ORDER BY expr [ ASC | DESC ], ... or ORDER BY INSERTION [ ASC | DESC ]
+type OrderClause = (Vec<(ast::Expression, ast::OrderDirection)>, Option<ast::OrderDirection>);
+
 pub struct Parser<'a> {
     lexer:Peekable<Lexer<'a>>,
 }
@@ -18,21 +23,50 @@ impl<'a> Parser<'a> {
         }
     }
 
+    pub fn with_ident_case(input: &'a str, ident_case: IdentCase) -> Self {
+        Parser {
+            lexer: Lexer::with_ident_case(input, ident_case).peekable(),
+        }
+    }
+
     pub fn parse(&mut self) -> Result<ast::Statement>{
         let stmt = self.parse_statement()?;
-        self.next_expect(Token::Semicolon)?;
+        // The semicolon may be omitted for the last statement
+        self.next_if_token(Token::Semicolon);
         if let Some(token) = self.peek()? {
             return Err(Error::Parse(format!("[Parser] Unexpected token {}", token)));
         } 
         Ok(stmt)
     }
 
+    // Statements separated by semicolons, the last one may omit it
+    pub fn parse_script(&mut self) -> Result<Vec<ast::Statement>> {
+        let mut stmts = Vec::new();
+        while self.peek()?.is_some() {
+            stmts.push(self.parse_statement()?);
+            if self.next_if_token(Token::Semicolon).is_none() {
+                if let Some(token) = self.peek()? {
+                    return Err(Error::Parse(format!("[Parser] Unexpected token {}", token)));
+                }
+            }
+        }
+        Ok(stmts)
+    }
+
     fn parse_statement(&mut self) -> Result<ast::Statement> {
         // Check the first Token
         match self.peek()? {
-            Some(Token::Keyword(Keyword::Create)) => self.parse_ddl(),
+            Some(Token::Keyword(Keyword::Create | Keyword::Alter | Keyword::Drop)) => self.parse_ddl(),
             Some(Token::Keyword(Keyword::Select)) => self.parse_select(),
             Some(Token::Keyword(Keyword::Insert)) => self.parse_insert(),
+            Some(Token::Keyword(Keyword::Delete)) => self.parse_delete(),
+            Some(Token::Keyword(Keyword::Update)) => self.parse_update(),
+            Some(Token::Keyword(Keyword::Describe)) => self.parse_describe(),
+            Some(Token::Keyword(Keyword::Explain)) => self.parse_explain(),
+            Some(Token::Keyword(Keyword::Begin)) => self.next().map(|_| ast::Statement::Begin),
+            Some(Token::Keyword(Keyword::Commit)) => self.next().map(|_| ast::Statement::Commit),
+            Some(Token::Keyword(Keyword::Rollback)) => self.next().map(|_| ast::Statement::Rollback),
+            Some(Token::Keyword(Keyword::Vacuum)) => self.next().map(|_| ast::Statement::Vacuum),
             Some(t) => Err(Error::Parse(format!("[Parser] Unexpected {}", t))),
             None => Err(Error::Parse(format!("[Parser] Unexpected end of input"))),
         }
@@ -43,21 +77,176 @@ impl<'a> Parser<'a> {
     fn parse_ddl(&mut self) -> Result<ast::Statement> {
         match self.next()? {
             Token::Keyword(Keyword::Create) => match self.next()? {
-                Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(),
+                Token::Keyword(Keyword::Table) => self.parse_ddl_create_table(false),
+                Token::Keyword(Keyword::Or) => {
+                    self.next_expect(Token::Keyword(Keyword::Replace))?;
+                    self.next_expect(Token::Keyword(Keyword::Table))?;
+                    self.parse_ddl_create_table(true)
+                }
                 token => Err(Error::Parse(format!("[Parse] Unexcepted token {}", token))),
             },
+            Token::Keyword(Keyword::Alter) => self.parse_ddl_alter_table(),
+            // DROP TABLE [ IF EXISTS ] name
+            Token::Keyword(Keyword::Drop) => {
+                self.next_expect(Token::Keyword(Keyword::Table))?;
+                let if_exists = self.next_if_token(Token::Keyword(Keyword::If)).is_some();
+                if if_exists {
+                    self.next_expect(Token::Keyword(Keyword::Exists))?;
+                }
+                Ok(ast::Statement::DropTable { name: self.next_ident()?, if_exists })
+            }
             token => Err(Error::Parse(format!("[Parse] Unexcepted token {}", token))),
         }
     }
 
-    // Parser: SELECT * FROM TABLE
+    // Parser: SELECT { * | expr [AS alias] [, ...] } FROM TABLE [AS OF VERSION n] [WHERE expr]
     fn parse_select(&mut self) -> Result<ast::Statement> {
         self.next_expect(Token::Keyword(Keyword::Select))?;
-        self.next_expect(Token::Asterisk)?;
+        let distinct = self.next_if_token(Token::Keyword(Keyword::Distinct)).is_some();
+        let mut columns = Vec::new();
+        if self.next_if_token(Token::Asterisk).is_none() {
+            loop {
+                let expr = self.parse_expression()?;
+                let alias = match self.next_if_token(Token::Keyword(Keyword::As)) {
+                    Some(_) => Some(self.next_ident()?),
+                    None => None,
+                };
+                columns.push((expr, alias));
+                if self.next_if_token(Token::Comma).is_none() {
+                    break;
+                }
+            }
+        }
         self.next_expect(Token::Keyword(Keyword::From))?;
 
         let table_name = self.next_ident()?;
-        Ok(ast::Statement::Select { table_name: table_name })
+        let as_of = self.parse_as_of_clause()?;
+        let filter = self.parse_where_clause()?;
+        let (order_by, insertion_order) = self.parse_order_clause()?;
+        // LIMIT ALL is the same as no limit
+        let limit = match self.next_if_token(Token::Keyword(Keyword::Limit)) {
+            Some(_) if self.next_if_token(Token::Keyword(Keyword::All)).is_some() => None,
+            Some(_) => Some(self.parse_row_count("LIMIT")?),
+            None => None,
+        };
+        let offset = match self.next_if_token(Token::Keyword(Keyword::Offset)) {
+            Some(_) => Some(self.parse_row_count("OFFSET")?),
+            None => None,
+        };
+        Ok(ast::Statement::Select { distinct, columns, table_name, filter, as_of, order_by, insertion_order, limit, offset })
+    }
+
+    // LIMIT and OFFSET take a non-negative integer
+    fn parse_row_count(&mut self, clause: &str) -> Result<usize> {
+        match self.next()? {
+            Token::Number(n) if n.chars().all(|c| c.is_ascii_digit()) => Ok(n.parse()?),
+            token => Err(Error::Parse(format!("[Parser] {} must be a non-negative integer, got {}", clause, token))),
+        }
+    }
+
+    fn parse_order_clause(&mut self) -> Result<OrderClause> {
+        let mut order_by = Vec::new();
+        if self.next_if_token(Token::Keyword(Keyword::Order)).is_none() {
+            return Ok((order_by, None));
+        }
+        self.next_expect(Token::Keyword(Keyword::By))?;
+        if self.next_if_token(Token::Keyword(Keyword::Insertion)).is_some() {
+            return Ok((order_by, Some(self.parse_order_direction())));
+        }
+        loop {
+            let expr = self.parse_expression()?;
+            order_by.push((expr, self.parse_order_direction()));
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        Ok((order_by, None))
+    }
+
+    fn parse_order_direction(&mut self) -> ast::OrderDirection {
+        match self.next_if(|t| matches!(t, Token::Keyword(Keyword::Asc | Keyword::Desc))) {
+            Some(Token::Keyword(Keyword::Desc)) => ast::OrderDirection::Desc,
+            _ => ast::OrderDirection::Asc,
+        }
+    }
+
+    // ALTER TABLE t ALTER COLUMN c { SET DEFAULT expr | DROP DEFAULT }
+    fn parse_ddl_alter_table(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Table))?;
+        let table_name = self.next_ident()?;
+        self.next_expect(Token::Keyword(Keyword::Alter))?;
+        self.next_expect(Token::Keyword(Keyword::Column))?;
+        let column_name = self.next_ident()?;
+        let default = match self.next()? {
+            Token::Keyword(Keyword::Set) => {
+                self.next_expect(Token::Keyword(Keyword::Default))?;
+                Some(self.parse_expression()?)
+            }
+            Token::Keyword(Keyword::Drop) => {
+                self.next_expect(Token::Keyword(Keyword::Default))?;
+                None
+            }
+            token => return Err(Error::Parse(format!("[Parser] Expected SET or DROP, got {}", token))),
+        };
+        Ok(ast::Statement::AlterColumnDefault { table_name, column_name, default })
+    }
+
+    // Parser: DESCRIBE table_name
+    fn parse_describe(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Describe))?;
+        Ok(ast::Statement::Describe { table_name: self.next_ident()? })
+    }
+
+    // Parser: EXPLAIN statement
+    fn parse_explain(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Explain))?;
+        Ok(ast::Statement::Explain { statement: Box::new(self.parse_statement()?) })
+    }
+
+    fn parse_as_of_clause(&mut self) -> Result<Option<u64>> {
+        if self.next_if_token(Token::Keyword(Keyword::As)).is_none() {
+            return Ok(None);
+        }
+        self.next_expect(Token::Keyword(Keyword::Of))?;
+        self.next_expect(Token::Keyword(Keyword::Version))?;
+        match self.next()? {
+            Token::Number(n) => Ok(Some(n.parse()?)),
+            token => Err(Error::Parse(format!("[Parser] Unexpected token {}, expected version number", token))),
+        }
+    }
+
+    fn parse_where_clause(&mut self) -> Result<Option<ast::Expression>> {
+        if self.next_if_token(Token::Keyword(Keyword::Where)).is_none() {
+            return Ok(None);
+        }
+        Ok(Some(self.parse_expression()?))
+    }
+
+    // Parser: DELETE FROM table_name [WHERE expr]
+    fn parse_delete(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Delete))?;
+        self.next_expect(Token::Keyword(Keyword::From))?;
+        let table_name = self.next_ident()?;
+        let filter = self.parse_where_clause()?;
+        Ok(ast::Statement::Delete { table_name, filter })
+    }
+
+    // Parser: UPDATE table_name SET column = expr [, ...] [WHERE expr]
+    fn parse_update(&mut self) -> Result<ast::Statement> {
+        self.next_expect(Token::Keyword(Keyword::Update))?;
+        let table_name = self.next_ident()?;
+        self.next_expect(Token::Keyword(Keyword::Set))?;
+        let mut columns = Vec::new();
+        loop {
+            let column = self.next_ident()?;
+            self.next_expect(Token::Equal)?;
+            columns.push((column, self.parse_expression()?));
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        let filter = self.parse_where_clause()?;
+        Ok(ast::Statement::Update { table_name, columns, filter })
     }
 
     // Parser: INSERT value INTO TABLE
@@ -75,6 +264,8 @@ impl<'a> Parser<'a> {
                 col.push(self.next_ident()?.to_string());
                 match self.next()? {
                     Token::CloseParen => break,
+                    // Trailing comma is allowed: (a, b,)
+                    Token::Comma if self.next_if_token(Token::CloseParen).is_some() => break,
                     Token::Comma => {},
                     t => return Err(Error::Parse(format!("[Parser] Unexcepted token {}", t))),
                 }
@@ -93,6 +284,7 @@ impl<'a> Parser<'a> {
                 exprs.push(self.parse_expression()?);
                 match self.next()? {
                     Token::CloseParen => break,
+                    Token::Comma if self.next_if_token(Token::CloseParen).is_some() => break,
                     Token::Comma => {},
                     t => return Err(Error::Parse(format!("[Parser] Unexcepted token {}", t))),
                 }
@@ -107,23 +299,61 @@ impl<'a> Parser<'a> {
 
 
     // Parser: CREATE TABLE
-    fn parse_ddl_create_table(&mut self) -> Result<ast::Statement> {
+    fn parse_ddl_create_table(&mut self, or_replace: bool) -> Result<ast::Statement> {
         // Tablename
         let table_name = self.next_ident()?;
+
+        // CREATE TABLE tbl AS SELECT ...
+        if self.next_if_token(Token::Keyword(Keyword::As)).is_some() {
+            if or_replace {
+                return Err(Error::Parse("[Parser] OR REPLACE is not supported with AS".into()));
+            }
+            let query = self.parse_statement()?;
+            return Ok(ast::Statement::CreateTableAs { name: table_name, query: Box::new(query) });
+        }
+
         // Openparen
         self.next_expect(Token::OpenParen)?;
 
-        // Column
+        // Column or table constraint
         let mut columns = Vec::new();
+        let mut unique = Vec::new();
         loop {
-            columns.push(self.parse_ddl_column()?);
+            if self.next_if_token(Token::Keyword(Keyword::Unique)).is_some() {
+                unique.push(self.parse_column_list()?);
+            } else {
+                columns.push(self.parse_ddl_column()?);
+            }
             if self.next_if_token(Token::Comma).is_none() {
                 break;
             }
+            // Trailing comma before the close paren
+            if self.peek()? == Some(Token::CloseParen) {
+                break;
+            }
         }
 
         self.next_expect(Token::CloseParen)?;
-        Ok(ast::Statement::CreateTable { name: table_name, columns: columns })
+        let insertion_order = self.next_if_token(Token::Keyword(Keyword::With)).is_some();
+        if insertion_order {
+            self.next_expect(Token::Keyword(Keyword::Insertion))?;
+            self.next_expect(Token::Keyword(Keyword::Order))?;
+        }
+        Ok(ast::Statement::CreateTable { name: table_name, columns, unique, or_replace, insertion_order })
+    }
+
+    // ( column_name [, ...] )
+    fn parse_column_list(&mut self) -> Result<Vec<String>> {
+        self.next_expect(Token::OpenParen)?;
+        let mut names = Vec::new();
+        loop {
+            names.push(self.next_ident()?);
+            if self.next_if_token(Token::Comma).is_none() {
+                break;
+            }
+        }
+        self.next_expect(Token::CloseParen)?;
+        Ok(names)
     }
 
     // Column value
@@ -140,21 +370,33 @@ impl<'a> Parser<'a> {
             nullable: None,
             default: None,
             primary_key: false,
+            unique: false,
+            autoincrement: false,
         };
 
-        // Nullable or Default
+        // Constraints in any order, contradicting ones are rejected
         while let Some(Token::Keyword(keyword)) = self.next_if_keyword() {
             match keyword {
-                Keyword::Null => column.nullable = Some(true),
-                Keyword::Not => {
-                    self.next_expect(Token::Keyword(Keyword::Null))?;
-                    column.nullable = Some(false);
+                Keyword::Null | Keyword::Not => {
+                    let nullable = keyword == Keyword::Null;
+                    if !nullable {
+                        self.next_expect(Token::Keyword(Keyword::Null))?;
+                    }
+                    if column.nullable.is_some_and(|n| n != nullable) {
+                        return Err(Error::Parse(format!("[Parser] Column {} cannot be both NULL and NOT NULL", column.name)));
+                    }
+                    column.nullable = Some(nullable);
                 },
+                Keyword::Default if column.default.is_some() => {
+                    return Err(Error::Parse(format!("[Parser] Column {} has more than one DEFAULT", column.name)));
+                }
                 Keyword::Default => column.default = Some(self.parse_expression()?),
                 Keyword::Primary => {
                     self.next_expect(Token::Keyword(Keyword::Key))?;
                     column.primary_key = true;
                 }
+                Keyword::Unique => column.unique = true,
+                Keyword::Autoincrement => column.autoincrement = true,
                 k => return Err(Error::Parse(format!("[Parser] Unexcepted keyword {}", k))),
             }
         }
@@ -163,15 +405,118 @@ impl<'a> Parser<'a> {
     }
 
 
+    // conjunction [ OR conjunction ... ]
     fn parse_expression(&mut self) -> Result<ast::Expression> {
+        let mut left = self.parse_conjunction()?;
+        while self.next_if_token(Token::Keyword(Keyword::Or)).is_some() {
+            let right = self.parse_conjunction()?;
+            left = ast::Operation::Or(Box::new(left), Box::new(right)).into();
+        }
+        Ok(left)
+    }
+
+    // comparison [ AND comparison ... ]
+    fn parse_conjunction(&mut self) -> Result<ast::Expression> {
+        let mut left = self.parse_comparison()?;
+        while self.next_if_token(Token::Keyword(Keyword::And)).is_some() {
+            let right = self.parse_comparison()?;
+            left = ast::Operation::And(Box::new(left), Box::new(right)).into();
+        }
+        Ok(left)
+    }
+
+    // sum [ { = | != | <> | > | < | >= | <= } sum ]
+    fn parse_comparison(&mut self) -> Result<ast::Expression> {
+        let left = self.parse_sum()?;
+        let op: BinaryOp = match self.peek()? {
+            Some(Token::Equal) => ast::Operation::Equal,
+            Some(Token::NotEqual) => ast::Operation::NotEqual,
+            Some(Token::GreaterThan) => ast::Operation::GreaterThan,
+            Some(Token::LessThan) => ast::Operation::LessThan,
+            Some(Token::GreaterThanOrEqual) => ast::Operation::GreaterThanOrEqual,
+            Some(Token::LessThanOrEqual) => ast::Operation::LessThanOrEqual,
+            _ => return Ok(left),
+        };
+        self.next()?;
+        let right = self.parse_sum()?;
+        Ok(op(Box::new(left), Box::new(right)).into())
+    }
+
+    // product [ { + | - } product ... ]
+    fn parse_sum(&mut self) -> Result<ast::Expression> {
+        let mut left = self.parse_product()?;
+        loop {
+            let op: BinaryOp = match self.peek()? {
+                Some(Token::Plus) => ast::Operation::Add,
+                Some(Token::Minus) => ast::Operation::Subtract,
+                _ => return Ok(left),
+            };
+            self.next()?;
+            let right = self.parse_product()?;
+            left = op(Box::new(left), Box::new(right)).into();
+        }
+    }
+
+    // atom [ { * | / } atom ... ]
+    fn parse_product(&mut self) -> Result<ast::Expression> {
+        let mut left = self.parse_expression_atom()?;
+        loop {
+            let op: BinaryOp = match self.peek()? {
+                Some(Token::Asterisk) => ast::Operation::Multiply,
+                Some(Token::Slash) => ast::Operation::Divide,
+                _ => return Ok(left),
+            };
+            self.next()?;
+            let right = self.parse_expression_atom()?;
+            left = op(Box::new(left), Box::new(right)).into();
+        }
+    }
+
+    fn parse_expression_atom(&mut self) -> Result<ast::Expression> {
         Ok(match self.next()? {
-            Token::Number(n) => {
-                if n.chars().all(|c|c.is_ascii_digit()) {
-                    ast::Consts::Integer(n.parse()?).into()
-                } else {
-                    ast::Consts::Float(n.parse()?).into()
+            // Function call: name(arg [, ...]), name(*).
+            // Function names are not identifiers, they match in any case
+            Token::Ident(name) if self.next_if_token(Token::OpenParen).is_some() => {
+                let mut args = Vec::new();
+                if self.next_if_token(Token::Asterisk).is_none() {
+                    loop {
+                        args.push(self.parse_expression()?);
+                        if self.next_if_token(Token::Comma).is_none() {
+                            break;
+                        }
+                    }
                 }
+                self.next_expect(Token::CloseParen)?;
+                ast::Expression::Function(name.to_lowercase(), args)
+            }
+            // Qualified column: table_name.column_name
+            Token::Ident(name) if self.next_if_token(Token::Period).is_some() =>
+                ast::Expression::Field(format!("{}.{}", name, self.next_ident()?)),
+            Token::Ident(name) => ast::Expression::Field(name),
+            // Parenthesized expression: (a + b) * c
+            Token::OpenParen => {
+                let expr = self.parse_expression()?;
+                self.next_expect(Token::CloseParen)?;
+                expr
             }
+            Token::Keyword(Keyword::Exists) => {
+                self.next_expect(Token::OpenParen)?;
+                let query = self.parse_select()?;
+                self.next_expect(Token::CloseParen)?;
+                match query {
+                    // Only the presence of rows matters, the select list is ignored
+                    ast::Statement::Select { table_name, filter, as_of: None, limit: None, offset: None, .. } =>
+                        ast::Expression::Exists { table_name, filter: filter.map(Box::new) },
+                    _ => return Err(Error::Parse("[Parser] EXISTS does not support AS OF, LIMIT or OFFSET".into())),
+                }
+            }
+            Token::Keyword(Keyword::CurrentTimestamp) => ast::Expression::Function(ast::CURRENT_TIMESTAMP.into(), vec![]),
+            Token::Number(n) => Self::parse_number(&n)?,
+            // Negative number: -1, -2.5
+            Token::Minus => match self.next()? {
+                Token::Number(n) => Self::parse_number(&format!("-{}", n))?,
+                t => return Err(Error::Parse(format!("[Parser] Unexpected token {} after -", t))),
+            },
             Token::String(c) => ast::Consts::String(c).into(),
             Token::Keyword(Keyword::True) => ast::Consts::Boolean(true).into(),
             Token::Keyword(Keyword::False) => ast::Consts::Boolean(false).into(),
@@ -180,6 +525,19 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_number(n: &str) -> Result<ast::Expression> {
+        if n.trim_start_matches('-').chars().all(|c|c.is_ascii_digit()) {
+            Ok(ast::Consts::Integer(n.parse()?).into())
+        } else {
+            // 1e999 parses to infinity
+            let f: f64 = n.parse()?;
+            if !f.is_finite() {
+                return Err(Error::Parse("float literal out of range".into()));
+            }
+            Ok(ast::Consts::Float(f).into())
+        }
+    }
+
     // -+------------------------+-
     //      Auxilliary Part
     // -+------------------------+- 
@@ -224,9 +582,9 @@ impl<'a> Parser<'a> {
 #[cfg(test)]
 
 mod tests {
-    use crate::error::Result;
+    use crate::error::{Error, Result};
 
-    use super::Parser;
+    use super::{ast, Parser};
 
     #[test]
     fn test_parser_create_table() -> Result<()> {
@@ -253,6 +611,129 @@ mod tests {
         let stmt2 = Parser::new(sql2).parse()?;
         assert_eq!(stmt1,stmt2);
 
+        // Constraint order does not matter
+        let column = |sql: &str| Parser::new(&format!("CREATE TABLE t ({});", sql)).parse();
+        let expected = column("a int primary key not null default 5 unique")?;
+        for sql in ["a int default 5 not null primary key unique", "a int unique not null primary key default 5",
+            "a int not null default 5 unique primary key", "a int primary key default 5 not null not null unique"] {
+            assert_eq!(column(sql)?, expected, "{}", sql);
+        }
+        assert_eq!(column("a int default 5 null")?, column("a int null default 5")?);
+
+        // Contradictory or repeated constraints
+        match column("a int null default 1 not null") {
+            Err(Error::Parse(msg)) => assert_eq!(msg, "[Parser] Column a cannot be both NULL and NOT NULL"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(column("a int not null null").is_err());
+        assert!(column("a int default 1 not null default 2").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_create_or_replace() -> Result<()> {
+        let stmt = Parser::new("CREATE OR REPLACE TABLE tbl (a int primary key);").parse()?;
+        assert!(matches!(stmt, ast::Statement::CreateTable { or_replace: true, .. }));
+
+        let stmt = Parser::new("CREATE TABLE tbl (a int primary key);").parse()?;
+        assert!(matches!(stmt, ast::Statement::CreateTable { or_replace: false, .. }));
+
+        assert!(Parser::new("CREATE OR TABLE tbl (a int primary key);").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_create_table_as() -> Result<()> {
+        let stmt = Parser::new("CREATE TABLE t2 AS SELECT * FROM t1;").parse()?;
+        assert_eq!(stmt, ast::Statement::CreateTableAs {
+            name: "t2".to_string(),
+            query: Box::new(ast::Statement::Select { distinct: false, columns: vec![], table_name: "t1".to_string(), filter: None, as_of: None, order_by: vec![], insertion_order: None, limit: None, offset: None }),
+        });
+
+        assert!(Parser::new("CREATE TABLE t2 AS t1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_exists() -> Result<()> {
+        let stmt = Parser::new("SELECT * FROM t WHERE EXISTS (SELECT * FROM other WHERE other.x = t.y);").parse()?;
+        match stmt {
+            ast::Statement::Select { filter: Some(filter), .. } => {
+                assert_eq!(filter, ast::Expression::Exists {
+                    table_name: "other".into(),
+                    filter: Some(Box::new(ast::Operation::Equal(
+                        Box::new(ast::Expression::Field("other.x".into())),
+                        Box::new(ast::Expression::Field("t.y".into())),
+                    ).into())),
+                });
+                assert_eq!(filter.to_string(), "EXISTS (SELECT * FROM other WHERE (other.x = t.y))");
+            }
+            s => panic!("unexpected statement {:?}", s),
+        }
+
+        let stmt = Parser::new("SELECT a FROM t WHERE EXISTS (SELECT b FROM other) = false;").parse()?;
+        assert!(matches!(stmt, ast::Statement::Select { filter: Some(ast::Expression::Operation(_)), .. }));
+        assert!(Parser::new("SELECT * FROM t WHERE EXISTS (SELECT * FROM other LIMIT 1);").parse().is_err());
+        assert!(Parser::new("SELECT * FROM t WHERE EXISTS SELECT * FROM other;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM t WHERE t. = 1;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_alter_default() -> Result<()> {
+        let stmt = Parser::new("ALTER TABLE t ALTER COLUMN c SET DEFAULT 'x';").parse()?;
+        assert_eq!(stmt, ast::Statement::AlterColumnDefault {
+            table_name: "t".into(),
+            column_name: "c".into(),
+            default: Some(ast::Consts::String("x".into()).into()),
+        });
+        let stmt = Parser::new("alter table t alter column c drop default;").parse()?;
+        assert_eq!(stmt, ast::Statement::AlterColumnDefault { table_name: "t".into(), column_name: "c".into(), default: None });
+        assert!(stmt.is_ddl());
+
+        assert!(Parser::new("ALTER TABLE t ALTER COLUMN c SET 'x';").parse().is_err());
+        assert!(Parser::new("ALTER TABLE t ALTER c DROP DEFAULT;").parse().is_err());
+        assert!(Parser::new("ALTER TABLE t ALTER COLUMN c RESET DEFAULT;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_unique() -> Result<()> {
+        let stmt = Parser::new("create table tbl (a int primary key, b int unique, c int, UNIQUE (b, c), d int);").parse()?;
+        match stmt {
+            ast::Statement::CreateTable { columns, unique, .. } => {
+                assert_eq!(columns.iter().map(|c| c.name.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c", "d"]);
+                assert!(columns[1].unique && !columns[2].unique);
+                assert_eq!(unique, vec![vec!["b".to_string(), "c".to_string()]]);
+            }
+            s => panic!("unexpected statement {:?}", s),
+        }
+
+        assert!(Parser::new("create table tbl (a int primary key, UNIQUE ());").parse().is_err());
+        assert!(Parser::new("create table tbl (a int primary key, UNIQUE a);").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_negative_number() -> Result<()> {
+        let stmt = Parser::new("create table tbl (a int default -1, b float default -2.5);").parse()?;
+        match stmt {
+            ast::Statement::CreateTable { columns, .. } => {
+                assert_eq!(columns[0].default, Some(ast::Consts::Integer(-1).into()));
+                assert_eq!(columns[1].default, Some(ast::Consts::Float(-2.5).into()));
+            }
+            s => panic!("unexpected statement {:?}", s),
+        }
+
+        let stmt = Parser::new("insert into tbl values (-9223372036854775808);").parse()?;
+        assert_eq!(stmt, ast::Statement::Insert {
+            table_name: "tbl".into(),
+            columns: None,
+            values: vec![vec![ast::Consts::Integer(i64::MIN).into()]],
+        });
+
+        assert!(Parser::new("insert into tbl values (-'a');").parse().is_err());
         Ok(())
     }
 
@@ -261,6 +742,317 @@ mod tests {
         let sql1 = "SELECT * FROM tbl;";
         let stmt1 = Parser::new(sql1).parse()?;
         println!("{:?}", stmt1);
+
+        let stmt2 = Parser::new("SELECT * FROM tbl WHERE false;").parse()?;
+        assert_eq!(stmt2, ast::Statement::Select {
+            distinct: false,
+            columns: vec![],
+            table_name: "tbl".to_string(),
+            filter: Some(ast::Consts::Boolean(false).into()),
+            as_of: None,
+            order_by: vec![],
+            insertion_order: None,
+            limit: None,
+            offset: None,
+        });
+
+        let stmt3 = Parser::new("SELECT * FROM tbl AS OF VERSION 5 WHERE true;").parse()?;
+        assert_eq!(stmt3, ast::Statement::Select {
+            distinct: false,
+            columns: vec![],
+            table_name: "tbl".to_string(),
+            filter: Some(ast::Consts::Boolean(true).into()),
+            as_of: Some(5),
+            order_by: vec![],
+            insertion_order: None,
+            limit: None,
+            offset: None,
+        });
+
+        assert!(Parser::new("SELECT * FROM tbl AS OF VERSION;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl AS OF VERSION -1;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl AS OF VERSION 1.5;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_count_where() -> Result<()> {
+        let stmt = Parser::new("SELECT COUNT(*) FROM tbl WHERE a > 3;").parse()?;
+        assert_eq!(stmt, ast::Statement::Select {
+            distinct: false,
+            columns: vec![(ast::Expression::Function("count".into(), vec![]), None)],
+            table_name: "tbl".to_string(),
+            filter: Some(ast::Operation::GreaterThan(
+                Box::new(ast::Expression::Field("a".into())),
+                Box::new(ast::Consts::Integer(3).into()),
+            ).into()),
+            as_of: None,
+            order_by: vec![],
+            insertion_order: None,
+            limit: None,
+            offset: None,
+        });
+
+        let stmt = Parser::new("SELECT * FROM tbl WHERE 'x' = b;").parse()?;
+        assert!(matches!(stmt, ast::Statement::Select { filter: Some(ast::Expression::Operation(ast::Operation::Equal(..))), .. }));
+        for sql in ["SELECT * FROM tbl WHERE b != 'x';", "SELECT * FROM tbl WHERE b<>'x';"] {
+            let stmt = Parser::new(sql).parse()?;
+            assert!(matches!(stmt, ast::Statement::Select { filter: Some(ast::Expression::Operation(ast::Operation::NotEqual(..))), .. }));
+        }
+
+        assert!(Parser::new("SELECT COUNT(* FROM tbl;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl WHERE a ! 1;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl WHERE a = ;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_float_exponent() -> Result<()> {
+        let value = |sql: &str| match Parser::new(sql).parse() {
+            Ok(ast::Statement::Insert { values, .. }) => Ok(values[0][0].clone()),
+            Ok(s) => panic!("unexpected statement {:?}", s),
+            Err(err) => Err(err),
+        };
+        assert_eq!(value("INSERT INTO t VALUES (1e3);")?, ast::Consts::Float(1000.0).into());
+        assert_eq!(value("INSERT INTO t VALUES (-2.5E-2);")?, ast::Consts::Float(-0.025).into());
+        assert_eq!(value("INSERT INTO t VALUES (1e-999);")?, ast::Consts::Float(0.0).into());
+
+        for sql in ["INSERT INTO t VALUES (1e999);", "INSERT INTO t VALUES (-1.5e400);"] {
+            assert_eq!(value(sql), Err(Error::Parse("float literal out of range".into())));
+        }
+        // Without digits the e is not part of the number
+        assert!(value("INSERT INTO t VALUES (1e);").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_script() -> Result<()> {
+        let stmts = Parser::new("SELECT * FROM a; ; SELECT * FROM b").parse_script();
+        assert!(stmts.is_err());
+        let stmts = Parser::new("SELECT * FROM a; DESCRIBE b;").parse_script()?;
+        assert_eq!(stmts, vec![
+            Parser::new("SELECT * FROM a").parse()?,
+            ast::Statement::Describe { table_name: "b".into() },
+        ]);
+        assert_eq!(Parser::new("").parse_script()?, vec![]);
+        assert!(Parser::new("SELECT * FROM a SELECT * FROM b;").parse_script().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_paren() -> Result<()> {
+        let stmt = Parser::new("SELECT * FROM t WHERE (a - (b - 1)) * 2 = ((c));").parse()?;
+        let field = |n: &str| Box::new(ast::Expression::Field(n.into()));
+        let int = |i: i64| Box::new(ast::Consts::Integer(i).into());
+        assert!(matches!(stmt, ast::Statement::Select { filter: Some(filter), .. } if filter == ast::Operation::Equal(
+            Box::new(ast::Operation::Multiply(
+                Box::new(ast::Operation::Subtract(field("a"), Box::new(ast::Operation::Subtract(field("b"), int(1)).into())).into()),
+                int(2),
+            ).into()),
+            field("c"),
+        ).into()));
+        assert!(Parser::new("SELECT * FROM t WHERE (a = 1;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM t WHERE ();").parse().is_err());
+        Ok(())
+    }
+
+    // Every kind of statement parses back from its SQL
+    #[test]
+    fn test_to_sql_round_trip() -> Result<()> {
+        let statements = [
+            "CREATE TABLE t (a INT PRIMARY KEY AUTOINCREMENT, b VARCHAR NOT NULL DEFAULT 'x' UNIQUE, c float null, d bool default current_timestamp, e text, unique (b, c))",
+            "CREATE OR REPLACE TABLE t (a int primary key, b int default -3)",
+            "CREATE TABLE log (id int primary key, msg text) with insertion order",
+            "CREATE TABLE t2 AS SELECT * FROM t WHERE a > 1",
+            "DROP TABLE t",
+            "DROP TABLE IF EXISTS t",
+            "INSERT INTO t VALUES (1, 'a', 1.5, TRUE, NULL), (2, 'b', -0.25, false, null)",
+            "INSERT INTO t (b, a) VALUES (upper('x'), 1 + 2 * 3)",
+            "DELETE FROM t",
+            "DELETE FROM t WHERE a = 1 OR b <= 'x' AND NOT_A_KEYWORD >= 2.0",
+            "UPDATE t SET a = (a - 1) * 2, b = coalesce(b, 'y') WHERE a >= 1e20",
+            "SELECT * FROM t",
+            "SELECT DISTINCT a, t.b AS bb, count(*) FROM t AS OF VERSION 3 WHERE exists (select a from t2 where a = t.a) ORDER BY a DESC, bb LIMIT 5 OFFSET 2",
+            "SELECT a FROM t OFFSET 1",
+            "SELECT * FROM log WHERE id > 1 ORDER BY insertion DESC LIMIT 3",
+            "SELECT nullif(a, 0) AS n FROM t WHERE a - (b - c) < 0 LIMIT 0",
+            "SELECT a FROM t WHERE a != 1 AND b <> 'x'",
+            "DESCRIBE t",
+            "BEGIN",
+            "COMMIT",
+            "ROLLBACK",
+            "VACUUM",
+            "EXPLAIN SELECT a FROM t WHERE a = 1",
+            "ALTER TABLE t ALTER COLUMN b SET DEFAULT 'z'",
+            "ALTER TABLE t ALTER COLUMN b DROP DEFAULT",
+        ];
+        for sql in statements {
+            let stmt = Parser::new(sql).parse()?;
+            let canonical = stmt.to_sql();
+            assert_eq!(Parser::new(&canonical).parse()?, stmt, "{} => {}", sql, canonical);
+            assert_eq!(Parser::new(&canonical).parse()?.to_sql(), canonical);
+        }
+        assert_eq!(Parser::new("insert into t values (1,'a')").parse()?.to_sql(), "INSERT INTO t VALUES (1, 'a')");
+        assert_eq!(Parser::new("select a from t where a=1 and b>2").parse()?.to_sql(),
+            "SELECT a FROM t WHERE ((a = 1) AND (b > 2))");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_update() -> Result<()> {
+        let stmt = Parser::new("UPDATE tbl SET a = a + 1, b = 'x' WHERE c = 1;").parse()?;
+        assert_eq!(stmt, ast::Statement::Update {
+            table_name: "tbl".into(),
+            columns: vec![
+                ("a".into(), ast::Operation::Add(
+                    Box::new(ast::Expression::Field("a".into())),
+                    Box::new(ast::Consts::Integer(1).into()),
+                ).into()),
+                ("b".into(), ast::Consts::String("x".into()).into()),
+            ],
+            filter: Some(ast::Operation::Equal(
+                Box::new(ast::Expression::Field("c".into())),
+                Box::new(ast::Consts::Integer(1).into()),
+            ).into()),
+        });
+        assert!(matches!(Parser::new("update tbl set a = 1").parse()?, ast::Statement::Update { filter: None, .. }));
+        assert!(Parser::new("UPDATE tbl WHERE a = 1;").parse().is_err());
+        assert!(Parser::new("UPDATE tbl SET a;").parse().is_err());
+        assert!(Parser::new("UPDATE tbl SET a = 1,;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_delete() -> Result<()> {
+        let stmt = Parser::new("DELETE FROM tbl WHERE a = 1;").parse()?;
+        assert_eq!(stmt, ast::Statement::Delete {
+            table_name: "tbl".into(),
+            filter: Some(ast::Operation::Equal(
+                Box::new(ast::Expression::Field("a".into())),
+                Box::new(ast::Consts::Integer(1).into()),
+            ).into()),
+        });
+        assert_eq!(Parser::new("delete from tbl").parse()?, ast::Statement::Delete { table_name: "tbl".into(), filter: None });
+        assert!(Parser::new("DELETE tbl;").parse().is_err());
+        assert!(Parser::new("DELETE FROM tbl WHERE;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_transaction() -> Result<()> {
+        assert_eq!(Parser::new("BEGIN;").parse()?, ast::Statement::Begin);
+        assert_eq!(Parser::new("commit").parse()?, ast::Statement::Commit);
+        assert_eq!(Parser::new("Rollback;").parse()?, ast::Statement::Rollback);
+        assert!(Parser::new("COMMIT t;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_arithmetic() -> Result<()> {
+        let field = |n: &str| Box::new(ast::Expression::Field(n.into()));
+        let int = |i: i64| Box::new(ast::Expression::from(ast::Consts::Integer(i)));
+
+        // a + b * 2 - 1 > 3  =>  ((a + (b * 2)) - 1) > 3
+        let stmt = Parser::new("SELECT a + b * 2 - 1 > 3, a / -2 FROM tbl;").parse()?;
+        match stmt {
+            ast::Statement::Select { columns, .. } => assert_eq!(columns.into_iter().map(|(e, _)| e).collect::<Vec<_>>(), vec![
+                ast::Operation::GreaterThan(
+                    Box::new(ast::Operation::Subtract(
+                        Box::new(ast::Operation::Add(field("a"), Box::new(ast::Operation::Multiply(field("b"), int(2)).into())).into()),
+                        int(1),
+                    ).into()),
+                    int(3),
+                ).into(),
+                ast::Operation::Divide(field("a"), int(-2)).into(),
+            ]),
+            s => panic!("unexpected statement {:?}", s),
+        }
+
+        assert!(Parser::new("SELECT a + FROM tbl;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_select_alias() -> Result<()> {
+        let stmt = Parser::new("SELECT a AS id, b + 1, c AS val FROM tbl AS OF VERSION 2;").parse()?;
+        match stmt {
+            ast::Statement::Select { columns, as_of, .. } => {
+                assert_eq!(columns.into_iter().map(|(_, alias)| alias).collect::<Vec<_>>(),
+                    vec![Some("id".to_string()), None, Some("val".to_string())]);
+                assert_eq!(as_of, Some(2));
+            }
+            s => panic!("unexpected statement {:?}", s),
+        }
+        assert!(Parser::new("SELECT a AS FROM tbl;").parse().is_err());
+        assert!(Parser::new("SELECT a AS 1 FROM tbl;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_drop_table() -> Result<()> {
+        assert_eq!(Parser::new("DROP TABLE t;").parse()?, ast::Statement::DropTable { name: "t".into(), if_exists: false });
+        assert_eq!(Parser::new("drop table if exists t").parse()?, ast::Statement::DropTable { name: "t".into(), if_exists: true });
+        assert!(Parser::new("DROP TABLE IF t;").parse().is_err());
+        assert!(Parser::new("DROP t;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_explain() -> Result<()> {
+        let stmt = Parser::new("EXPLAIN SELECT * FROM tbl;").parse()?;
+        assert_eq!(stmt, ast::Statement::Explain { statement: Box::new(Parser::new("SELECT * FROM tbl;").parse()?) });
+        assert!(Parser::new("EXPLAIN;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_order_limit() -> Result<()> {
+        let stmt = Parser::new("SELECT * FROM tbl ORDER BY a, b DESC, c ASC LIMIT 3 OFFSET 1;").parse()?;
+        match stmt {
+            ast::Statement::Select { order_by, limit, offset, .. } => {
+                assert_eq!(order_by, vec![
+                    (ast::Expression::Field("a".into()), ast::OrderDirection::Asc),
+                    (ast::Expression::Field("b".into()), ast::OrderDirection::Desc),
+                    (ast::Expression::Field("c".into()), ast::OrderDirection::Asc),
+                ]);
+                assert_eq!(limit, Some(3));
+                assert_eq!(offset, Some(1));
+            }
+            s => panic!("unexpected statement {:?}", s),
+        }
+
+        assert!(Parser::new("SELECT * FROM tbl ORDER a;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl ORDER BY;").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl LIMIT;").parse().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_limit_guards() -> Result<()> {
+        match Parser::new("SELECT * FROM tbl LIMIT ALL OFFSET 2;").parse()? {
+            ast::Statement::Select { limit, offset, .. } => {
+                assert_eq!(limit, None);
+                assert_eq!(offset, Some(2));
+            }
+            s => panic!("unexpected statement {:?}", s),
+        }
+
+        for sql in ["SELECT * FROM tbl LIMIT -1;", "SELECT * FROM tbl LIMIT 1.5;", "SELECT * FROM tbl OFFSET -1;",
+                    "SELECT * FROM tbl LIMIT 'a';", "SELECT * FROM tbl OFFSET ALL;"] {
+            assert!(matches!(Parser::new(sql).parse(), Err(Error::Parse(_))), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parser_without_semicolon() -> Result<()> {
+        let stmt1 = Parser::new("SELECT * FROM tbl;").parse()?;
+        let stmt2 = Parser::new("SELECT * FROM tbl").parse()?;
+        assert_eq!(stmt1, stmt2);
+
+        // More than one statement still needs the separator
+        assert!(Parser::new("SELECT * FROM tbl SELECT * FROM tbl").parse().is_err());
+        assert!(Parser::new("SELECT * FROM tbl; SELECT * FROM tbl;").parse().is_err());
         Ok(())
     }
 
@@ -276,5 +1068,20 @@ mod tests {
 
         Ok(())
     }
-}
 
+    #[test]
+    fn test_parser_trailing_comma() -> Result<()> {
+        let stmt1 = Parser::new("insert into tbl (a, b) values (1, 2), (3, 4);").parse()?;
+        let stmt2 = Parser::new("insert into tbl (a, b,) values (1, 2,), (3, 4,);").parse()?;
+        assert_eq!(stmt1, stmt2);
+
+        let stmt1 = Parser::new("create table tbl (a int primary key, b float);").parse()?;
+        let stmt2 = Parser::new("create table tbl (a int primary key, b float,);").parse()?;
+        assert_eq!(stmt1, stmt2);
+
+        // A single comma is not an empty list
+        assert!(Parser::new("insert into tbl values (,);").parse().is_err());
+        assert!(Parser::new("insert into tbl values (1,,);").parse().is_err());
+        Ok(())
+    }
+}
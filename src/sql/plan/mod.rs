@@ -1,9 +1,12 @@
+use std::fmt::Display;
+
 use planner::Planner;
-use crate::error::Result;
+use crate::error::{Error, Result};
 use super::engine::Transaction;
 use super::executor::{Executor, ResultSet};
 use super::schema::Table;
-use super::parser::ast::{Expression, Statement};
+use super::parser::ast::{Expression, Operation, OrderDirection, Statement};
+use super::types::{DataTypes, Value};
 mod planner;
 
 #[derive(Debug, PartialEq)]
@@ -11,6 +14,28 @@ pub enum Node {
     //CREATE
     CreateTable {
         schema: Table,
+        or_replace: bool,
+    },
+    // DROP TABLE, IF EXISTS makes a missing table a no-op
+    DropTable {
+        table_name: String,
+        if_exists: bool,
+    },
+    // CREATE TABLE AS SELECT
+    CreateTableAs {
+        table_name: String,
+        source_table: String,
+        source: Box<Node>,
+    },
+    // ALTER TABLE ... ALTER COLUMN ... SET/DROP DEFAULT
+    AlterColumnDefault {
+        table_name: String,
+        column_name: String,
+        default: Option<Expression>,
+    },
+    // DESCRIBE
+    Describe {
+        table_name: String,
     },
     // INSERT
     Insert {
@@ -18,29 +43,299 @@ pub enum Node {
         columns: Vec<String>,
         values: Vec<Vec<Expression>>,
     },
+    // DELETE the rows produced by the source
+    Delete {
+        table_name: String,
+        source: Box<Node>,
+    },
+    // UPDATE the rows produced by the source, each column
+    // is set to its expression evaluated on the stored row
+    Update {
+        table_name: String,
+        source: Box<Node>,
+        columns: Vec<(String, Expression)>,
+    },
     // SELECT/Scan
     Scan {
         table_name: String,
+    },
+    // ORDER BY INSERTION: the rows in the order they were inserted
+    InsertionScan {
+        table_name: String,
+        direction: OrderDirection,
+    },
+    // WHERE: keep the rows matching the predicate
+    Filter {
+        source: Box<Node>,
+        predicate: Expression,
+    },
+    // Always empty result, nothing is read
+    Nothing {
+        table_name: String,
+    },
+    // SELECT expr [AS alias], ...
+    Projection {
+        source: Box<Node>,
+        expressions: Vec<(Expression, Option<String>)>,
+    },
+    // SELECT DISTINCT
+    Distinct {
+        source: Box<Node>,
+    },
+    // ORDER BY
+    Order {
+        source: Box<Node>,
+        order_by: Vec<(Expression, OrderDirection)>,
+    },
+    // LIMIT
+    Limit {
+        source: Box<Node>,
+        limit: usize,
+    },
+    // OFFSET
+    Offset {
+        source: Box<Node>,
+        offset: usize,
+    },
+    // ORDER BY with LIMIT, only keeps limit + offset rows while sorting
+    TopN {
+        source: Box<Node>,
+        order_by: Vec<(Expression, OrderDirection)>,
+        limit: usize,
+        offset: usize,
+    },
+    // COUNT(*) of the rows matching the predicate
+    Count {
+        table_name: String,
+        predicate: Option<Expression>,
+        alias: Option<String>,
+    },
+    // Aggregate functions without grouping, one row over all source rows
+    Aggregate {
+        source: Box<Node>,
+        aggregates: Vec<(Expression, Option<String>)>,
+    },
+    // AS OF VERSION: the source runs in a transaction pinned at version
+    AsOf {
+        version: u64,
+        source: Box<Node>,
+    },
+    // EXPLAIN: only the plan of the source is shown
+    Explain {
+        source: Box<Node>,
+    },
+}
+
+// One line per node, the source indented below its parent
+// Projection a
+//   Filter (b = 1)
+//     Scan tbl
+impl Display for Node {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut node = Some(self);
+        let mut depth = 0;
+        while let Some(n) = node {
+            let (line, source) = n.explain_line();
+            if depth > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}{}", "  ".repeat(depth), line)?;
+            node = source;
+            depth += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn explain_line(&self) -> (String, Option<&Node>) {
+        let order = |order_by: &Vec<(Expression, OrderDirection)>| order_by.iter()
+            .map(|(c, d)| format!("{} {}", c, if *d == OrderDirection::Desc { "DESC" } else { "ASC" }))
+            .collect::<Vec<_>>().join(", ");
+        match self {
+            Node::CreateTable { schema, .. } => (format!("CreateTable {}", schema.name), None),
+            Node::DropTable { table_name, .. } => (format!("DropTable {}", table_name), None),
+            Node::CreateTableAs { table_name, source, .. } => (format!("CreateTableAs {}", table_name), Some(source)),
+            Node::AlterColumnDefault { table_name, column_name, .. } =>
+                (format!("AlterColumnDefault {}.{}", table_name, column_name), None),
+            Node::Describe { table_name } => (format!("Describe {}", table_name), None),
+            Node::Insert { table_name, values, .. } => (format!("Insert {} ({} rows)", table_name, values.len()), None),
+            Node::Delete { table_name, source } => (format!("Delete {}", table_name), Some(source)),
+            Node::Update { table_name, source, columns } => (format!("Update {} SET {}", table_name, columns.iter()
+                .map(|(c, e)| format!("{} = {}", c, e)).collect::<Vec<_>>().join(", ")), Some(source)),
+            Node::Scan { table_name } => (format!("Scan {}", table_name), None),
+            Node::InsertionScan { table_name, direction } =>
+                (format!("InsertionScan {} {}", table_name, if *direction == OrderDirection::Desc { "DESC" } else { "ASC" }), None),
+            Node::Filter { source, predicate } => (format!("Filter {}", predicate), Some(source)),
+            Node::Nothing { table_name } => (format!("Nothing {}", table_name), None),
+            Node::Projection { source, expressions } => (format!("Projection {}", expressions.iter().map(|(e, alias)| match alias {
+                Some(alias) => format!("{} AS {}", e, alias),
+                None => e.to_string(),
+            }).collect::<Vec<_>>().join(", ")), Some(source)),
+            Node::Distinct { source } => ("Distinct".to_string(), Some(source)),
+            Node::Order { source, order_by } => (format!("Order {}", order(order_by)), Some(source)),
+            Node::Limit { source, limit } => (format!("Limit {}", limit), Some(source)),
+            Node::Offset { source, offset } => (format!("Offset {}", offset), Some(source)),
+            Node::TopN { source, order_by, limit, offset } =>
+                (format!("TopN {} LIMIT {} OFFSET {}", order(order_by), limit, offset), Some(source)),
+            Node::Count { table_name, predicate: None, .. } => (format!("Count {}", table_name), None),
+            Node::Count { table_name, predicate: Some(predicate), .. } =>
+                (format!("Count {} WHERE {}", table_name, predicate), None),
+            Node::Aggregate { source, aggregates } => (format!("Aggregate {}", aggregates.iter().map(|(e, alias)| match alias {
+                Some(alias) => format!("{} AS {}", e, alias),
+                None => e.to_string(),
+            }).collect::<Vec<_>>().join(", ")), Some(source)),
+            Node::AsOf { version, source } => (format!("AsOf {}", version), Some(source)),
+            Node::Explain { source } => ("Explain".to_string(), Some(source)),
+        }
     }
 }
 
+// The node tree and the WHERE clauses of the statement as written,
+// folding may drop parts of them that still have to be valid
 #[derive(Debug, PartialEq)]
-pub struct Plan(pub Node);
+pub struct Plan(pub Node, Vec<(String, Expression)>);
 
 impl Plan {
-    pub fn build(stmt: Statement) -> Self {
-        Planner::new().build(stmt)
+    pub fn build(stmt: Statement) -> Result<Self> {
+        check_qualifiers(&stmt)?;
+        let predicates = where_clauses(&stmt);
+        Ok(Plan(Planner::new().build(stmt)?, predicates))
     }
 
-    pub fn execute<T: Transaction>(self, txn:&mut T) -> Result<ResultSet> {
+    // The node tree as shown by EXPLAIN
+    pub fn to_string_tree(&self) -> String {
+        self.0.to_string()
+    }
+
+    // The past version the plan reads at, if any
+    pub fn as_of(&self) -> Option<u64> {
+        match &self.0 {
+            Node::AsOf { version, .. } => Some(*version),
+            _ => None,
+        }
+    }
+
+    pub fn execute<T: Transaction + 'static>(self, txn:&mut T) -> Result<ResultSet> {
+        self.check_predicates(txn)?;
         <dyn Executor<T>>::build(self.0).execute(txn)
     }
+
+    // Every column in WHERE must exist and columns compared with each other
+    // must have comparable types, checked against the schema before any row is read
+    fn check_predicates<T: Transaction>(&self, txn: &T) -> Result<()> {
+        for (table_name, predicate) in &self.1 {
+            let table = txn.must_get_table(table_name.clone())?;
+            check_fields(&table, predicate)?;
+            check_comparisons(&table, predicate)?;
+        }
+        Ok(())
+    }
+}
+
+// The WHERE clauses that run, the statement under EXPLAIN does not
+fn where_clauses(stmt: &Statement) -> Vec<(String, Expression)> {
+    match stmt {
+        Statement::Select { table_name, filter: Some(filter), .. }
+        | Statement::Delete { table_name, filter: Some(filter) }
+        | Statement::Update { table_name, filter: Some(filter), .. } => vec![(table_name.clone(), filter.clone())],
+        Statement::CreateTableAs { query, .. } => where_clauses(query),
+        _ => Vec::new(),
+    }
+}
+
+// A qualified column names the table of the statement, or inside a
+// subquery the subquery's table or one of the tables around it
+fn check_qualifiers(stmt: &Statement) -> Result<()> {
+    let (table_name, exprs): (_, Vec<&Expression>) = match stmt {
+        Statement::Select { table_name, columns, filter, order_by, .. } => (table_name,
+            columns.iter().map(|(e, _)| e).chain(filter).chain(order_by.iter().map(|(e, _)| e)).collect()),
+        Statement::Update { table_name, columns, filter } => (table_name, columns.iter().map(|(_, e)| e).chain(filter).collect()),
+        Statement::Delete { table_name, filter } => (table_name, filter.iter().collect()),
+        Statement::CreateTableAs { query: statement, .. } | Statement::Explain { statement } => return check_qualifiers(statement),
+        _ => return Ok(()),
+    };
+    exprs.into_iter().try_for_each(|expr| check_qualifier(&[table_name.as_str()], expr))
+}
+
+fn check_qualifier(tables: &[&str], expr: &Expression) -> Result<()> {
+    match expr {
+        Expression::Field(name) => match name.rsplit_once('.') {
+            Some((table, _)) if !tables.contains(&table) => Err(Error::Internal(format!("Column {} not found", name))),
+            _ => Ok(()),
+        },
+        Expression::Operation(op) => {
+            let (l, r) = op.operands();
+            check_qualifier(tables, l)?;
+            check_qualifier(tables, r)
+        }
+        Expression::Function(_, args) => args.iter().try_for_each(|arg| check_qualifier(tables, arg)),
+        Expression::Exists { table_name, filter } => match filter {
+            Some(filter) => check_qualifier(&[tables, &[table_name.as_str()]].concat(), filter),
+            None => Ok(()),
+        },
+        Expression::Consts(_) => Ok(()),
+    }
+}
+
+// The columns of a subquery are checked when it runs
+fn check_fields(table: &Table, expr: &Expression) -> Result<()> {
+    match expr {
+        Expression::Field(name) if table.column(name).or_else(|| table.column(name.rsplit_once('.')?.1)).is_none() =>
+            Err(Error::Internal(format!("Column {} not found", name))),
+        Expression::Operation(op) => {
+            let (l, r) = op.operands();
+            check_fields(table, l)?;
+            check_fields(table, r)
+        }
+        Expression::Function(_, args) => args.iter().try_for_each(|arg| check_fields(table, arg)),
+        Expression::Field(_) | Expression::Consts(_) | Expression::Exists { .. } => Ok(()),
+    }
+}
+
+// Integers and floats compare as numbers, other types only with themselves,
+// for a column on both sides or a column and a constant
+fn check_comparisons(table: &Table, expr: &Expression) -> Result<()> {
+    let Expression::Operation(op) = expr else {
+        return Ok(());
+    };
+    let (l, r, compare) = match op {
+        Operation::Equal(l, r) | Operation::NotEqual(l, r) | Operation::GreaterThan(l, r) | Operation::LessThan(l, r)
+        | Operation::GreaterThanOrEqual(l, r) | Operation::LessThanOrEqual(l, r) => (l, r, true),
+        Operation::Add(l, r) | Operation::Subtract(l, r) | Operation::Multiply(l, r) | Operation::Divide(l, r)
+        | Operation::And(l, r) | Operation::Or(l, r) => (l, r, false),
+    };
+    let column = |name: &str| table.column(name).or_else(|| table.column(name.rsplit_once('.')?.1));
+    let numeric = |t: &DataTypes| matches!(t, DataTypes::Integer | DataTypes::Float);
+    let comparable = |a: &DataTypes, b: &DataTypes| a == b || (numeric(a) && numeric(b));
+    match (compare, l.as_ref(), r.as_ref()) {
+        (true, Expression::Field(a), Expression::Field(b)) => {
+            if let (Some(a), Some(b)) = (column(a), column(b)) {
+                if !comparable(&a.datatype, &b.datatype) {
+                    return Err(Error::Internal(format!("Cannot compare column {} ({:?}) with column {} ({:?})",
+                        a.name, a.datatype, b.name, b.datatype)));
+                }
+            }
+        }
+        (true, Expression::Field(name), c @ Expression::Consts(_)) | (true, c @ Expression::Consts(_), Expression::Field(name)) => {
+            let datatype = Value::from_expression_to_value(c.clone())?.datatype();
+            if let (Some(a), Some(datatype)) = (column(name), datatype) {
+                if !comparable(&a.datatype, &datatype) {
+                    return Err(Error::Internal(format!("Cannot compare column {} ({:?}) with {}", a.name, a.datatype, c)));
+                }
+            }
+        }
+        _ => {}
+    }
+    check_comparisons(table, l)?;
+    check_comparisons(table, r)
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{sql::parser::Parser, error::Result};
-    use super::Plan;
+    use crate::{sql::parser::Parser, error::{Error, Result}};
+    use super::{Node, OrderDirection, Plan};
+    use crate::sql::parser::ast::{Consts, Expression, Operation};
     
     #[test]
     fn test_plan_create_table() -> Result<()> {
@@ -54,7 +349,7 @@ mod tests {
         ";
 
         let stmt1 = Parser::new(sql1).parse()?;
-        let plan1 = Plan::build(stmt1);
+        let plan1 = Plan::build(stmt1)?;
         println!("{:?}", plan1);
 
         let sql2 = "
@@ -66,7 +361,7 @@ mod tests {
             );
         "; 
         let stmt2 = Parser::new(sql2).parse()?;
-        let plan2 = Plan::build(stmt2);
+        let plan2 = Plan::build(stmt2)?;
         assert_eq!(plan1,plan2);
 
 
@@ -77,7 +372,7 @@ mod tests {
     fn test_plan_insert_table() -> Result<()> {
         let sql1 = "insert into tbl values (1,3,'a', true);";
         let stmt1 = Parser::new(sql1).parse()?;
-        let plan1 = Plan::build(stmt1);        
+        let plan1 = Plan::build(stmt1)?;        
         println!("{:?}", plan1);
         Ok(())
     } 
@@ -86,8 +381,126 @@ mod tests {
     fn test_plan_select_table() -> Result<()> {
         let sql1 = "SELECT * FROM tbl;";
         let stmt1 = Parser::new(sql1).parse()?;
-        let plan1 = Plan::build(stmt1);
+        let plan1 = Plan::build(stmt1)?;
         println!("{:?}", plan1);
         Ok(())
     }
+
+    #[test]
+    fn test_plan_constant_filter() -> Result<()> {
+        let scan = Node::Scan { table_name: "tbl".into() };
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl WHERE true;").parse()?)?;
+        assert_eq!(plan.0, scan);
+
+        let nothing = Node::Nothing { table_name: "tbl".into() };
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl WHERE false;").parse()?)?;
+        assert_eq!(plan.0, nothing);
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl WHERE null;").parse()?)?;
+        assert_eq!(plan.0, Node::Nothing { table_name: "tbl".into() });
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_as_of() -> Result<()> {
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl AS OF VERSION 3;").parse()?)?;
+        assert_eq!(plan.as_of(), Some(3));
+        assert_eq!(plan.0, Node::AsOf { version: 3, source: Box::new(Node::Scan { table_name: "tbl".into() }) });
+
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl;").parse()?)?;
+        assert_eq!(plan.as_of(), None);
+
+        let stmt = Parser::new("CREATE TABLE t2 AS SELECT * FROM t1 AS OF VERSION 3;").parse()?;
+        assert!(matches!(Plan::build(stmt), Err(Error::Unsupported(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_filter_below_projection() -> Result<()> {
+        let plan = Plan::build(Parser::new("SELECT a FROM tbl WHERE b = 1;").parse()?)?;
+        assert_eq!(plan.0, Node::Projection {
+            source: Box::new(Node::Filter {
+                source: Box::new(Node::Scan { table_name: "tbl".into() }),
+                predicate: Operation::Equal(
+                    Box::new(Expression::Field("b".into())),
+                    Box::new(Consts::Integer(1).into()),
+                ).into(),
+            }),
+            expressions: vec![(Expression::Field("a".into()), None)],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_order_limit() -> Result<()> {
+        let scan = || Box::new(Node::Scan { table_name: "tbl".into() });
+        let order_by = vec![(Expression::Field("a".into()), OrderDirection::Desc)];
+
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl ORDER BY a DESC LIMIT 3 OFFSET 2;").parse()?)?;
+        assert_eq!(plan.0, Node::TopN { source: scan(), order_by: order_by.clone(), limit: 3, offset: 2 });
+
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl ORDER BY a DESC OFFSET 2;").parse()?)?;
+        assert_eq!(plan.0, Node::Offset {
+            source: Box::new(Node::Order { source: scan(), order_by }),
+            offset: 2,
+        });
+
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl LIMIT 3;").parse()?)?;
+        assert_eq!(plan.0, Node::Limit { source: scan(), limit: 3 });
+
+        // Sorted below the projection, unless DISTINCT needs the output rows
+        let plan = Plan::build(Parser::new("SELECT b FROM tbl ORDER BY a DESC LIMIT 3;").parse()?)?;
+        assert_eq!(plan.to_string_tree(), "Projection b\n  TopN a DESC LIMIT 3 OFFSET 0\n    Scan tbl");
+        let plan = Plan::build(Parser::new("SELECT DISTINCT b FROM tbl ORDER BY b LIMIT 3;").parse()?)?;
+        assert_eq!(plan.to_string_tree(), "TopN b ASC LIMIT 3 OFFSET 0\n  Distinct\n    Projection b\n      Scan tbl");
+
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl ORDER BY a DESC LIMIT ALL;").parse()?)?;
+        assert_eq!(plan.0, Node::Order { source: scan(), order_by: vec![(Expression::Field("a".into()), OrderDirection::Desc)] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_unsupported() -> Result<()> {
+        let stmt = Parser::new("CREATE TABLE t2 AS INSERT INTO t1 VALUES (1);").parse()?;
+        match Plan::build(stmt) {
+            Err(Error::Unsupported(msg)) => assert!(msg.contains("CREATE TABLE AS")),
+            r => panic!("unexpected result {:?}", r),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_display() -> Result<()> {
+        let plan = Plan::build(Parser::new("SELECT a, b + 1 AS c FROM tbl WHERE a = 1 ORDER BY c DESC;").parse()?)?;
+        assert_eq!(plan.to_string_tree(), "Projection a, (b + 1) AS c\n  Order (b + 1) DESC\n    Filter (a = 1)\n      Scan tbl");
+        assert_eq!(plan.0.to_string(), plan.to_string_tree());
+
+        let plan = Plan::build(Parser::new("SELECT * FROM tbl;").parse()?)?;
+        assert_eq!(plan.to_string_tree(), "Scan tbl");
+        let plan = Plan::build(Parser::new("SELECT a AS id FROM tbl WHERE b = 1 LIMIT 2;").parse()?)?;
+        assert_eq!(plan.to_string_tree(), "Limit 2\n  Projection a AS id\n    Filter (b = 1)\n      Scan tbl");
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan_constant_folding() -> Result<()> {
+        let plan = |sql: &str| -> Result<String> { Ok(Plan::build(Parser::new(sql).parse()?)?.to_string_tree()) };
+        assert_eq!(plan("SELECT * FROM t WHERE a = 1 + 1;")?, "Filter (a = 2)\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE 2 > 1 AND b = 3;")?, "Filter (b = 3)\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 OR 1 * 2 > 3;")?, "Filter (b = 3)\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 OR 2 > 1;")?, "Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 AND 1 + 1 = 3;")?, "Nothing t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 AND a > 1 AND 4 = t.b;")?, "Nothing t");
+        assert_eq!(plan("SELECT * FROM t WHERE (b = 3 OR a = 1) AND b = 2 + 2 AND b = 3;")?, "Nothing t");
+        // Equal values, an OR or NULL decide nothing here
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 AND b = 3.0;")?, "Filter ((b = 3) AND (b = 3.0))\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 OR b = 4;")?, "Filter ((b = 3) OR (b = 4))\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 AND b = NULL;")?, "Filter ((b = 3) AND (b = NULL))\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE zz = 1 AND zz = 2;")?, "Nothing t");
+        assert_eq!(plan("DELETE FROM t WHERE a > 10 - 8 * 1;")?, "Delete t\n  Filter (a > 2)\n    Scan t");
+        // Only constants are folded, NULL and failing ones are kept
+        assert_eq!(plan("SELECT * FROM t WHERE a + 1 = 2;")?, "Filter ((a + 1) = 2)\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 3 AND 1 > NULL;")?, "Filter ((b = 3) AND NULL)\n  Scan t");
+        assert_eq!(plan("SELECT * FROM t WHERE b = 1 / 0;")?, "Filter (b = (1 / 0))\n  Scan t");
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -1,5 +1,7 @@
-use crate::sql::{parser::ast, schema::{self, Table}, types::Value};
-use super::{Node, Plan};
+use std::cmp::Ordering;
+
+use crate::{error::{Error, Result}, sql::{eval::{evaluate, ColumnMap}, parser::ast::{self, BinaryOp, Consts, Expression, Operation, OrderDirection}, schema::{self, Table}, types::Value}};
+use super::Node;
 pub struct Planner;
 
 impl Planner {
@@ -7,36 +9,242 @@ impl Planner {
         Self {}
     }
 
-    pub fn build(&mut self, stmt: ast::Statement) -> Plan {
-        Plan(self.build_statement(stmt))
+    pub fn build(&mut self, stmt: ast::Statement) -> Result<Node> {
+        self.build_statement(stmt)
     }
 
-    fn build_statement(&self, stmt:ast::Statement) -> Node {
-        match stmt {
-            ast::Statement::CreateTable { name, columns } => Node::CreateTable { 
+    fn build_statement(&self, stmt:ast::Statement) -> Result<Node> {
+        Ok(match stmt {
+            ast::Statement::CreateTable { name, columns, unique, or_replace, insertion_order } => Node::CreateTable { 
+                or_replace,
                 schema: Table {
-                    name,
-                    columns: columns.into_iter().map(|c|{
-                        let nullable = c.nullable.unwrap_or(true);
-                        let default = match c.default {
-                            Some(expr) => Some(Value::from_expression_to_value(expr)),
-                            None if nullable => Some(Value::Null),
-                            None => None, 
-                        };
-
-                        schema::Column {
+                    // Column level UNIQUE is a set of one
+                    unique: columns.iter().filter(|c| c.unique).map(|c| vec![c.name.clone()]).chain(unique).collect(),
+                    insertion_order,
+                    ..Table::new(name, columns.into_iter().map(|c|{
+                        // primary key is not nullable unless stated
+                        let mut column = schema::Column {
                             name: c.name,
                             datatype: c.datatype,
-                            nullable,
-                            default,
+                            nullable: c.nullable.unwrap_or(!c.primary_key),
+                            default: None,
+                            default_expr: None,
                             primary_key: c.primary_key,
-                        }
-                    }).collect(),
+                            autoincrement: c.autoincrement,
+                        };
+                        column.set_default(c.default)?;
+                        Ok(column)
+                    }).collect::<Result<_>>()?)
                 }
              },
+             ast::Statement::CreateTableAs { name, query } => {
+                let source_table = match query.as_ref() {
+                    ast::Statement::Select { as_of: Some(_), .. } => return Err(Error::Unsupported("AS OF VERSION in CREATE TABLE AS".into())),
+                    ast::Statement::Select { columns, table_name, .. } if columns.is_empty() => table_name.clone(),
+                    ast::Statement::Select { .. } => return Err(Error::Unsupported("CREATE TABLE AS only supports SELECT *".into())),
+                    _ => return Err(Error::Unsupported("CREATE TABLE AS only supports SELECT".into())),
+                };
+                Node::CreateTableAs { table_name: name, source_table, source: Box::new(self.build_statement(*query)?) }
+             },
+             ast::Statement::DropTable { name, if_exists } => Node::DropTable { table_name: name, if_exists },
+             ast::Statement::AlterColumnDefault { table_name, column_name, default } =>
+                Node::AlterColumnDefault { table_name, column_name, default },
+             ast::Statement::Describe { table_name } => Node::Describe { table_name },
+             ast::Statement::Begin | ast::Statement::Commit | ast::Statement::Rollback | ast::Statement::Vacuum =>
+                return Err(Error::Internal(format!("{:?} is not planned, the session runs it", stmt))),
+             ast::Statement::Explain { statement } => Node::Explain { source: Box::new(self.build_statement(*statement)?) },
              ast::Statement::Insert { table_name, columns, values } => 
              Node::Insert { table_name, columns:columns.unwrap_or_default(), values},
-             ast::Statement::Select { table_name } => Node::Scan { table_name },
+             // The rows to delete are found the same way as for SELECT
+             ast::Statement::Delete { table_name, filter } =>
+                Node::Delete { source: Box::new(Self::build_scan(table_name.clone(), filter)), table_name },
+             // The source gives whole rows, WHERE may use any column
+             ast::Statement::Update { table_name, columns, filter } =>
+                Node::Update { source: Box::new(Self::build_scan(table_name.clone(), filter)), table_name, columns },
+             ast::Statement::Select { distinct, columns, table_name, filter, as_of, mut order_by, insertion_order, limit, offset } => {
+                // ORDER BY INSERTION is the order the rows are read in
+                let build_scan = |table_name: String, filter| match insertion_order {
+                    Some(direction) => Self::build_filter(Node::InsertionScan { table_name: table_name.clone(), direction }, table_name, filter),
+                    None => Self::build_scan(table_name, filter),
+                };
+                let offset = offset.unwrap_or(0);
+                // Without DISTINCT or aggregates the rows are sorted before the
+                // projection, so ORDER BY may use columns that are not selected
+                let sort_below = !distinct && !order_by.is_empty() && !columns.is_empty() && !columns.iter().any(|(c, _)| is_aggregate(c));
+                let mut node = match columns.as_slice() {
+                    [] => build_scan(table_name, filter),
+                    // COUNT(*) without grouping, rows are only counted
+                    [(Expression::Function(f, args), alias)] if f == "count" && args.is_empty() => match filter {
+                        Some(f) if f.has_subquery() => return Err(Error::Unsupported("COUNT(*) with a subquery in WHERE".into())),
+                        filter => Node::Count { table_name, predicate: filter.map(fold_predicate), alias: alias.clone() },
+                    },
+                    columns if columns.iter().all(|(c, _)| is_aggregate(c)) =>
+                        Node::Aggregate { source: Box::new(build_scan(table_name, filter)), aggregates: columns.to_vec() },
+                    columns if columns.iter().any(|(c, _)| is_aggregate(c)) =>
+                        return Err(Error::Unsupported("aggregate functions cannot be mixed with other columns".into())),
+                    _ if sort_below => {
+                        let order_by = unalias(&columns, std::mem::take(&mut order_by));
+                        let source = Self::build_order(build_scan(table_name, filter), order_by, limit, offset);
+                        Node::Projection { source: Box::new(source), expressions: columns }
+                    }
+                    // Filter goes below the projection, WHERE sees all columns
+                    _ => Node::Projection { source: Box::new(build_scan(table_name, filter)), expressions: columns },
+                };
+                if distinct {
+                    node = Node::Distinct { source: Box::new(node) };
+                }
+                if !sort_below {
+                    node = Self::build_order(node, order_by, limit, offset);
+                }
+                match as_of {
+                    Some(version) => Node::AsOf { version, source: Box::new(node) },
+                    None => node,
+                }
+             },
+        })
+    }
+
+    fn build_order(mut node: Node, order_by: Vec<(Expression, OrderDirection)>, limit: Option<usize>, offset: usize) -> Node {
+        match (order_by.is_empty(), limit) {
+            // Only the first limit + offset rows are kept while sorting
+            (false, Some(limit)) => Node::TopN { source: Box::new(node), order_by, limit, offset },
+            (order_empty, limit) => {
+                if !order_empty {
+                    node = Node::Order { source: Box::new(node), order_by };
+                }
+                if offset > 0 {
+                    node = Node::Offset { source: Box::new(node), offset };
+                }
+                if let Some(limit) = limit {
+                    node = Node::Limit { source: Box::new(node), limit };
+                }
+                node
+            }
+        }
+    }
+
+    fn build_scan(table_name: String, filter: Option<Expression>) -> Node {
+        Self::build_filter(Node::Scan { table_name: table_name.clone() }, table_name, filter)
+    }
+
+    fn build_filter(scan: Node, table_name: String, filter: Option<Expression>) -> Node {
+        match filter.map(fold_predicate) {
+            // Constant predicates are decided here, without a filter
+            None | Some(Expression::Consts(Consts::Boolean(true))) => scan,
+            Some(Expression::Consts(Consts::Boolean(false) | Consts::Null)) => Node::Nothing { table_name },
+            Some(predicate) => Node::Filter { source: Box::new(scan), predicate },
         }
     }
-}
\ No newline at end of file
+}
+
+// ORDER BY an output alias sorts by the expression it names
+fn unalias(columns: &[(Expression, Option<String>)], order_by: Vec<(Expression, OrderDirection)>) -> Vec<(Expression, OrderDirection)> {
+    order_by.into_iter().map(|(expr, direction)| match &expr {
+        Expression::Field(name) => match columns.iter().find(|(_, alias)| alias.as_ref() == Some(name)) {
+            Some((aliased, _)) => (aliased.clone(), direction),
+            None => (expr, direction),
+        },
+        _ => (expr, direction),
+    }).collect()
+}
+
+// A predicate folded to its constants, false when it is known to fail
+fn fold_predicate(expr: Expression) -> Expression {
+    let expr = fold_constants(expr);
+    if contradictory(&expr) {
+        return Consts::Boolean(false).into();
+    }
+    expr
+}
+
+// Two equalities of one column with different constants in the same
+// conjunction, a = 1 AND a = 2 holds for no row
+fn contradictory(expr: &Expression) -> bool {
+    fn conjuncts<'a>(expr: &'a Expression, out: &mut Vec<&'a Expression>) {
+        match expr {
+            Expression::Operation(Operation::And(l, r)) => {
+                conjuncts(l, out);
+                conjuncts(r, out);
+            }
+            expr => out.push(expr),
+        }
+    }
+    let mut exprs = Vec::new();
+    conjuncts(expr, &mut exprs);
+
+    let mut equal: Vec<(&str, Value)> = Vec::new();
+    for expr in exprs {
+        let Expression::Operation(Operation::Equal(l, r)) = expr else { continue };
+        let ((Expression::Field(name), Expression::Consts(c)) | (Expression::Consts(c), Expression::Field(name))) = (l.as_ref(), r.as_ref()) else {
+            continue;
+        };
+        let Ok(value) = Value::from_expression_to_value(Expression::Consts(c.clone())) else { continue };
+        let name = name.rsplit_once('.').map_or(name.as_str(), |(_, column)| column);
+        // Only values that compare as different, NULL and mixed types are left to run
+        if equal.iter().any(|(n, v)| *n == name && matches!(v.partial_cmp(&value), Some(Ordering::Less | Ordering::Greater))) {
+            return true;
+        }
+        equal.push((name, value));
+    }
+    false
+}
+
+// Fold the operations over constants into one constant, once here instead
+// of for every row. 1 + 1 => 2, true AND x => x, x OR false => x.
+// One that fails, such as 1 / 0, is kept to fail when it runs
+fn fold_constants(expr: Expression) -> Expression {
+    let Expression::Operation(op) = expr else {
+        return expr;
+    };
+    let fold = |l: Box<Expression>, r: Box<Expression>| (fold_constants(*l), fold_constants(*r));
+    let (l, r, build): (_, _, BinaryOp) = match op {
+        // A decided side wins, the neutral one drops out
+        Operation::And(l, r) => return match fold(l, r) {
+            (Expression::Consts(Consts::Boolean(false)), _) | (_, Expression::Consts(Consts::Boolean(false))) => Consts::Boolean(false).into(),
+            (Expression::Consts(Consts::Boolean(true)), e) | (e, Expression::Consts(Consts::Boolean(true))) => e,
+            (l, r) => fold_operation(l, r, Operation::And),
+        },
+        Operation::Or(l, r) => return match fold(l, r) {
+            (Expression::Consts(Consts::Boolean(true)), _) | (_, Expression::Consts(Consts::Boolean(true))) => Consts::Boolean(true).into(),
+            (Expression::Consts(Consts::Boolean(false)), e) | (e, Expression::Consts(Consts::Boolean(false))) => e,
+            (l, r) => fold_operation(l, r, Operation::Or),
+        },
+        Operation::Equal(l, r) => (l, r, Operation::Equal),
+        Operation::NotEqual(l, r) => (l, r, Operation::NotEqual),
+        Operation::GreaterThan(l, r) => (l, r, Operation::GreaterThan),
+        Operation::LessThan(l, r) => (l, r, Operation::LessThan),
+        Operation::GreaterThanOrEqual(l, r) => (l, r, Operation::GreaterThanOrEqual),
+        Operation::LessThanOrEqual(l, r) => (l, r, Operation::LessThanOrEqual),
+        Operation::Add(l, r) => (l, r, Operation::Add),
+        Operation::Subtract(l, r) => (l, r, Operation::Subtract),
+        Operation::Multiply(l, r) => (l, r, Operation::Multiply),
+        Operation::Divide(l, r) => (l, r, Operation::Divide),
+    };
+    let (l, r) = fold(l, r);
+    fold_operation(l, r, build)
+}
+
+// The operation over its folded operands, evaluated if both are constants
+fn fold_operation(l: Expression, r: Expression, build: BinaryOp) -> Expression {
+    let consts = matches!((&l, &r), (Expression::Consts(_), Expression::Consts(_)));
+    let expr = Expression::Operation(build(Box::new(l), Box::new(r)));
+    if !consts {
+        return expr;
+    }
+    match evaluate(&expr, &Vec::new(), &ColumnMap::empty()) {
+        Ok(value) => value.into(),
+        Err(_) => expr,
+    }
+}
+
+// COUNT(*), COUNT(expr), SUM, MIN, MAX and AVG of one expression
+fn is_aggregate(expr: &Expression) -> bool {
+    match expr {
+        Expression::Function(name, args) => match args.len() {
+            0 => name == "count",
+            1 => matches!(name.as_str(), "count" | "sum" | "min" | "max" | "avg"),
+            _ => false,
+        },
+        _ => false,
+    }
+}
@@ -1,14 +1,39 @@
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
 use crate::error::{Error, Result};
-use super::types::{DataTypes, Row, Value};
+use super::{parser::ast::{Expression, CURRENT_TIMESTAMP}, types::{codec::{codec_for_tag, decode_row, encode_row, BINCODE_TAG}, DataTypes, HashKey, Row, Value}};
 
-#[derive(Debug,PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Table {
     pub name: String,
     pub columns: Vec<Column>,
+    // UNIQUE column sets, rows may only share one if all its columns are NULL
+    pub unique: Vec<Vec<String>>,
+    // Column defaults by position, empty when the table is read. INSERT
+    // fills every column once per statement, computed ones and NULL
+    // included. None where the column has no default and is required
+    #[serde(skip)]
+    pub defaults: Vec<Option<Value>>,
+    // Bumped each time the table is created or altered, filled when
+    // the table is read. Plans cached for an older version are stale
+    #[serde(skip)]
+    pub schema_version: u64,
+    // WITH INSERTION ORDER, each row also gets the next insertion sequence
+    pub insertion_order: bool,
+    // Tag of the codec new rows are written with, set by the engine
+    // when the table is read. Stored rows are read by their own tag
+    #[serde(skip)]
+    pub row_codec: u8,
 }
 
 impl Table {
+    // A new table without UNIQUE sets or insertion order, the other
+    // fields are filled when the engine reads it back
+    pub fn new(name: String, columns: Vec<Column>) -> Self {
+        Self { name, columns, unique: Vec::new(), defaults: Vec::new(), schema_version: 0, insertion_order: false, row_codec: BINCODE_TAG }
+    }
+
     // Check if table is valid
     pub fn validate(&self) -> Result<()> {
         // check column
@@ -23,22 +48,303 @@ impl Table {
             _ => return Err(Error::Internal(format!("Multiple primary key for table {}", self.name))),
         }
 
+        // check unique column sets
+        for set in self.unique.iter() {
+            if set.is_empty() {
+                return Err(Error::Internal(format!("Empty unique constraint in table {}", self.name)));
+            }
+            for (i, name) in set.iter().enumerate() {
+                if self.column_index(name).is_none() {
+                    return Err(Error::Internal(format!("Unique column {} not found in table {}", name, self.name)));
+                }
+                if set[..i].contains(name) {
+                    return Err(Error::Internal(format!("Duplicate unique column {} in table {}", name, self.name)));
+                }
+            }
+        }
+
+        // check nullable primary key
+        for column in self.columns.iter() {
+            if column.primary_key && column.nullable {
+                return Err(Error::Internal(format!("Primary key {} cannot be nullable in table {}", column.name, self.name)));
+            }
+            if column.autoincrement && (!column.primary_key || column.datatype != DataTypes::Integer || column.default_expr.is_some()) {
+                return Err(Error::Internal(format!("Autoincrement column {} must be an integer primary key without default in table {}", column.name, self.name)));
+            }
+        }
+
         Ok(())
     }
 
+    // Position of the primary key column, validate() makes sure there is one
+    pub fn primary_key_index(&self) -> Result<usize> {
+        self.columns.iter().position(|c| c.primary_key)
+            .ok_or_else(|| Error::Internal(format!("No primary key for table {}", self.name)))
+    }
+
     pub fn get_primary_key(&self, row:&Row) -> Result<Value> {
-        let pos = self.columns.iter().position(|c|c.primary_key).expect("No primary key found");
+        let pos = self.primary_key_index()?;
         Ok(row[pos].clone())
     }
-    
+
+    // Values of a unique column set in the row, None if they are all NULL
+    pub fn unique_key(&self, set: &[String], row: &Row) -> Result<Option<Vec<HashKey>>> {
+        let mut key = Vec::with_capacity(set.len());
+        for name in set {
+            let i = self.column_index(name)
+                .ok_or_else(|| Error::Internal(format!("Unique column {} not found in table {}", name, self.name)))?;
+            key.push(row[i].hash_key());
+        }
+        Ok(if key.iter().all(|k| *k == HashKey::Null) { None } else { Some(key) })
+    }
+
+    // The stored form of a row of the table
+    pub fn serialize_row(&self, row: &Row) -> Result<Vec<u8>> {
+        self.check_arity(row)?;
+        encode_row(codec_for_tag(self.row_codec)?, row)
+    }
+
+    pub fn deserialize_row(&self, data: &[u8]) -> Result<Row> {
+        let row = decode_row(data)?;
+        self.check_arity(&row)?;
+        Ok(row)
+    }
+
+    fn check_arity(&self, row: &Row) -> Result<()> {
+        if row.len() != self.columns.len() {
+            return Err(Error::Internal(format!("Expected {} values in a row of table {}, got {}", self.columns.len(), self.name, row.len())));
+        }
+        Ok(())
+    }
+
+    // Position of the column in the row, None if not found
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == name)
+    }
+
+    // Get the column by name
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.column_index(name).map(|i| &self.columns[i])
+    }
+
+    // Positions of the columns an insert has to give a value for
+    pub fn required_columns(&self) -> Vec<usize> {
+        (0..self.columns.len()).filter(|&i| self.columns[i].required()).collect()
+    }
+}
+
+// Render the row with column names for logs and errors
+// Table t1 (a int, b float, c varchar): a=4, b=3.0, c=apple
+pub fn format_row(table: &Table, row: &Row) -> String {
+    table.columns.iter().zip(row.iter()).map(|(col, value)| {
+        match (&col.datatype, value) {
+            // Keep the decimal point for whole floats
+            (DataTypes::Float, Value::Float(f)) if f.is_finite() && f.fract() == 0.0 => format!("{}={:.1}", col.name, f),
+            _ => format!("{}={}", col.name, value),
+        }
+    }).collect::<Vec<_>>().join(", ")
 }
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Column {
     pub name: String,
     pub datatype: DataTypes,
     pub nullable: bool,
     pub default: Option<Value>,
+    // The default as written, computed ones have no value above
+    pub default_expr: Option<Expression>,
     pub primary_key: bool,
+    // Integer primary key, a NULL or omitted value gets the next id
+    pub autoincrement: bool,
+}
+
+impl Column {
+    // Not nullable, without a default and not filled by autoincrement
+    pub fn required(&self) -> bool {
+        !self.nullable && self.default.is_none() && self.default_expr.is_none() && !self.autoincrement
+    }
+
+    // Computed defaults are evaluated on insert and have no value here,
+    // nullable columns without a default get NULL
+    pub fn set_default(&mut self, default: Option<Expression>) -> Result<()> {
+        self.default = match &default {
+            Some(Expression::Function(name, _)) if name == CURRENT_TIMESTAMP => None,
+            Some(expr) => Some(Value::from_expression_to_value(expr.clone())?),
+            // NULL stands for the id assigned on insert
+            None if self.nullable || self.autoincrement => Some(Value::Null),
+            None => None,
+        };
+        self.default_expr = default;
+        Ok(())
+    }
 }
 
+// DESCRIBE output
+// TABLE tbl (
+//     a INTEGER PRIMARY KEY,
+//     b VARCHAR NULL DEFAULT 'apple'
+// )
+impl Display for Table {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "TABLE {} (", self.name)?;
+        for (i, column) in self.columns.iter().enumerate() {
+            let datatype = match column.datatype {
+                DataTypes::Boolean => "BOOLEAN",
+                DataTypes::Integer => "INTEGER",
+                DataTypes::Float => "FLOAT",
+                DataTypes::String => "VARCHAR",
+            };
+            write!(f, "    {} {}", column.name, datatype)?;
+            if column.primary_key {
+                write!(f, " PRIMARY KEY")?;
+                if column.autoincrement {
+                    write!(f, " AUTOINCREMENT")?;
+                }
+            } else {
+                write!(f, " {}", if column.nullable { "NULL" } else { "NOT NULL" })?;
+            }
+            if let Some(expr) = &column.default_expr {
+                write!(f, " DEFAULT {}", expr)?;
+            }
+            let last = i + 1 == self.columns.len() && self.unique.is_empty();
+            writeln!(f, "{}", if last { "" } else { "," })?;
+        }
+        for (i, set) in self.unique.iter().enumerate() {
+            writeln!(f, "    UNIQUE ({}){}", set.join(", "), if i + 1 < self.unique.len() { "," } else { "" })?;
+        }
+        write!(f, "){}", if self.insertion_order { " WITH INSERTION ORDER" } else { "" })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::{Error, Result}, sql::{parser::ast::{Consts, Expression, CURRENT_TIMESTAMP}, types::{codec::{BINCODE_TAG, COMPACT_TAG}, DataTypes, Value}}};
+    use super::{format_row, Column, Table};
+
+    fn sample_table() -> Table {
+        Table {
+            name: "t1".to_string(),
+            columns: vec![
+                Column { name: "a".to_string(), datatype: DataTypes::Integer, nullable: false, default: None, default_expr: None, primary_key: true, autoincrement: false },
+                Column { name: "b".to_string(), datatype: DataTypes::Integer, nullable: true, default: None, default_expr: None, primary_key: false, autoincrement: false },
+                Column {
+                    name: "c".to_string(), datatype: DataTypes::String, nullable: true,
+                    default: Some(Value::String("apple".into())), default_expr: Some(Consts::String("apple".into()).into()),
+                    primary_key: false, autoincrement: false,
+                },
+            ],
+            unique: vec![],
+            defaults: vec![],
+            schema_version: 0,
+            insertion_order: false,
+            row_codec: BINCODE_TAG,
+        }
+    }
+
+    #[test]
+    fn test_serialize_row() -> Result<()> {
+        let mut table = sample_table();
+        let row = vec![Value::Integer(1), Value::Null, Value::String("apple".into())];
+        for tag in [BINCODE_TAG, COMPACT_TAG] {
+            table.row_codec = tag;
+            let data = table.serialize_row(&row)?;
+            assert_eq!(data[0], tag);
+            assert_eq!(table.deserialize_row(&data)?, row);
+        }
+        // Rows of the other codec are still read
+        table.row_codec = BINCODE_TAG;
+        let compact = Table { row_codec: COMPACT_TAG, ..sample_table() }.serialize_row(&row)?;
+        assert_eq!(table.deserialize_row(&compact)?, row);
+
+        // The row must have a value for every column
+        assert!(table.serialize_row(&vec![Value::Integer(1)]).is_err());
+        let mut narrow = sample_table();
+        narrow.columns.pop();
+        let data = narrow.serialize_row(&vec![Value::Integer(1), Value::Null])?;
+        match table.deserialize_row(&data) {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "Expected 3 values in a row of table t1, got 2"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        table.row_codec = 9;
+        assert!(table.serialize_row(&row).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_lookup() {
+        let table = sample_table();
+        assert_eq!(table.column_index("a"), Some(0));
+        assert_eq!(table.column_index("c"), Some(2));
+        assert_eq!(table.column_index("d"), None);
+
+        assert_eq!(table.column("b").map(|c| &c.datatype), Some(&DataTypes::Integer));
+        assert!(table.column("d").is_none());
+    }
+
+    #[test]
+    fn test_primary_key_index() {
+        let mut table = sample_table();
+        assert_eq!(table.primary_key_index().ok(), Some(0));
+        let row = vec![Value::Integer(4), Value::Null, Value::Null];
+        assert_eq!(table.get_primary_key(&row).ok(), Some(Value::Integer(4)));
+
+        // A table that skipped validate() errors instead of panicking
+        table.columns[0].primary_key = false;
+        assert!(table.primary_key_index().is_err());
+        assert!(table.get_primary_key(&row).is_err());
+    }
+
+    #[test]
+    fn test_format_row() {
+        let mut table = sample_table();
+        table.columns[1].datatype = DataTypes::Float;
+
+        let row = vec![Value::Integer(4), Value::Float(3.0), Value::String("apple".into())];
+        assert_eq!(format_row(&table, &row), "a=4, b=3.0, c=apple");
+
+        let row = vec![Value::Integer(5), Value::Float(0.25), Value::Null];
+        assert_eq!(format_row(&table, &row), "a=5, b=0.25, c=NULL");
+    }
+
+    #[test]
+    fn test_validate_nullable_primary_key() {
+        let mut table = sample_table();
+        assert!(table.validate().is_ok());
+
+        table.columns[0].nullable = true;
+        assert!(table.validate().is_err());
+    }
+
+    #[test]
+    fn test_describe_defaults() {
+        let mut table = sample_table();
+        table.columns.push(Column {
+            name: "ts".to_string(), datatype: DataTypes::Integer, nullable: false,
+            default: None, default_expr: Some(Expression::Function(CURRENT_TIMESTAMP.into(), vec![])),
+            primary_key: false, autoincrement: false,
+        });
+        assert_eq!(table.to_string(), "TABLE t1 (\n    a INTEGER PRIMARY KEY,\n    b INTEGER NULL,\n    \
+            c VARCHAR NULL DEFAULT 'apple',\n    ts INTEGER NOT NULL DEFAULT CURRENT_TIMESTAMP\n)");
+    }
+
+    #[test]
+    fn test_unique_sets() {
+        let mut table = sample_table();
+        table.unique = vec![vec!["b".into(), "c".into()]];
+        assert!(table.validate().is_ok());
+        assert_eq!(table.to_string(), "TABLE t1 (\n    a INTEGER PRIMARY KEY,\n    b INTEGER NULL,\n    \
+            c VARCHAR NULL DEFAULT 'apple',\n    UNIQUE (b, c)\n)");
+
+        let set = table.unique[0].clone();
+        assert!(table.unique_key(&set, &vec![Value::Integer(1), Value::Null, Value::Null]).unwrap().is_none());
+        assert!(table.unique_key(&set, &vec![Value::Integer(1), Value::Integer(2), Value::Null]).unwrap().is_some());
+
+        table.unique = vec![vec!["b".into(), "d".into()]];
+        assert!(table.validate().is_err());
+        table.unique = vec![vec!["b".into(), "b".into()]];
+        assert!(table.validate().is_err());
+        table.unique = vec![vec![]];
+        assert!(table.validate().is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use bincode::Options;
+
+use crate::error::{Error, Result};
+use super::{Row, Value};
+
+// Row codec: how a row is stored as the value of Key::Row
+// Every stored row starts with the tag byte of its codec, so rows
+// written by different codecs can live in the same database.
+//
+// +---------+--------------------+
+// | tag(1)  |   encoded row      |
+// +---------+--------------------+
+pub trait RowCodec {
+    // Tag byte written in front of each row
+    fn tag(&self) -> u8;
+
+    fn encode(&self, row: &Row) -> Result<Vec<u8>>;
+
+    fn decode(&self, data: &[u8]) -> Result<Row>;
+}
+
+pub const BINCODE_TAG: u8 = 0;
+pub const COMPACT_TAG: u8 = 1;
+
+// Encode the row with the codec, prefixed by its tag
+pub fn encode_row(codec: &dyn RowCodec, row: &Row) -> Result<Vec<u8>> {
+    let mut data = vec![codec.tag()];
+    data.extend(codec.encode(row)?);
+    Ok(data)
+}
+
+// Decode the row with the codec that wrote it
+pub fn decode_row(data: &[u8]) -> Result<Row> {
+    match data.split_first() {
+        Some((&tag, rest)) => codec_for_tag(tag)?.decode(rest),
+        None => Err(Error::Internal("Empty row data".into())),
+    }
+}
+
+// The codec writing rows with the tag
+pub fn codec_for_tag(tag: u8) -> Result<&'static dyn RowCodec> {
+    match tag {
+        BINCODE_TAG => Ok(&BincodeCodec),
+        COMPACT_TAG => Ok(&CompactCodec),
+        tag => Err(Error::Internal(format!("Unsupported row format {}, the row may be written by a newer version", tag))),
+    }
+}
+
+// Default codec, rows are serialized by bincode directly.
+// Value variants are stored by their index, reordering them
+// needs a new tag. The options are pinned to what tag 0 has
+// always been written with, rather than the crate defaults:
+// fixed size integers, little endian, no trailing bytes
+pub struct BincodeCodec;
+
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+        .reject_trailing_bytes()
+}
+
+impl RowCodec for BincodeCodec {
+    fn tag(&self) -> u8 {
+        BINCODE_TAG
+    }
+
+    fn encode(&self, row: &Row) -> Result<Vec<u8>> {
+        Ok(bincode_options().serialize(row)?)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Row> {
+        Ok(bincode_options().deserialize(data)?)
+    }
+}
+
+// Compact codec, smaller for numeric-heavy rows
+//
+// row:     count(varint) value...
+// value:   type(1) payload
+//   Null / False / True:  no payload
+//   Integer:              zigzag varint
+//   Float:                8 bytes, little endian
+//   String:               len(varint) bytes
+pub struct CompactCodec;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INTEGER: u8 = 3;
+const FLOAT: u8 = 4;
+const STRING: u8 = 5;
+
+impl RowCodec for CompactCodec {
+    fn tag(&self) -> u8 {
+        COMPACT_TAG
+    }
+
+    fn encode(&self, row: &Row) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        write_varint(&mut out, row.len() as u64);
+        for value in row.iter() {
+            match value {
+                Value::Null => out.push(NULL),
+                Value::Boolean(false) => out.push(FALSE),
+                Value::Boolean(true) => out.push(TRUE),
+                Value::Integer(i) => {
+                    out.push(INTEGER);
+                    // zigzag: 0 -> 0, -1 -> 1, 1 -> 2, -2 -> 3 ...
+                    write_varint(&mut out, ((i << 1) ^ (i >> 63)) as u64);
+                }
+                Value::Float(f) => {
+                    out.push(FLOAT);
+                    out.extend(f.to_le_bytes());
+                }
+                Value::String(s) => {
+                    out.push(STRING);
+                    write_varint(&mut out, s.len() as u64);
+                    out.extend(s.as_bytes());
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Row> {
+        let mut input = data;
+        let count = read_varint(&mut input)?;
+        let mut row = Vec::new();
+        for _ in 0..count {
+            let value = match take(&mut input, 1)?[0] {
+                NULL => Value::Null,
+                FALSE => Value::Boolean(false),
+                TRUE => Value::Boolean(true),
+                INTEGER => {
+                    let v = read_varint(&mut input)?;
+                    Value::Integer(((v >> 1) as i64) ^ -((v & 1) as i64))
+                }
+                FLOAT => Value::Float(f64::from_le_bytes(take(&mut input, 8)?.try_into()?)),
+                STRING => {
+                    let len = read_varint(&mut input)? as usize;
+                    Value::String(String::from_utf8(take(&mut input, len)?.to_vec())?)
+                }
+                t => return Err(Error::Internal(format!("Unknown value type {} in row", t))),
+            };
+            row.push(value);
+        }
+        Ok(row)
+    }
+}
+
+// -+------------------------+-
+//      Auxilliary Part
+// -+------------------------+-
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+    while v >= 0x80 {
+        out.push((v as u8) | 0x80);
+        v >>= 7;
+    }
+    out.push(v as u8);
+}
+
+fn read_varint(input: &mut &[u8]) -> Result<u64> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = take(input, 1)?[0];
+        v |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(v);
+        }
+    }
+    Err(Error::Internal("Varint too long in row".into()))
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if input.len() < len {
+        return Err(Error::Internal("Unexpected end of row".into()));
+    }
+    let (bytes, rest) = input.split_at(len);
+    *input = rest;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error::{Error, Result}, sql::types::Value};
+    use super::{decode_row, encode_row, BincodeCodec, CompactCodec};
+
+    #[test]
+    fn test_codec_round_trip() -> Result<()> {
+        let row = vec![
+            Value::Null,
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::Integer(0),
+            Value::Integer(-1),
+            Value::Integer(i64::MAX),
+            Value::Integer(i64::MIN),
+            Value::Float(-2.5),
+            Value::String("哈哈 apple".into()),
+        ];
+        assert_eq!(decode_row(&encode_row(&BincodeCodec, &row)?)?, row);
+        assert_eq!(decode_row(&encode_row(&CompactCodec, &row)?)?, row);
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_smaller() -> Result<()> {
+        let row = vec![Value::Integer(1), Value::Integer(300), Value::Integer(-42), Value::Integer(100000)];
+        let bincode_len = encode_row(&BincodeCodec, &row)?.len();
+        let compact_len = encode_row(&CompactCodec, &row)?.len();
+        assert!(compact_len < bincode_len, "compact {} vs bincode {}", compact_len, bincode_len);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decode_corrupt() {
+        assert!(decode_row(&[]).is_err());
+        assert!(decode_row(&[9, 1, 2]).is_err());
+        // declares 2 values but holds only one
+        assert!(decode_row(&[1, 2, 3, 2]).is_err());
+    }
+
+    // The stored bytes of a row must never change for a tag
+    #[test]
+    fn test_bincode_format() -> Result<()> {
+        let row = vec![Value::Null, Value::Boolean(true), Value::Integer(-2), Value::Float(1.0), Value::String("a".into())];
+        let data = [
+            vec![0],
+            vec![5, 0, 0, 0, 0, 0, 0, 0],
+            vec![0, 0, 0, 0],
+            vec![1, 0, 0, 0, 1],
+            vec![2, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+            vec![3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f],
+            vec![4, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, b'a'],
+        ].concat();
+        assert_eq!(encode_row(&BincodeCodec, &row)?, data);
+        assert_eq!(decode_row(&data)?, row);
+
+        // A newer format is refused, not read as an old one
+        let mut newer = data.clone();
+        newer[0] = 7;
+        match decode_row(&newer) {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "Unsupported row format 7, the row may be written by a newer version"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        // Trailing bytes mean the row is not what it claims to be
+        assert!(decode_row(&[data.as_slice(), &[0]].concat()).is_err());
+        Ok(())
+    }
+
+    // NaN != NaN, so floats are compared bit by bit
+    fn assert_same_row(a: &[Value], b: &[Value]) {
+        assert_eq!(a.len(), b.len());
+        for (x, y) in a.iter().zip(b) {
+            match (x, y) {
+                (Value::Float(x), Value::Float(y)) => assert_eq!(x.to_bits(), y.to_bits(), "{} vs {}", x, y),
+                (x, y) => assert_eq!(x, y),
+            }
+        }
+    }
+
+    #[test]
+    fn test_codec_special_floats() -> Result<()> {
+        let row = vec![
+            Value::Null,
+            Value::Boolean(false),
+            Value::Integer(42),
+            Value::String(String::new()),
+            Value::Float(f64::NAN),
+            Value::Float(f64::INFINITY),
+            Value::Float(f64::NEG_INFINITY),
+            Value::Float(-0.0),
+            Value::Float(f64::MIN_POSITIVE),
+            Value::Float(f64::MAX),
+        ];
+        let bincode_row: Vec<Value> = bincode::deserialize(&bincode::serialize(&row)?)?;
+        assert_same_row(&bincode_row, &row);
+        assert_same_row(&decode_row(&encode_row(&BincodeCodec, &row)?)?, &row);
+        assert_same_row(&decode_row(&encode_row(&CompactCodec, &row)?)?, &row);
+        assert!(matches!(bincode_row[4], Value::Float(f) if f.is_nan()));
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{de::{self, value::MapDeserializer, DeserializeOwned, IntoDeserializer, Visitor}, forward_to_deserialize_any};
+
+use crate::error::{Error, Result};
+use super::{Row, Value};
+
+// Map a result row into a struct, fields are matched by column name
+//
+// #[derive(Deserialize)]
+// struct User { id: i64, name: String, score: Option<f64> }
+// let user: User = row_to(&columns, &row)?;
+pub fn row_to<T: DeserializeOwned>(columns: &[String], row: &Row) -> Result<T> {
+    if columns.len() != row.len() {
+        return Err(Error::Internal(format!("expected {} values, got {}", columns.len(), row.len())));
+    }
+    let map = columns.iter().map(|c| c.as_str()).zip(row.iter().cloned());
+    T::deserialize(MapDeserializer::new(map))
+}
+
+// Each value is handed to the visitor as its plain type,
+// NULL is None for an Option field and unit otherwise
+pub struct ValueDeserializer(Value);
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = ValueDeserializer;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        ValueDeserializer(self)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for ValueDeserializer {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Value::Null => visitor.visit_unit(),
+            Value::Boolean(b) => visitor.visit_bool(b),
+            Value::Integer(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => visitor.visit_string(s),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.0 {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use crate::{error::Result, sql::types::Value};
+    use super::row_to;
+
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct User {
+        id: i64,
+        name: String,
+        score: Option<f64>,
+    }
+
+    #[test]
+    fn test_row_to() -> Result<()> {
+        let columns = vec!["id".to_string(), "name".to_string(), "score".to_string()];
+        let user: User = row_to(&columns, &vec![Value::Integer(1), Value::String("ray".into()), Value::Float(9.5)])?;
+        assert_eq!(user, User { id: 1, name: "ray".into(), score: Some(9.5) });
+        let user: User = row_to(&columns, &vec![Value::Integer(2), Value::String("db".into()), Value::Null])?;
+        assert_eq!(user, User { id: 2, name: "db".into(), score: None });
+
+        // Wrong type, missing column and wrong arity
+        assert!(row_to::<User>(&columns, &vec![Value::String("1".into()), Value::String("ray".into()), Value::Null]).is_err());
+        assert!(row_to::<User>(&[columns[0].clone(), columns[2].clone()], &vec![Value::Integer(1), Value::Null]).is_err());
+        assert!(row_to::<User>(&columns, &vec![Value::Integer(1)]).is_err());
+        Ok(())
+    }
+}
@@ -1,10 +1,14 @@
-use std::fmt::Display;
+use std::{cmp::Ordering, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 
+use crate::error::{Error, Result};
 use super::parser::ast::{Consts, Expression};
 
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub mod codec;
+pub mod de;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum DataTypes {
     Boolean,
     String,
@@ -12,7 +16,7 @@ pub enum DataTypes {
     Float,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Value {
     Null,
     Boolean(bool),
@@ -22,16 +26,91 @@ pub enum Value {
 }
 
 impl Value {
-    pub fn from_expression_to_value(expr:Expression) -> Self {
-        match expr {
+    // Only constants have a value without a row
+    pub fn from_expression_to_value(expr:Expression) -> Result<Self> {
+        Ok(match expr {
             Expression::Consts(Consts::Null) => Self::Null,
             Expression::Consts(Consts::Boolean(b)) => Self::Boolean(b),
             Expression::Consts(Consts::Integer(i)) => Self::Integer(i),
             Expression::Consts(Consts::Float(f)) => Self::Float(f),
             Expression::Consts(Consts::String(s)) => Self::String(s),
-        }
+            expr => return Err(Error::Internal(format!("Expression {:?} is not a constant", expr))),
+        })
     } 
 
+    // Arithmetic, NULL in either operand gives NULL.
+    // Integers stay integers, mixed with a float gives a float.
+    pub fn add(&self, other: &Value) -> Result<Value> {
+        self.arithmetic(other, "+", i64::checked_add, |a, b| a + b)
+    }
+
+    pub fn subtract(&self, other: &Value) -> Result<Value> {
+        self.arithmetic(other, "-", i64::checked_sub, |a, b| a - b)
+    }
+
+    pub fn multiply(&self, other: &Value) -> Result<Value> {
+        self.arithmetic(other, "*", i64::checked_mul, |a, b| a * b)
+    }
+
+    pub fn divide(&self, other: &Value) -> Result<Value> {
+        if matches!(other, Value::Integer(0)) && matches!(self, Value::Integer(_)) {
+            return Err(Error::Internal("Division by zero".into()));
+        }
+        self.arithmetic(other, "/", i64::checked_div, |a, b| a / b)
+    }
+
+    fn arithmetic(
+        &self,
+        other: &Value,
+        op: &str,
+        int_op: fn(i64, i64) -> Option<i64>,
+        float_op: fn(f64, f64) -> f64,
+    ) -> Result<Value> {
+        Ok(match (self, other) {
+            (Value::Null, _) | (_, Value::Null) => Value::Null,
+            (Value::Integer(a), Value::Integer(b)) => match int_op(*a, *b) {
+                Some(v) => Value::Integer(v),
+                None => return Err(Error::Internal(format!("Integer overflow in {} {} {}", a, op, b))),
+            },
+            (Value::Integer(a), Value::Float(b)) => Value::Float(float_op(*a as f64, *b)),
+            (Value::Float(a), Value::Integer(b)) => Value::Float(float_op(*a, *b as f64)),
+            (Value::Float(a), Value::Float(b)) => Value::Float(float_op(*a, *b)),
+            (a, b) => return Err(Error::Internal(format!("Cannot apply {} to {} and {}", op, a, b))),
+        })
+    }
+
+    // Stable, locale-free text for export, parsing it gives the value back.
+    // Floats use the shortest round-trip form and always keep a decimal
+    // point or exponent (1.0, 1e300), -0.0 is written as 0.0.
+    pub fn to_canonical_string(&self) -> String {
+        match self {
+            Value::Null => "NULL".to_string(),
+            Value::Boolean(b) => if *b { "TRUE" } else { "FALSE" }.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Float(f) if f.is_nan() => "NaN".to_string(),
+            Value::Float(f) if f.is_infinite() => if *f > 0.0 { "Infinity" } else { "-Infinity" }.to_string(),
+            Value::Float(f) if *f == 0.0 => "0.0".to_string(),
+            Value::Float(f) => format!("{:?}", f),
+            Value::String(s) => s.clone(),
+        }
+    }
+
+    // Key for grouping rows (DISTINCT). Unlike comparisons, NULL equals NULL
+    // here, so all NULLs land in one group. Whole floats are keyed as the
+    // integer they equal, -0.0 included, the rest by their bits with every
+    // NaN folded into one.
+    pub fn hash_key(&self) -> HashKey {
+        match self {
+            Value::Null => HashKey::Null,
+            Value::Boolean(b) => HashKey::Boolean(*b),
+            Value::Integer(i) => HashKey::Integer(*i),
+            Value::Float(f) if f.is_nan() => HashKey::Float(f64::NAN.to_bits()),
+            Value::Float(f) if whole_i64(*f).is_some() => HashKey::Integer(*f as i64),
+            Value::Float(f) => HashKey::Float(f.to_bits()),
+            Value::String(s) => HashKey::String(s.clone()),
+        }
+    }
+
     pub fn datatype(&self) -> Option<DataTypes> {
         match self {
             Value::Null => None,
@@ -41,6 +120,94 @@ impl Value {
             Value::String(_) => Some(DataTypes::String),
         }
     }
+
+    // Total order for sorting. Comparable values keep their order, the rest
+    // go by type: NULL < booleans < numbers < strings, NaN after every number.
+    pub fn sort_cmp(&self, other: &Value) -> Ordering {
+        let rank = |v: &Value| match v {
+            Value::Null => 0,
+            Value::Boolean(_) => 1,
+            Value::Integer(_) | Value::Float(_) => 2,
+            Value::String(_) => 3,
+        };
+        let nan = |v: &Value| matches!(v, Value::Float(f) if f.is_nan());
+        self.partial_cmp(other)
+            .unwrap_or_else(|| rank(self).cmp(&rank(other)).then_with(|| nan(self).cmp(&nan(other))))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashKey {
+    Null,
+    Boolean(bool),
+    Integer(i64),
+    Float(u64),
+    String(String),
+}
+
+// A value as a constant expression
+impl From<Value> for Expression {
+    fn from(value: Value) -> Self {
+        Expression::Consts(match value {
+            Value::Null => Consts::Null,
+            Value::Boolean(b) => Consts::Boolean(b),
+            Value::Integer(i) => Consts::Integer(i),
+            Value::Float(f) => Consts::Float(f),
+            Value::String(s) => Consts::String(s),
+        })
+    }
+}
+
+// Equal when partial_cmp finds them equal, so 1 = 1.0. NULL also equals
+// NULL here, SQL comparisons go through partial_cmp and never see it.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (a, b) => a.partial_cmp(b) == Some(Ordering::Equal),
+        }
+    }
+}
+
+// Values of the same type compare, integers and floats compare as numbers.
+// Anything else, NULL included, is not comparable.
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            // false < true
+            (Value::Boolean(a), Value::Boolean(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.partial_cmp(b),
+            (Value::Integer(a), Value::Float(b)) => cmp_integer_float(*a, *b),
+            (Value::Float(a), Value::Integer(b)) => cmp_integer_float(*b, *a).map(Ordering::reverse),
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+            (_, _) => None,
+        }
+    }
+}
+
+// The integer a float equals, if it is whole and fits an i64
+fn whole_i64(f: f64) -> Option<i64> {
+    // -2^63 and 2^63 are exact as floats, i64::MAX is not
+    (f.fract() == 0.0 && (-9223372036854775808.0..9223372036854775808.0).contains(&f)).then_some(f as i64)
+}
+
+// Exact, without rounding the integer to a float: above 2^53 that
+// would make 2^53 + 1 equal to 2^53 as a float
+fn cmp_integer_float(i: i64, f: f64) -> Option<Ordering> {
+    if f.is_nan() {
+        return None;
+    }
+    if let Some(whole) = whole_i64(f) {
+        return Some(i.cmp(&whole));
+    }
+    Some(match f {
+        f if f >= 9223372036854775808.0 => Ordering::Less,
+        f if f < -9223372036854775808.0 => Ordering::Greater,
+        // Between floor(f) and floor(f) + 1, never equal
+        f if i <= f.floor() as i64 => Ordering::Less,
+        _ => Ordering::Greater,
+    })
 }
 
 impl Display for Value {
@@ -56,4 +223,170 @@ impl Display for Value {
     }
 }
 
-pub type Row = Vec<Value>;
\ No newline at end of file
+// Build values from Rust primitives: vec![1.into(), "x".into(), None::<i64>.into()]
+impl From<i64> for Value {
+    fn from(v: i64) -> Self {
+        Value::Integer(v)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(v: f64) -> Self {
+        Value::Float(v)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(v: bool) -> Self {
+        Value::Boolean(v)
+    }
+}
+
+impl From<String> for Value {
+    fn from(v: String) -> Self {
+        Value::String(v)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(v: &str) -> Self {
+        Value::String(v.to_string())
+    }
+}
+
+// None is NULL
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(v: Option<T>) -> Self {
+        v.map_or(Value::Null, Into::into)
+    }
+}
+
+pub type Row = Vec<Value>;
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Result;
+    use super::Value;
+
+    #[test]
+    fn test_null_arithmetic() -> Result<()> {
+        let ops: Vec<fn(&Value, &Value) -> Result<Value>> = vec![Value::add, Value::subtract, Value::multiply, Value::divide];
+        for op in ops {
+            assert_eq!(op(&Value::Null, &Value::Integer(1))?, Value::Null);
+            assert_eq!(op(&Value::Float(1.0), &Value::Null)?, Value::Null);
+            assert_eq!(op(&Value::Null, &Value::Null)?, Value::Null);
+            // NULL wins even over a type error
+            assert_eq!(op(&Value::String("a".into()), &Value::Null)?, Value::Null);
+        }
+        // NULL divided by zero is still NULL
+        assert_eq!(Value::Null.divide(&Value::Integer(0))?, Value::Null);
+
+        assert_eq!(Value::Integer(7).divide(&Value::Integer(2))?, Value::Integer(3));
+        assert_eq!(Value::Integer(1).add(&Value::Float(0.5))?, Value::Float(1.5));
+        assert!(Value::Integer(1).divide(&Value::Integer(0)).is_err());
+        assert!(Value::Integer(i64::MAX).add(&Value::Integer(1)).is_err());
+        assert!(Value::Boolean(true).add(&Value::Integer(1)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_primitives() {
+        let row: Vec<Value> = vec![1.into(), 2.5.into(), true.into(), "x".into(), String::from("y").into()];
+        assert_eq!(row, vec![
+            Value::Integer(1),
+            Value::Float(2.5),
+            Value::Boolean(true),
+            Value::String("x".into()),
+            Value::String("y".into()),
+        ]);
+        assert_eq!(Value::from(None::<i64>), Value::Null);
+        assert_eq!(Value::from(None::<&str>), Value::Null);
+        assert_eq!(Value::from(Some(3)), Value::Integer(3));
+        assert_eq!(Value::from(Some("z")), Value::String("z".into()));
+    }
+
+    #[test]
+    fn test_sort_cmp() {
+        let mut values: Vec<Value> = vec!["b".into(), 2.into(), Value::Null, f64::NAN.into(), true.into(),
+            1.5.into(), "a".into(), false.into(), Value::Null, (-1).into()];
+        values.sort_by(Value::sort_cmp);
+        let expected: Vec<Value> = vec![Value::Null, Value::Null, false.into(), true.into(),
+            (-1).into(), 1.5.into(), 2.into(), f64::NAN.into(), "a".into(), "b".into()];
+        assert_eq!(values.iter().map(Value::to_canonical_string).collect::<Vec<_>>(),
+            expected.iter().map(Value::to_canonical_string).collect::<Vec<_>>());
+
+        // Same result from any starting order
+        let mut reversed = expected.clone();
+        reversed.reverse();
+        reversed.sort_by(Value::sort_cmp);
+        assert_eq!(reversed.iter().map(Value::to_canonical_string).collect::<Vec<_>>(),
+            expected.iter().map(Value::to_canonical_string).collect::<Vec<_>>());
+        assert_eq!(Value::Integer(1).sort_cmp(&Value::Float(1.0)), std::cmp::Ordering::Equal);
+    }
+
+    #[test]
+    fn test_eq() {
+        assert_eq!(Value::Integer(1), Value::Float(1.0));
+        assert_eq!(Value::Float(-0.0), Value::Integer(0));
+        assert_ne!(Value::Integer(1), Value::Float(1.5));
+        assert_ne!(Value::Integer(1), Value::String("1".into()));
+        assert_ne!(Value::Float(f64::NAN), Value::Float(f64::NAN));
+        assert_eq!(Value::Null, Value::Null);
+        assert_ne!(Value::Null, Value::Boolean(false));
+
+        // Exact past 2^53, where an i64 does not fit a float
+        let big = 1i64 << 53;
+        assert_ne!(Value::Integer(big + 1), Value::Float(big as f64));
+        assert_eq!(Value::Integer(big), Value::Float(big as f64));
+        assert!(Value::Integer(big + 1) > Value::Float(big as f64));
+        assert!(Value::Integer(i64::MAX) < Value::Float(9223372036854775808.0));
+        assert_eq!(Value::Integer(i64::MIN), Value::Float(-9223372036854775808.0));
+        assert!(Value::Integer(i64::MIN) > Value::Float(f64::NEG_INFINITY));
+        assert!(Value::Integer(-3) < Value::Float(-2.5));
+        assert!(Value::Integer(-2) > Value::Float(-2.5));
+        assert_eq!(Value::Float(2.5).partial_cmp(&Value::Integer(3)), Some(std::cmp::Ordering::Less));
+        assert_eq!(Value::Integer(1).partial_cmp(&Value::Float(f64::NAN)), None);
+    }
+
+    #[test]
+    fn test_hash_key() {
+        assert_eq!(Value::Null.hash_key(), Value::Null.hash_key());
+        assert_ne!(Value::Null.hash_key(), Value::Integer(0).hash_key());
+        assert_ne!(Value::Null.hash_key(), Value::String("".into()).hash_key());
+        assert_ne!(Value::Integer(1).hash_key(), Value::String("1".into()).hash_key());
+        assert_eq!(Value::Float(-0.0).hash_key(), Value::Float(0.0).hash_key());
+        assert_eq!(Value::Integer(1).hash_key(), Value::Float(1.0).hash_key());
+        assert_ne!(Value::Integer(1).hash_key(), Value::Float(1.5).hash_key());
+        // Equal values share a key, unequal ones do not
+        let big = 1i64 << 53;
+        assert_ne!(Value::Integer(big + 1).hash_key(), Value::Float(big as f64).hash_key());
+        assert_ne!(Value::Integer(i64::MAX).hash_key(), Value::Float(9223372036854775808.0).hash_key());
+        assert_eq!(Value::Integer(i64::MIN).hash_key(), Value::Float(-9223372036854775808.0).hash_key());
+        assert_eq!(Value::Float(f64::NAN).hash_key(), Value::Float(-f64::NAN).hash_key());
+    }
+
+    #[test]
+    fn test_canonical_string() {
+        let canonical = |f: f64| Value::Float(f).to_canonical_string();
+        assert_eq!(canonical(-0.0), "0.0");
+        assert_eq!(canonical(0.0), "0.0");
+        assert_eq!(canonical(1.0), "1.0");
+        assert_eq!(canonical(-2.5), "-2.5");
+        assert_eq!(canonical(0.1 + 0.2), "0.30000000000000004");
+        assert_eq!(canonical(1e300), "1e300");
+        assert_eq!(canonical(1.5e-300), "1.5e-300");
+        assert_eq!(canonical(f64::NAN), "NaN");
+        assert_eq!(canonical(f64::INFINITY), "Infinity");
+        assert_eq!(canonical(f64::NEG_INFINITY), "-Infinity");
+
+        // Finite floats parse back to the same bits
+        for f in [1e300, 1.5e-300, 0.1 + 0.2, f64::MAX, f64::MIN_POSITIVE, -123456789.125] {
+            assert_eq!(canonical(f).parse::<f64>().unwrap().to_bits(), f.to_bits());
+        }
+
+        assert_eq!(Value::Integer(-1234567).to_canonical_string(), "-1234567");
+        assert_eq!(Value::Null.to_canonical_string(), "NULL");
+        assert_eq!(Value::Boolean(false).to_canonical_string(), "FALSE");
+        assert_eq!(Value::String("a,b".into()).to_canonical_string(), "a,b");
+    }
+}
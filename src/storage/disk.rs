@@ -1,8 +1,8 @@
 use std::{collections::{btree_map, BTreeMap}, fs::{File, OpenOptions}, io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write}, path::PathBuf};
 use fs4::fs_std::FileExt;
 
-use crate::error::Result;
-use super::engine::{Engine, EngineIterator};
+use crate::error::{Error, Result};
+use super::engine::{checked_range, Engine, EngineIterator};
 
 
 pub type KeyDir = BTreeMap<Vec<u8>, (u64, u32)>;
@@ -24,32 +24,6 @@ impl DiskEngine {
         eng.compact()?;
         Ok(eng)
     }
-
-    fn compact(&mut self) -> Result<()> {
-        // Create a temporary log 
-        let mut new_path = self.log.file_path.clone();
-        new_path.set_extension("compact");
-        let mut new_log = Log::new(new_path)?;
-        let mut new_keydir = KeyDir::new();
-
-        // Re-Write
-        for (key, (offset, val_size)) in self.keydir.iter() {
-            // Read value
-            let value = self.log.read_value(*offset, *val_size)?;
-            let (new_offset, new_size) = new_log.write_entry(key, Some(&value))?;
-
-            new_keydir.insert(key.clone(), (new_offset + new_size as u64 - *val_size as u64, *val_size));
-        }
-
-        // Replace with temporary file 
-        std::fs::rename(&new_log.file_path, &self.log.file_path)?;
-
-        new_log.file_path = self.log.file_path.clone();
-        self.keydir = new_keydir;
-        self.log = new_log;
-
-        Ok(())
-    }
 }
 
 impl Engine for DiskEngine {
@@ -80,10 +54,47 @@ impl Engine for DiskEngine {
         self.keydir.remove(&key);
         Ok(())
     }
+
+    fn flush(&mut self) -> Result<()> {
+        self.log.file.sync_all()?;
+        Ok(())
+    }
+
+    // Rewrite the live entries to a new log, which replaces the old one
+    fn compact(&mut self) -> Result<()> {
+        // Create a temporary log 
+        let mut new_path = self.log.file_path.clone();
+        new_path.set_extension("compact");
+        let mut new_log = Log::new(new_path)?;
+        let mut new_keydir = KeyDir::new();
+
+        // Re-Write
+        for (key, (offset, val_size)) in self.keydir.iter() {
+            // Read value
+            let value = self.log.read_value(*offset, *val_size)?;
+            let (new_offset, new_size) = new_log.write_entry(key, Some(&value))?;
+
+            new_keydir.insert(key.clone(), (new_offset + new_size as u64 - *val_size as u64, *val_size));
+        }
+
+        // Replace with temporary file 
+        std::fs::rename(&new_log.file_path, &self.log.file_path)?;
+
+        new_log.file_path = self.log.file_path.clone();
+        self.keydir = new_keydir;
+        self.log = new_log;
+
+        Ok(())
+    }
+
+    // The length of the log, overwritten and deleted entries included
+    fn size(&mut self) -> Result<u64> {
+        Ok(self.log.file.metadata()?.len())
+    }
     
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
         DiskEngineIterator {
-            inner: self.keydir.range(range),
+            inner: self.keydir.range(checked_range(range)),
             log: &mut self.log,
         }
     }
@@ -141,7 +152,13 @@ impl Log {
             .write(true).open(&file_path)?;
 
         // Add a file lock; The file is limited to only one transaction 
-        file.try_lock_exclusive()?;
+        // A held lock is reported apart from the other IO errors
+        if let Err(err) = file.try_lock_exclusive() {
+            if err.raw_os_error() == fs4::lock_contended_error().raw_os_error() {
+                return Err(Error::Internal("database is locked by another process".into()));
+            }
+            return Err(err.into());
+        }
 
         Ok(Self {file, file_path})
     }
@@ -157,7 +174,7 @@ impl Log {
             if offset >= file_size {
                 break;
             }
-            let (key, val_size) = Self::read_entry(&mut reader, offset)?;
+            let (key, val_size) = Self::read_entry(&mut reader, offset, file_size)?;
             let key_size = key.len() as u32;
             if val_size == -1 {
                 keydir.remove(&key);
@@ -177,18 +194,17 @@ impl Log {
     // | key len(4)    val len(4)     key(varint)       val(varint)  |
     // +-------------+-------------+----------------+----------------+
     fn write_entry(&mut self, key: &Vec<u8>, value: Option<&Vec<u8>>) -> Result<(u64, u32)> {
+        // Checked before anything is written
+        let (key_size, val_size, total_length) = Self::entry_sizes(key.len(), value.map(|v| v.len()))?;
+
         // Point to the end of log file
         let offset = self.file.seek(std::io::SeekFrom::End(0))?;
-        // Write in
-        let key_size = key.len() as u32;
-        let val_size = value.map_or(0, |v| v.len() as u32 );
-        let total_length = key_size + val_size + LOG_HEADER_SIZE;
 
         // Write in key size, value size, key and value
         let mut writer = BufWriter::with_capacity(total_length as usize, &self.file);
 
         writer.write_all(&key_size.to_be_bytes())?;
-        writer.write_all(&value.map_or(-1, |v|v.len() as i32).to_be_bytes())?;
+        writer.write_all(&val_size.to_be_bytes())?;
         writer.write_all(&key)?;
         if let Some(v) = value {
             writer.write_all(v)?;
@@ -198,6 +214,21 @@ impl Log {
         Ok((offset, total_length))
     }  
 
+    // The header stores the key size as u32 and the value size as i32
+    // (-1 for a delete), the entry size is returned as u32
+    fn entry_sizes(key_len: usize, val_len: Option<usize>) -> Result<(u32, i32, u32)> {
+        let key_size = u32::try_from(key_len).map_err(|_| Error::Internal("key too large".into()))?;
+        let val_size = match val_len {
+            Some(len) => i32::try_from(len).map_err(|_| Error::Internal("value too large".into()))?,
+            None => -1,
+        };
+        let total_length = key_size
+            .checked_add(val_size.max(0) as u32)
+            .and_then(|n| n.checked_add(LOG_HEADER_SIZE))
+            .ok_or_else(|| Error::Internal("entry too large".into()))?;
+        Ok((key_size, val_size, total_length))
+    }
+
     fn read_value(&mut self, offset: u64, val_size: u32) -> Result<Vec<u8>> {
         self.file.seek(SeekFrom::Start(offset))?;
         let mut buf = vec![0; val_size as usize];
@@ -205,7 +236,14 @@ impl Log {
         Ok(buf)
     } 
 
-    fn read_entry(reader: &mut BufReader<&File>, offset: u64) -> Result<(Vec<u8>, i32)> {
+    // The sizes in the header are checked against the rest of the file
+    // before anything is allocated, a corrupt entry stops the recovery
+    fn read_entry(reader: &mut BufReader<&File>, offset: u64, file_size: u64) -> Result<(Vec<u8>, i32)> {
+        let corrupt = || Error::Internal(format!("corrupt log at offset {}", offset));
+        let remaining = file_size - offset;
+        if remaining < LOG_HEADER_SIZE as u64 {
+            return Err(corrupt());
+        }
         reader.seek(SeekFrom::Start(offset))?;
         let mut buf = [0; 4];
 
@@ -213,9 +251,12 @@ impl Log {
         reader.read_exact(&mut buf)?;
         let key_size = u32::from_be_bytes(buf);
 
-        // Read value size
+        // Read value size, -1 marks a deletion
         reader.read_exact(&mut buf)?;
         let val_size = i32::from_be_bytes(buf);
+        if val_size < -1 || LOG_HEADER_SIZE as u64 + key_size as u64 + val_size.max(0) as u64 > remaining {
+            return Err(corrupt());
+        }
 
         // Read key
         let mut key = vec![0; key_size as usize];
@@ -229,8 +270,8 @@ impl Log {
 
 mod tests {
     use std::path::PathBuf;
-    use crate::{error::Result, storage::engine::Engine};
-    use super::DiskEngine;
+    use crate::{error::{Error, Result}, storage::engine::Engine};
+    use super::{DiskEngine, Log};
 
     #[test]
     fn test_disk_engine_start() -> Result<()> {
@@ -285,4 +326,83 @@ mod tests {
 
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_disk_engine_flush() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        eng.delete(b"key1".to_vec())?;
+        eng.flush()?;
+        drop(eng);
+
+        let mut eng2 = DiskEngine::new(p.clone())?;
+        assert_eq!(eng2.get(b"key1".to_vec())?, None);
+        assert_eq!(eng2.get(b"key2".to_vec())?, Some(b"value2".to_vec()));
+        drop(eng2);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_locked() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        let eng = DiskEngine::new(p.clone())?;
+        match DiskEngine::new(p.clone()) {
+            Err(Error::Internal(msg)) => assert_eq!(msg, "database is locked by another process"),
+            r => panic!("unexpected result {:?}", r.map(|_| ())),
+        }
+
+        // Reopening after a clean drop keeps the data
+        drop(eng);
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key".to_vec(), b"value".to_vec())?;
+        drop(eng);
+        let mut eng = DiskEngine::new(p.clone())?;
+        assert_eq!(eng.get(b"key".to_vec())?, Some(b"value".to_vec()));
+        drop(eng);
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_disk_engine_corrupt_header() -> Result<()> {
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        let mut eng = DiskEngine::new(p.clone())?;
+        eng.set(b"key".to_vec(), b"value".to_vec())?;
+        drop(eng);
+        let valid = std::fs::metadata(&p)?.len();
+
+        // Oversized key length, then an oversized value length, then a cut header
+        for header in [[u32::MAX.to_be_bytes(), 5u32.to_be_bytes()].concat(), [1u32.to_be_bytes(), i32::MAX.to_be_bytes()].concat(), vec![0, 0, 0]] {
+            let mut log = std::fs::read(&p)?;
+            log.truncate(valid as usize);
+            log.extend(header);
+            log.extend(b"k");
+            std::fs::write(&p, log)?;
+            match DiskEngine::new(p.clone()) {
+                Err(Error::Internal(msg)) => assert_eq!(msg, format!("corrupt log at offset {}", valid)),
+                r => panic!("unexpected result {:?}", r.map(|_| ())),
+            }
+        }
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_size_limits() -> Result<()> {
+        assert_eq!(Log::entry_sizes(3, Some(5))?, (3, 5, 16));
+        assert_eq!(Log::entry_sizes(3, None)?, (3, -1, 11));
+        assert_eq!(Log::entry_sizes(0, Some(i32::MAX as usize))?, (0, i32::MAX, i32::MAX as u32 + 8));
+
+        let error = |r: Result<(u32, i32, u32)>| match r {
+            Err(Error::Internal(msg)) => msg,
+            r => panic!("unexpected result {:?}", r),
+        };
+        assert_eq!(error(Log::entry_sizes(3, Some(i32::MAX as usize + 1))), "value too large");
+        assert_eq!(error(Log::entry_sizes(u32::MAX as usize + 1, None)), "key too large");
+        assert_eq!(error(Log::entry_sizes(u32::MAX as usize - 8, Some(1))), "entry too large");
+        Ok(())
+    }
+}
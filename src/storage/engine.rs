@@ -17,32 +17,83 @@ pub trait Engine {
     // Delete the value by key; Ignore if not exists
     fn delete(&mut self, key: Vec<u8>) -> Result<()>;
 
+    // Set the value, or delete it for None, only if the current value is
+    // the expected one (None for a missing key). Returns whether it was set.
+    // The engine is borrowed mutably, nothing can change in between
+    fn compare_and_set(&mut self, key: Vec<u8>, expected: Option<Vec<u8>>, new: Option<Vec<u8>>) -> Result<bool> {
+        if self.get(key.clone())? != expected {
+            return Ok(false);
+        }
+        match new {
+            Some(value) => self.set(key, value)?,
+            None => self.delete(key)?,
+        }
+        Ok(true)
+    }
+
+    // Persist the written data; Nothing to do for memory-based engines
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    // Reclaim the space of overwritten and deleted entries; Nothing to do for memory-based engines
+    fn compact(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    // Bytes taken by the engine, by default the size of the live keys and values
+    fn size(&mut self) -> Result<u64> {
+        self.scan(..).try_fold(0, |size, item| item.map(|(k, v)| size + (k.len() + v.len()) as u64))
+    }
+
     // Scan the value
     fn scan(&mut self, range: impl RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_>;
 
     // Scan with the prefix
     fn scan_prefix(&mut self, prefix: Vec<u8>) -> Self::EngineIterator<'_> {
-        // start: aaaa
-        // end: aaab
-        // Only ascii(0-127) 
-
-        let start = Bound::Included(prefix.clone());
-        let mut bound_prefix = prefix.clone();
-        if let Some(last) = bound_prefix.iter_mut().last() {
-            *last += 1;
-        };
-        let end = Bound::Excluded(bound_prefix);
-
-        self.scan((start, end))
+        let end = prefix_end(&prefix);
+        self.scan((Bound::Included(prefix), end))
+    }
+
+}
+
+// The range as bounds that BTreeMap::range accepts. It panics on a
+// range that ends before it starts, such a range holds no keys and
+// is replaced by an empty one
+pub fn checked_range(range: impl RangeBounds<Vec<u8>>) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+    let (start, end) = (range.start_bound().cloned(), range.end_bound().cloned());
+    let empty = match (&start, &end) {
+        (Bound::Included(s), Bound::Included(e)) => s > e,
+        (Bound::Included(s) | Bound::Excluded(s), Bound::Included(e) | Bound::Excluded(e)) => s >= e,
+        _ => false,
+    };
+    match empty {
+        true => (Bound::Included(Vec::new()), Bound::Excluded(Vec::new())),
+        false => (start, end),
     }
+}
 
+// Exclusive end of the keys starting with the prefix
+// start: aaaa
+// end: aaab
+// Trailing 0xff bytes carry into the byte before them:
+// [0x61, 0xff] => [0x62], a prefix of only 0xff has no end
+pub fn prefix_end(prefix: &[u8]) -> Bound<Vec<u8>> {
+    match prefix.iter().rposition(|b| *b != 0xff) {
+        Some(i) => {
+            let mut end = prefix[..=i].to_vec();
+            end[i] += 1;
+            Bound::Excluded(end)
+        }
+        None => Bound::Unbounded,
+    }
 }
 
 pub trait EngineIterator: DoubleEndedIterator<Item = Result<(Vec<u8>, Vec<u8>)>> {}
 
 #[cfg(test)]
 mod tests {
-    use super::Engine;
+    use super::{prefix_end, Engine};
     use crate::{error::Result, storage::{disk::DiskEngine, memory::MemoryEngine}};
     use std::{ops::Bound, path::PathBuf};
 
@@ -116,12 +167,93 @@ mod tests {
         assert_eq!(key2, b"cccfff".to_vec());
         Ok(())
     }
+
+    // Every combination of bounds, a range ending before it starts is empty
+    fn test_scan_bounds(mut eng: impl Engine) -> Result<()> {
+        fn keys(eng: &mut impl Engine, range: (Bound<Vec<u8>>, Bound<Vec<u8>>)) -> Result<Vec<Vec<u8>>> {
+            eng.scan(range).map(|r| r.map(|(k, _)| k)).collect()
+        }
+        for key in [b"a", b"b", b"c", b"d"] {
+            eng.set(key.to_vec(), b"value".to_vec())?;
+        }
+        let (inc, exc) = (|k: &[u8]| Bound::Included(k.to_vec()), |k: &[u8]| Bound::Excluded(k.to_vec()));
+        let all = |ks: &[&[u8]]| ks.iter().map(|k| k.to_vec()).collect::<Vec<_>>();
+
+        assert_eq!(keys(&mut eng, (Bound::Unbounded, Bound::Unbounded))?, all(&[b"a", b"b", b"c", b"d"]));
+        assert_eq!(keys(&mut eng, (Bound::Unbounded, exc(b"c")))?, all(&[b"a", b"b"]));
+        assert_eq!(keys(&mut eng, (Bound::Unbounded, inc(b"c")))?, all(&[b"a", b"b", b"c"]));
+        assert_eq!(keys(&mut eng, (inc(b"b"), Bound::Unbounded))?, all(&[b"b", b"c", b"d"]));
+        assert_eq!(keys(&mut eng, (exc(b"b"), Bound::Unbounded))?, all(&[b"c", b"d"]));
+        assert_eq!(keys(&mut eng, (inc(b"b"), inc(b"c")))?, all(&[b"b", b"c"]));
+        assert_eq!(keys(&mut eng, (exc(b"b"), exc(b"d")))?, all(&[b"c"]));
+        assert_eq!(keys(&mut eng, (inc(b"b"), inc(b"b")))?, all(&[b"b"]));
+
+        // Empty and reversed ranges
+        assert_eq!(keys(&mut eng, (inc(b"b"), exc(b"b")))?, all(&[]));
+        assert_eq!(keys(&mut eng, (exc(b"b"), inc(b"b")))?, all(&[]));
+        assert_eq!(keys(&mut eng, (exc(b"b"), exc(b"b")))?, all(&[]));
+        assert_eq!(keys(&mut eng, (inc(b"c"), inc(b"a")))?, all(&[]));
+        assert_eq!(keys(&mut eng, (exc(b"d"), exc(b"a")))?, all(&[]));
+        assert_eq!(keys(&mut eng, (inc(b"x"), Bound::Unbounded))?, all(&[]));
+        assert_eq!(eng.scan((inc(b"c"), inc(b"a"))).next_back().transpose()?, None);
+        Ok(())
+    }
+
+    // Prefix ending with 0xff
+    fn test_prefix_scan_carry(mut eng: impl Engine) -> Result<()> {
+        fn keys(eng: &mut impl Engine, prefix: Vec<u8>) -> Result<Vec<Vec<u8>>> {
+            eng.scan_prefix(prefix).map(|r| r.map(|(k, _)| k)).collect()
+        }
+        eng.set(vec![0x01, 0xfe, 0x00], b"value1".to_vec())?;
+        eng.set(vec![0x01, 0xff], b"value2".to_vec())?;
+        eng.set(vec![0x01, 0xff, 0xff, 0x07], b"value3".to_vec())?;
+        eng.set(vec![0x02], b"value4".to_vec())?;
+        eng.set(vec![0xff, 0xff, 0x01], b"value5".to_vec())?;
+
+        assert_eq!(keys(&mut eng, vec![0x01, 0xff])?, vec![vec![0x01, 0xff], vec![0x01, 0xff, 0xff, 0x07]]);
+        assert_eq!(keys(&mut eng, vec![0x01, 0xff, 0xff])?, vec![vec![0x01, 0xff, 0xff, 0x07]]);
+        assert_eq!(keys(&mut eng, vec![0xff])?, vec![vec![0xff, 0xff, 0x01]]);
+        assert_eq!(keys(&mut eng, vec![])?.len(), 5);
+        Ok(())
+    }
+
+    // Compare and set, a missing key is None
+    fn test_compare_and_set(mut eng: impl Engine) -> Result<()> {
+        let key = b"counter".to_vec();
+        assert!(!eng.compare_and_set(key.clone(), Some(vec![0]), Some(vec![1]))?);
+        assert_eq!(eng.get(key.clone())?, None);
+        assert!(eng.compare_and_set(key.clone(), None, Some(vec![1]))?);
+        assert!(!eng.compare_and_set(key.clone(), None, Some(vec![2]))?);
+        assert!(!eng.compare_and_set(key.clone(), Some(vec![2]), Some(vec![3]))?);
+        assert_eq!(eng.get(key.clone())?, Some(vec![1]));
+        assert!(eng.compare_and_set(key.clone(), Some(vec![1]), Some(vec![2]))?);
+        assert_eq!(eng.get(key.clone())?, Some(vec![2]));
+
+        // None as the new value deletes
+        assert!(!eng.compare_and_set(key.clone(), Some(vec![1]), None)?);
+        assert!(eng.compare_and_set(key.clone(), Some(vec![2]), None)?);
+        assert_eq!(eng.get(key.clone())?, None);
+        assert!(eng.compare_and_set(key.clone(), None, None)?);
+        assert_eq!(eng.scan(..).count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"aaaa"), Bound::Excluded(b"aaab".to_vec()));
+        assert_eq!(prefix_end(&[0x61, 0xff, 0xff]), Bound::Excluded(vec![0x62]));
+        assert_eq!(prefix_end(&[0xff, 0xff]), Bound::Unbounded);
+        assert_eq!(prefix_end(&[]), Bound::Unbounded);
+    }
     // Memory Engine
     #[test]
     fn test_memory_engine() -> Result<()> {
         test_point_opt(MemoryEngine::new())?;
         test_scan(MemoryEngine::new())?;
         test_prefix_scan(MemoryEngine::new())?;
+        test_prefix_scan_carry(MemoryEngine::new())?;
+        test_scan_bounds(MemoryEngine::new())?;
+        test_compare_and_set(MemoryEngine::new())?;
         Ok(())
     } 
 
@@ -131,6 +263,23 @@ mod tests {
         test_point_opt(DiskEngine::new(PathBuf::from("/tmp/db/db.log"))?)?;
         test_scan(DiskEngine::new(PathBuf::from("/tmp/db/db.log"))?)?;
         test_prefix_scan(DiskEngine::new(PathBuf::from("/tmp/db//db.log"))?)?;
+        let path = PathBuf::from("/tmp/db/prefix-carry.log");
+        let _ = std::fs::remove_file(&path);
+        test_prefix_scan_carry(DiskEngine::new(path)?)?;
+        let path = PathBuf::from("/tmp/db/scan-bounds.log");
+        let _ = std::fs::remove_file(&path);
+        test_scan_bounds(DiskEngine::new(path)?)?;
+        let path = PathBuf::from("/tmp/db/compare-and-set.log");
+        let _ = std::fs::remove_file(&path);
+        test_compare_and_set(DiskEngine::new(path.clone())?)?;
+
+        // The swaps are in the log like any write
+        let mut eng = DiskEngine::new(path.clone())?;
+        assert!(eng.compare_and_set(b"k".to_vec(), None, Some(b"v1".to_vec()))?);
+        assert!(eng.compare_and_set(b"k".to_vec(), Some(b"v1".to_vec()), Some(b"v2".to_vec()))?);
+        drop(eng);
+        let mut eng = DiskEngine::new(path)?;
+        assert_eq!(eng.get(b"k".to_vec())?, Some(b"v2".to_vec()));
         Ok(())
     }
 }
\ No newline at end of file
@@ -31,7 +31,8 @@ impl <'a> ser::Serializer for &'a mut Serializer {
     type SerializeStructVariant = serde::ser::Impossible<Self::Ok, Self::Error>;
 
     fn serialize_bool(self, v: bool) -> Result<()> {
-        todo!()
+        self.output.push(v.into());
+        Ok(())
     }
 
     fn serialize_i8(self, v: i8) -> Result<()> {
@@ -46,8 +47,9 @@ impl <'a> ser::Serializer for &'a mut Serializer {
         todo!()
     }
 
+    // Flip the sign bit, so negative numbers sort before positive ones
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.output.extend(v.to_be_bytes());
+        self.output.extend(((v as u64) ^ (1 << 63)).to_be_bytes());
         Ok(())
     }
 
@@ -72,17 +74,21 @@ impl <'a> ser::Serializer for &'a mut Serializer {
         todo!()
     }
 
+    // Positive: flip the sign bit; Negative: flip all bits
     fn serialize_f64(self, v: f64) -> Result<()> {
-        todo!()
+        let bits = v.to_bits();
+        let bits = if v.is_sign_negative() { !bits } else { bits ^ (1 << 63) };
+        self.output.extend(bits.to_be_bytes());
+        Ok(())
     }
 
     fn serialize_char(self, v: char) -> Result<()> {
         todo!()
     }
 
+    // Same as bytes, so that the string is terminated
     fn serialize_str(self, v: &str) -> Result<()> {
-        self.output.extend(v.as_bytes());
-        Ok(())
+        self.serialize_bytes(v.as_bytes())
     }
 
     // Original value        serialized
@@ -129,7 +135,9 @@ impl <'a> ser::Serializer for &'a mut Serializer {
         variant_index: u32,
         variant: &'static str,
     ) -> Result<()> {
-        self.output.extend(u8::try_from(variant_index));
+        let index = u8::try_from(variant_index)
+            .map_err(|_| Error::Internal(format!("Variant index {} of {} does not fit in a byte", variant_index, name)))?;
+        self.output.push(index);
         Ok(())
     }
 
@@ -155,8 +163,7 @@ impl <'a> ser::Serializer for &'a mut Serializer {
         T: ?Sized + ser::Serialize,
     {
         self.serialize_unit_variant(name, variant_index, variant)?;
-        value.serialize(self);
-        Ok(())
+        value.serialize(self)
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
@@ -269,10 +276,13 @@ pub struct Deserializer<'d> {
 
 
 impl<'d> Deserializer<'d>  {
-    fn take_bytes(&mut self, len: usize) -> &[u8] {
+    fn take_bytes(&mut self, len: usize) -> Result<&[u8]> {
+        if len > self.input.len() {
+            return Err(Error::Internal("unexpected end of key".into()));
+        }
         let bytes = &self.input[..len];
         self.input = &self.input[len..];
-        bytes
+        Ok(bytes)
     }
 
     // - if 255 after0, it is 0 in original string
@@ -309,7 +319,11 @@ impl<'a, 'd> de::Deserializer<'d> for &'a mut Deserializer<'d> {
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'d> {
-        todo!()
+        match self.take_bytes(1)?[0] {
+            0 => visitor.visit_bool(false),
+            1 => visitor.visit_bool(true),
+            b => Err(Error::Internal(format!("Unexpected bool byte {}", b))),
+        }
     }
     
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value>
@@ -333,8 +347,8 @@ impl<'a, 'd> de::Deserializer<'d> for &'a mut Deserializer<'d> {
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'d> {
-        let bytes = self.take_bytes(8);
-        let v = i64::from_be_bytes(bytes.try_into()?);
+        let bytes = self.take_bytes(8)?;
+        let v = (u64::from_be_bytes(bytes.try_into()?) ^ (1 << 63)) as i64;
         visitor.visit_i64(v)
     }
     
@@ -361,7 +375,7 @@ impl<'a, 'd> de::Deserializer<'d> for &'a mut Deserializer<'d> {
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'d> {
-        let bytes = self.take_bytes(8); // u64 for 8 bytes
+        let bytes = self.take_bytes(8)?; // u64 for 8 bytes
         
         let v = u64::from_be_bytes(bytes.try_into()?);
         visitor.visit_u64(v)
@@ -376,7 +390,10 @@ impl<'a, 'd> de::Deserializer<'d> for &'a mut Deserializer<'d> {
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'d> {
-        todo!()
+        let bytes = self.take_bytes(8)?;
+        let bits = u64::from_be_bytes(bytes.try_into()?);
+        let bits = if bits >> 63 == 1 { bits ^ (1 << 63) } else { !bits };
+        visitor.visit_f64(f64::from_bits(bits))
     }
     
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
@@ -395,7 +412,8 @@ impl<'a, 'd> de::Deserializer<'d> for &'a mut Deserializer<'d> {
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
         V: de::Visitor<'d> {
-        todo!()
+        let bytes = self.next_bytes()?;
+        visitor.visit_string(String::from_utf8(bytes)?)
     }
     
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
@@ -525,7 +543,7 @@ impl<'d, 'a> de::EnumAccess<'d> for &mut Deserializer<'d> {
     fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant)>
     where
         V: de::DeserializeSeed<'d> {
-        let index = self.take_bytes(1)[0] as u32;
+        let index = self.take_bytes(1)?[0] as u32;
         let variant_index: Result<_> = seed.deserialize(index.into_deserializer());
         Ok((variant_index?, self))
     }
@@ -625,4 +643,70 @@ mod tests {
         der_cmp(MvccKey::TxnWrite(1, vec![1,2,3]), vec![2, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 3, 0, 0]);
         der_cmp(MvccKey::Version(b"abc".to_vec(), 11), vec![3, 97, 98, 99, 0, 0, 0, 0, 0, 0, 0, 0, 0, 11]);        
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_encode_value_order() {
+        use crate::sql::types::Value;
+
+        let values = [
+            Value::Integer(i64::MIN),
+            Value::Integer(-5),
+            Value::Integer(0),
+            Value::Integer(3),
+            Value::Integer(i64::MAX),
+        ];
+        let encoded = values.iter().map(|v| serialize_key(v).unwrap()).collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+        let values = [Value::Float(-2.5), Value::Float(-0.5), Value::Float(0.0), Value::Float(1.5)];
+        let encoded = values.iter().map(|v| serialize_key(v).unwrap()).collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|w| w[0] < w[1]));
+
+        let values = [Value::Boolean(false), Value::Boolean(true)];
+        let encoded = values.iter().map(|v| serialize_key(v).unwrap()).collect::<Vec<_>>();
+        assert!(encoded[0] < encoded[1]);
+
+        // strings are terminated, "a" must not be a prefix of "ab"
+        let a = serialize_key(&Value::String("a".into())).unwrap();
+        let ab = serialize_key(&Value::String("ab".into())).unwrap();
+        assert!(a < ab && !ab.starts_with(&a));
+
+        for v in [Value::Null, Value::Boolean(true), Value::Integer(-7), Value::Float(-1.25), Value::String("apple".into())] {
+            let res: Value = deserialize_key(&serialize_key(&v).unwrap()).unwrap();
+            assert_eq!(res, v);
+        }
+    }
+
+    #[test]
+    fn test_decode_truncated() {
+        let mut v = serialize_key(&MvccKey::TxnActive(1)).unwrap();
+        v.truncate(5);
+        let res: crate::error::Result<MvccKey> = deserialize_key(&v);
+        assert_eq!(res, Err(crate::error::Error::Internal("unexpected end of key".into())));
+
+        let res: crate::error::Result<MvccKey> = deserialize_key(&[]);
+        assert!(res.is_err());
+    }
+
+    // A value that fails to encode, like a Value variant keycode does not know
+    struct Unsupported;
+
+    impl serde::Serialize for Unsupported {
+        fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+            Err(serde::ser::Error::custom("unsupported value"))
+        }
+    }
+
+    #[derive(serde::Serialize)]
+    enum TestKey {
+        Wrap(Unsupported),
+        Row(String, Vec<Unsupported>),
+    }
+
+    #[test]
+    fn test_encode_error_propagation() {
+        assert!(serialize_key(&TestKey::Wrap(Unsupported)).is_err());
+        // Deep inside a sequence inside a variant, same shape as Key::Row
+        assert!(serialize_key(&TestKey::Row("tbl".into(), vec![Unsupported])).is_err());
+    }
+}
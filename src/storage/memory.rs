@@ -10,6 +10,19 @@ impl MemoryEngine {
             data: BTreeMap::new()
         }
     }
+
+    // Drop all the data, so the engine can be reused
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
 }
 
 impl super::engine::Engine for MemoryEngine {
@@ -33,7 +46,7 @@ impl super::engine::Engine for MemoryEngine {
     
     fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator <'_>{
         MemoryEngineIterator {
-            inner: self.data.range(range)
+            inner: self.data.range(super::engine::checked_range(range))
         }
     }
 
@@ -64,4 +77,28 @@ impl<'a> DoubleEndedIterator for MemoryEngineIterator<'a> {
     fn next_back(&mut self) -> Option<Self::Item> {
         self.inner.next_back().map(Self::map)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::{error::Result, storage::engine::Engine};
+    use super::MemoryEngine;
+
+    #[test]
+    fn test_memory_engine_clear() -> Result<()> {
+        let mut eng = MemoryEngine::new();
+        assert!(eng.is_empty());
+        eng.set(b"key1".to_vec(), b"value1".to_vec())?;
+        eng.set(b"key2".to_vec(), b"value2".to_vec())?;
+        assert_eq!(eng.len(), 2);
+
+        eng.clear();
+        assert_eq!(eng.get(b"key1".to_vec())?, None);
+        assert_eq!(eng.len(), 0);
+        assert!(eng.is_empty());
+
+        // Still usable afterwards
+        eng.set(b"key1".to_vec(), b"value3".to_vec())?;
+        assert_eq!(eng.get(b"key1".to_vec())?, Some(b"value3".to_vec()));
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use std::{collections::{BTreeMap, HashSet}, sync::{Arc, Mutex, MutexGuard}, u64};
+use std::{collections::{BTreeMap, HashSet}, ops::{Bound, RangeBounds}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex, MutexGuard}};
 use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
@@ -6,27 +6,115 @@ use super::{engine::Engine, keycode::{deserialize_key,serialize_key}};
 
 pub struct Mvcc<E: Engine>{
     engine: Arc<Mutex<E>>,
+    // Open AS OF transactions, they are not in the active set
+    readers: Arc<AtomicUsize>,
 }
 
 impl<E: Engine> Clone for Mvcc<E> {
     fn clone(&self) -> Self {
-        Self { engine: self.engine.clone() }
+        Self { engine: self.engine.clone(), readers: self.readers.clone() }
     }    
 }
 
 impl<E: Engine> Mvcc<E> {
     pub fn new(eng:E) -> Self {
-        Self{ engine: Arc::new(Mutex::new(eng)) }
+        Self{ engine: Arc::new(Mutex::new(eng)), readers: Arc::new(AtomicUsize::new(0)) }
     }
 
     pub fn begin(&self) -> Result<MvccTransaction<E>> {
         MvccTransaction::begin(self.engine.clone())
     }
+
+    // Read-only transaction seeing the data as of a past version
+    pub fn begin_as_of(&self, version: Version) -> Result<MvccTransaction<E>> {
+        MvccTransaction::begin_as_of(self.engine.clone(), version, self.readers.clone())
+    }
+
+    // Transaction that also aborts on write skew, see ReadSpan
+    pub fn begin_serializable(&self) -> Result<MvccTransaction<E>> {
+        MvccTransaction::begin_serializable(self.engine.clone())
+    }
+
+    // Remove the versions no new transaction can see: all but the latest
+    // of each key, and the latest too if it is a delete. The history read
+    // by AS OF is gone afterwards, reads before the newest version are
+    // refused from then on. Only runs without active transactions or
+    // open AS OF reads, then the storage is compacted
+    pub fn vacuum(&self) -> Result<VacuumStats> {
+        let mut engine = self.engine.lock()?;
+        // AS OF reads register under the same lock, none can start meanwhile
+        if !MvccTransaction::scan_txnactive(&mut engine)?.is_empty() || self.readers.load(Ordering::SeqCst) > 0 {
+            return Err(Error::Internal("Cannot vacuum while transactions are active".into()));
+        }
+        let size = engine.size()?;
+        let next_version: Version = match engine.get(MvccKey::NextVersion.encode()?)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => 1,
+        };
+
+        // Versions of a key are next to each other, oldest first
+        let mut prefix = MvccKeyPrefix::Version(vec![]).encode()?;
+        prefix.truncate(1);
+        let mut garbage = Vec::new();
+        let mut latest: Option<(Vec<u8>, Vec<u8>, bool)> = None;
+        let mut iter = engine.scan_prefix(prefix);
+        while let Some((key, value)) = iter.next().transpose()? {
+            let raw_key = match MvccKey::decode(key.clone())? {
+                MvccKey::Version(raw_key, _) => raw_key,
+                _ => return Err(Error::Internal(format!("Unexpected key: {:?}", String::from_utf8(key)))),
+            };
+            let deleted = bincode::deserialize::<Option<Vec<u8>>>(&value)?.is_none();
+            if let Some((last_raw, last_key, last_deleted)) = latest.replace((raw_key, key, deleted)) {
+                if last_deleted || latest.as_ref().is_some_and(|(raw_key, _, _)| *raw_key == last_raw) {
+                    garbage.push(last_key);
+                }
+            }
+        }
+        if let Some((_, key, true)) = latest {
+            garbage.push(key);
+        }
+        drop(iter);
+
+        // Written first, a crash halfway never leaves old reads unguarded
+        engine.set(MvccKey::VacuumHorizon.encode()?, bincode::serialize(&(next_version - 1))?)?;
+        for key in garbage.iter() {
+            engine.delete(key.clone())?;
+        }
+        engine.compact()?;
+        engine.flush()?;
+        Ok(VacuumStats { versions: garbage.len(), bytes: size.saturating_sub(engine.size()?) })
+    }
+}
+
+// What a vacuum removed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VacuumStats {
+    pub versions: usize,
+    pub bytes: u64,
 }
 
 pub struct MvccTransaction<E: Engine> {
     engine: Arc<Mutex<E>>,
     state: TransactionState,
+    // Pinned at a past version, writes are rejected
+    read_only: bool,
+    // Read set, only tracked in serializable mode
+    reads: Option<Mutex<Vec<ReadSpan>>>,
+    // Committed or rolled back
+    finished: AtomicBool,
+    // Once set, scans stop with "query cancelled"
+    cancel: Option<Arc<AtomicBool>>,
+    // The reader count of an AS OF transaction, released when it finishes
+    reader: Option<Arc<AtomicUsize>>,
+}
+
+// What a serializable transaction has read, as encoded Version keys.
+// At commit, any version in these spans written by a concurrent
+// transaction that has committed means the reads are stale, so
+// write skew across different keys is detected too.
+enum ReadSpan {
+    Prefix(Vec<u8>),
+    Range(Bound<Vec<u8>>, Bound<Vec<u8>>),
 }
 
 pub struct TransactionState {
@@ -61,6 +149,8 @@ pub enum MvccKey {
         Vec<u8>, 
         Version
     ),
+    // The oldest version AS OF can read, the history before it was vacuumed
+    VacuumHorizon,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -94,6 +184,26 @@ impl MvccKey {
     pub fn decode(data: Vec<u8>) -> Result<Self> {
         deserialize_key(&data)
     }
+
+    // Encoded bounds of the versions lo..=hi of the raw key,
+    // both are inclusive: key-lo ... key-hi
+    pub fn version_range(raw_key: &[u8], lo: Version, hi: Version) -> Result<(Vec<u8>, Vec<u8>)> {
+        Ok((
+            MvccKey::Version(raw_key.to_vec(), lo).encode()?,
+            MvccKey::Version(raw_key.to_vec(), hi).encode()?,
+        ))
+    }
+}
+
+// An AS OF transaction dropped without finishing stops holding off vacuum
+impl<E: Engine> Drop for MvccTransaction<E> {
+    fn drop(&mut self) {
+        if !self.finished.load(Ordering::SeqCst) {
+            if let Some(readers) = &self.reader {
+                readers.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+    }
 }
 
 impl<E: Engine> MvccTransaction<E> {
@@ -110,14 +220,21 @@ impl<E: Engine> MvccTransaction<E> {
         };
 
         // 2. Save the next version
+        // Written before the active marker: a crash in between only skips
+        // the version, it can never be handed out twice after recovery
         engine.set(MvccKey::NextVersion.encode()?, bincode::serialize(&(next_version + 1))?)?;
 
         // 3. Get the current snapshot
+        // Taken before our own marker, so our version is never in it and
+        // is_visible keeps our own writes visible to us
         let active_versions = Self::scan_txnactive(&mut engine)?;
 
         // 4. Add current transaction into snapshot
         engine.set(MvccKey::TxnActive(next_version).encode()?, vec![])?;
 
+        // Both are durable before the version is used
+        engine.flush()?;
+
         // 5. Return the MvccTransaction
         Ok(
             Self {
@@ -125,16 +242,101 @@ impl<E: Engine> MvccTransaction<E> {
                 state: TransactionState {
                     version: next_version,
                     active_versions,
-                }
+                },
+                read_only: false,
+                reads: None,
+                finished: AtomicBool::new(false),
+                cancel: None,
+                reader: None,
              }
         )
     }
 
+    // Begin a transaction with read-set tracking
+    pub fn begin_serializable(eng: Arc<Mutex<E>>) -> Result<Self> {
+        let mut txn = Self::begin(eng)?;
+        txn.reads = Some(Mutex::new(Vec::new()));
+        Ok(txn)
+    }
+
+    // Begin a read-only transaction pinned at a past version
+    // Nothing is registered as active, the snapshot only hides
+    // the transactions that are still running now. It is counted
+    // in readers instead, so vacuum waits until it finishes.
+    pub fn begin_as_of(eng: Arc<Mutex<E>>, version: Version, readers: Arc<AtomicUsize>) -> Result<Self> {
+        let mut engine = eng.lock()?;
+
+        let next_version: Version = match engine.get(MvccKey::NextVersion.encode()?)? {
+            Some(value) => bincode::deserialize(&value)?,
+            None => 1,
+        };
+        if version >= next_version {
+            return Err(Error::Internal(format!("Version {} does not exist yet", version)));
+        }
+        if let Some(value) = engine.get(MvccKey::VacuumHorizon.encode()?)? {
+            let horizon: Version = bincode::deserialize(&value)?;
+            if version < horizon {
+                return Err(Error::Internal(format!("Version {} was removed by VACUUM, the oldest is {}", version, horizon)));
+            }
+        }
+
+        let active_versions = Self::scan_txnactive(&mut engine)?;
+        readers.fetch_add(1, Ordering::SeqCst);
+
+        Ok(
+            Self {
+                engine: eng.clone(),
+                state: TransactionState {
+                    version,
+                    active_versions,
+                },
+                read_only: true,
+                reads: None,
+                finished: AtomicBool::new(false),
+                cancel: None,
+                reader: Some(readers),
+            }
+        )
+    }
+
+    pub fn version(&self) -> Version {
+        self.state.version
+    }
+
+    pub fn set_cancel(&mut self, token: Option<Arc<AtomicBool>>) {
+        self.cancel = token;
+    }
+
     // Txn Commit
+    // Finished only once the commit point is written, a commit that
+    // fails before it can be retried or rolled back
     pub fn commit(&self) -> Result<()> {
+        self.check_unfinished()?;
+
+        // The pinned version belongs to another transaction
+        if self.read_only {
+            return self.finish();
+        }
+
         // Get the storage engine
         let mut engine = self.engine.lock()?;
-        
+
+        // Abort if anything read has been changed since, checked under
+        // the same lock as the writes so no commit lands in between
+        if self.has_read_conflict(&mut engine)? {
+            drop(engine);
+            self.rollback_writes()?;
+            self.finish()?;
+            return Err(Error::WriteConflict);
+        }
+
+        // Leaving the active set is the commit point, nothing before it
+        // has changed the storage
+        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)?;
+        self.finish()?;
+
+        // The write records of a committed version are never read again,
+        // a failure from here leaves them behind but the commit stands
         let mut delete_keys = Vec::new();
 
         // Get the current TxnWrite
@@ -149,13 +351,40 @@ impl<E: Engine> MvccTransaction<E> {
             engine.delete(key)?;
         }
 
-        // Delete from active_txn
-        engine.delete(MvccKey::TxnActive(self.state.version).encode()?)?;
+        // Make the commit durable
+        engine.flush()?;
         Ok(())
     }
 
     // Txn Rollback
     pub fn rollback(&self) -> Result<()> {
+        self.check_unfinished()?;
+        if !self.read_only {
+            self.rollback_writes()?;
+        }
+        self.finish()
+    }
+
+    fn check_unfinished(&self) -> Result<()> {
+        if self.finished.load(Ordering::SeqCst) {
+            return Err(Error::Internal("transaction already finished".into()));
+        }
+        Ok(())
+    }
+
+    // A transaction is committed or rolled back only once
+    fn finish(&self) -> Result<()> {
+        if self.finished.swap(true, Ordering::SeqCst) {
+            return Err(Error::Internal("transaction already finished".into()));
+        }
+        if let Some(readers) = &self.reader {
+            readers.fetch_sub(1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
+    // Undo the writes and leave the active set
+    fn rollback_writes(&self) -> Result<()> {
         // Obtain engine
         let mut engine = self.engine.lock()?;
 
@@ -201,8 +430,9 @@ impl<E: Engine> MvccTransaction<E> {
 
         // Version: 9
         // Scan range: 0 - 9
-        let from = MvccKey::Version(key.clone(), 0).encode()?;
-        let to = MvccKey::Version(key.clone(), self.state.version).encode()?;
+        let (from, to) = MvccKey::version_range(&key, 0, self.state.version)?;
+        let (_, last) = MvccKey::version_range(&key, 0, u64::MAX)?;
+        self.record_read(ReadSpan::Range(Bound::Included(from.clone()), Bound::Included(last)))?;
         let mut iter = engine.scan(from..=to).rev();
 
         // Start from latest, find the latest visible
@@ -233,37 +463,137 @@ impl<E: Engine> MvccTransaction<E> {
         // Remove the [0, 0] end
 
         enc_prefix.truncate(enc_prefix.len() - 2); 
+        self.record_read(ReadSpan::Prefix(enc_prefix.clone()))?;
 
-        let mut iter = eng.scan_prefix(enc_prefix);
-        let mut results = BTreeMap::new();
-        while let Some((key, value)) = iter.next().transpose()? {
-            match MvccKey::decode(key.clone())? {
-                MvccKey::Version(raw_key, version) => {
-                    if self.state.is_visible(version) {
-                        match bincode::deserialize(&value)? {
-                            Some(raw_value) => results.insert(raw_key, raw_value),
-                            None => results.remove(&raw_key),
-                        };
-                    }
-                }
-                _ => {
-                    return Err(Error::Internal(format!("Unexpected key {:?}", String::from_utf8(key))))
-                }
+        let iter = eng.scan_prefix(enc_prefix);
+        self.collect_visible(iter, None)
+    }
+
+    // Scan the raw key range, return the latest visible value of each key
+    pub fn scan(&self, range: impl RangeBounds<Vec<u8>>) -> Result<Vec<ScanResult>> {
+        self.scan_range(range, None)
+    }
+
+    // Scan only the first limit visible keys of the range,
+    // the engine is not read past them
+    pub fn scan_first(&self, range: impl RangeBounds<Vec<u8>>, limit: usize) -> Result<Vec<ScanResult>> {
+        self.scan_range(range, Some(limit))
+    }
+
+    fn scan_range(&self, range: impl RangeBounds<Vec<u8>>, limit: Option<usize>) -> Result<Vec<ScanResult>> {
+        let mut eng = self.engine.lock()?;
+
+        // key   -> key-0 ... key-MAX
+        let start = match range.start_bound() {
+            Bound::Included(k) => Bound::Included(MvccKey::Version(k.clone(), 0).encode()?),
+            Bound::Excluded(k) => Bound::Excluded(MvccKey::Version(k.clone(), u64::MAX).encode()?),
+            Bound::Unbounded => Bound::Included(MvccKey::Version(vec![], 0).encode()?),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) => Bound::Included(MvccKey::Version(k.clone(), u64::MAX).encode()?),
+            Bound::Excluded(k) => Bound::Excluded(MvccKey::Version(k.clone(), 0).encode()?),
+            Bound::Unbounded => {
+                // Only keep the variant byte of Version, and move to the next one
+                let mut bound = MvccKeyPrefix::Version(vec![]).encode()?;
+                bound.truncate(1);
+                bound[0] += 1;
+                Bound::Excluded(bound)
             }
+        };
+
+        self.record_read(ReadSpan::Range(start.clone(), end.clone()))?;
+
+        let iter = eng.scan((start, end));
+        self.collect_visible(iter, limit)
+    }
+
+    // Write the entries without the per-key conflict check.
+    // Only done when no other transaction is active and nothing was
+    // ever written in the range, else returns false and writes nothing.
+    pub fn bulk_load(&self, range: impl RangeBounds<Vec<u8>>, entries: &[(Vec<u8>, Vec<u8>)]) -> Result<bool> {
+        if self.read_only {
+            return Err(Error::Internal(format!("Cannot write in a read-only transaction at version {}", self.state.version)));
+        }
+        if let Some((key, _)) = entries.iter().find(|(k, _)| !range.contains(k)) {
+            return Err(Error::Internal(format!("Bulk load key {:?} is out of range", key)));
         }
 
-        Ok(results
-            .into_iter()
-            .map(|(key, value)| ScanResult {key, value})
-            .collect())
+        let mut engine = self.engine.lock()?;
+        if Self::scan_txnactive(&mut engine)?.into_iter().any(|v| v != self.state.version) {
+            return Ok(false);
+        }
+
+        let start = match range.start_bound() {
+            Bound::Included(k) => Bound::Included(MvccKey::Version(k.clone(), 0).encode()?),
+            Bound::Excluded(k) => Bound::Excluded(MvccKey::Version(k.clone(), u64::MAX).encode()?),
+            Bound::Unbounded => return Ok(false),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(k) => Bound::Included(MvccKey::Version(k.clone(), u64::MAX).encode()?),
+            Bound::Excluded(k) => Bound::Excluded(MvccKey::Version(k.clone(), 0).encode()?),
+            Bound::Unbounded => return Ok(false),
+        };
+        // Any version counts, deleted or not
+        if engine.scan((start, end)).next().is_some() {
+            return Ok(false);
+        }
+
+        for (key, value) in entries {
+            engine.set(MvccKey::TxnWrite(self.state.version, key.clone()).encode()?, vec![])?;
+            engine.set(MvccKey::Version(key.clone(), self.state.version).encode()?, bincode::serialize(&Some(value))?)?;
+        }
+        Ok(true)
     }
 
     // -+------------------------+-
     //      Auxilliary Part
     // -+------------------------+- 
 
+    fn record_read(&self, span: ReadSpan) -> Result<()> {
+        if let Some(reads) = &self.reads {
+            reads.lock()?.push(span);
+        }
+        Ok(())
+    }
+
+    // Check the read set against versions committed by concurrent transactions
+    fn has_read_conflict(&self, engine: &mut MutexGuard<E>) -> Result<bool> {
+        let reads = match &self.reads {
+            Some(reads) => reads.lock()?,
+            None => return Ok(false),
+        };
+        let active_now = Self::scan_txnactive(engine)?;
+
+        for span in reads.iter() {
+            let keys = match span {
+                ReadSpan::Prefix(prefix) => engine.scan_prefix(prefix.clone()).collect::<Result<Vec<_>>>()?,
+                ReadSpan::Range(start, end) => engine.scan((start.clone(), end.clone())).collect::<Result<Vec<_>>>()?,
+            };
+            for (key, _) in keys {
+                match MvccKey::decode(key.clone())? {
+                    MvccKey::Version(_, version) => {
+                        // Invisible to us, but committed by now
+                        if version != self.state.version
+                            && !self.state.is_visible(version)
+                            && !active_now.contains(&version) {
+                            return Ok(true);
+                        }
+                    }
+                    _ => {
+                        return Err(Error::Internal(format!("Unexpected key: {:?}", String::from_utf8(key))));
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
     // Update/Delete data
     fn write_inner(&self, key: Vec<u8>, value: Option<Vec<u8>>) -> Result<()> {
+        if self.read_only {
+            return Err(Error::Internal(format!("Cannot write in a read-only transaction at version {}", self.state.version)));
+        }
+
         // Obtain the storage engine
         let mut engine = self.engine.lock()?;
 
@@ -271,8 +601,8 @@ impl<E: Engine> MvccTransaction<E> {
         // 3 4 5 
         // 6
         // key1-3 key2-4 key3-5
-        let from = MvccKey::Version(key.clone(), self.state.active_versions.iter().min().copied().unwrap_or(self.state.version + 1)).encode()?;
-        let to = MvccKey::Version(key.clone(), u64::MAX).encode()?;
+        let oldest_active = self.state.active_versions.iter().min().copied().unwrap_or(self.state.version + 1);
+        let (from, to) = MvccKey::version_range(&key, oldest_active, u64::MAX)?;
 
         // Current actice: 3 4 5 
         // Current txn 6
@@ -302,6 +632,44 @@ impl<E: Engine> MvccTransaction<E> {
         Ok(())
     }
 
+    // Keep the latest visible version of each key, skip the deleted ones
+    fn collect_visible(&self, mut iter: impl Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>, limit: Option<usize>) -> Result<Vec<ScanResult>> {
+        let mut results = BTreeMap::new();
+        let mut last_key = None;
+        while let Some((key, value)) = iter.next().transpose()? {
+            // Checked per entry read, a long scan stops while it reads
+            if self.cancel.as_ref().is_some_and(|token| token.load(Ordering::Relaxed)) {
+                return Err(Error::Internal("query cancelled".into()));
+            }
+            match MvccKey::decode(key.clone())? {
+                MvccKey::Version(raw_key, version) => {
+                    // All versions of a key come before the next key, so
+                    // the keys seen so far are final once another one starts
+                    if last_key.as_ref() != Some(&raw_key) {
+                        if limit.is_some_and(|limit| results.len() >= limit) {
+                            break;
+                        }
+                        last_key = Some(raw_key.clone());
+                    }
+                    if self.state.is_visible(version) {
+                        match bincode::deserialize(&value)? {
+                            Some(raw_value) => results.insert(raw_key, raw_value),
+                            None => results.remove(&raw_key),
+                        };
+                    }
+                }
+                _ => {
+                    return Err(Error::Internal(format!("Unexpected key {:?}", String::from_utf8(key))))
+                }
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|(key, value)| ScanResult {key, value})
+            .collect())
+    }
+
     // Scan current active transactions
     fn scan_txnactive(engine: &mut MutexGuard<E>) -> Result<HashSet<Version>> {
         let mut active_versions = HashSet::new();
@@ -336,7 +704,7 @@ mod tests {
         storage::{disk::DiskEngine, engine::Engine, memory::MemoryEngine}
     };
 
-    use super::{Mvcc, MvccKey};
+    use super::{Mvcc, MvccKey, ScanResult};
 
     // 1. Get
     fn get(eng: impl Engine) -> Result<()> {
@@ -904,4 +1272,512 @@ mod tests {
         std::fs::remove_dir_all(p.parent().unwrap())?;
         Ok(())
     } 
-}
\ No newline at end of file
+
+    // 13. Scan range
+    fn scan_range(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"a".to_vec(), b"val1".to_vec())?;
+        tx.set(b"b".to_vec(), b"val2".to_vec())?;
+        tx.set(b"c".to_vec(), b"val3".to_vec())?;
+        tx.set(b"d".to_vec(), b"val4".to_vec())?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        tx1.delete(b"c".to_vec())?;
+        tx1.set(b"bb".to_vec(), b"val5".to_vec())?;
+
+        let keys = |res: Vec<super::ScanResult>| res.into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys(tx1.scan(b"b".to_vec()..=b"d".to_vec())?), vec![b"b".to_vec(), b"bb".to_vec(), b"d".to_vec()]);
+        assert_eq!(keys(tx1.scan(b"b".to_vec()..b"d".to_vec())?), vec![b"b".to_vec(), b"bb".to_vec()]);
+        assert_eq!(keys(tx1.scan(..b"b".to_vec())?), vec![b"a".to_vec()]);
+        assert_eq!(keys(tx1.scan(b"bb".to_vec()..)?), vec![b"bb".to_vec(), b"d".to_vec()]);
+        tx1.commit()?;
+
+        // Uncommitted writes are not visible
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"e".to_vec(), b"val6".to_vec())?;
+        let tx3 = mvcc.begin()?;
+        assert_eq!(keys(tx3.scan(..)?), vec![b"a".to_vec(), b"b".to_vec(), b"bb".to_vec(), b"d".to_vec()]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_range() -> Result<()> {
+        scan_range(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        scan_range(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 14. As of version
+    fn as_of_version(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+        let old = tx.version();
+
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"val2".to_vec())?;
+        tx1.set(b"key2".to_vec(), b"val3".to_vec())?;
+        tx1.commit()?;
+
+        // Uncommitted writes stay hidden even below the pinned version
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"key3".to_vec(), b"val4".to_vec())?;
+
+        let past = mvcc.begin_as_of(old)?;
+        assert_eq!(past.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(past.get(b"key2".to_vec())?, None);
+        assert!(past.set(b"key1".to_vec(), b"val5".to_vec()).is_err());
+        past.commit()?;
+
+        let now = mvcc.begin_as_of(tx2.version())?;
+        assert_eq!(now.get(b"key1".to_vec())?, Some(b"val2".to_vec()));
+        assert_eq!(now.get(b"key3".to_vec())?, None);
+        now.rollback()?;
+
+        // Closing the pinned transactions leaves tx2 untouched
+        tx2.commit()?;
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.get(b"key3".to_vec())?, Some(b"val4".to_vec()));
+
+        assert!(mvcc.begin_as_of(tx3.version() + 1).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_as_of_version() -> Result<()> {
+        as_of_version(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        as_of_version(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 15. Write skew
+    fn write_skew(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"alice".to_vec(), b"on".to_vec())?;
+        tx.set(b"bob".to_vec(), b"on".to_vec())?;
+        tx.commit()?;
+
+        // Both see two doctors on call, and each one leaves
+        let on_call = |tx: &super::MvccTransaction<_>| -> Result<usize> {
+            Ok(tx.scan_prefix(vec![])?.into_iter().filter(|r| r.value == b"on".to_vec()).count())
+        };
+
+        // Snapshot isolation lets both go
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        assert_eq!(on_call(&tx1)?, 2);
+        assert_eq!(on_call(&tx2)?, 2);
+        tx1.set(b"alice".to_vec(), b"off".to_vec())?;
+        tx2.set(b"bob".to_vec(), b"off".to_vec())?;
+        tx1.commit()?;
+        tx2.commit()?;
+
+        let tx = mvcc.begin()?;
+        tx.set(b"alice".to_vec(), b"on".to_vec())?;
+        tx.set(b"bob".to_vec(), b"on".to_vec())?;
+        tx.commit()?;
+
+        // Serializable mode aborts the second one
+        let tx3 = mvcc.begin_serializable()?;
+        let tx4 = mvcc.begin_serializable()?;
+        assert_eq!(on_call(&tx3)?, 2);
+        assert_eq!(on_call(&tx4)?, 2);
+        tx3.set(b"alice".to_vec(), b"off".to_vec())?;
+        tx4.set(b"bob".to_vec(), b"off".to_vec())?;
+        tx3.commit()?;
+        assert_eq!(tx4.commit(), Err(super::Error::WriteConflict));
+
+        let tx5 = mvcc.begin_serializable()?;
+        assert_eq!(tx5.get(b"bob".to_vec())?, Some(b"on".to_vec()));
+        assert_eq!(on_call(&tx5)?, 1);
+        tx5.commit()?;
+
+        // Single key reads are tracked too
+        let tx6 = mvcc.begin_serializable()?;
+        let tx7 = mvcc.begin()?;
+        tx6.get(b"alice".to_vec())?;
+        tx7.set(b"alice".to_vec(), b"on".to_vec())?;
+        tx7.commit()?;
+        tx6.set(b"bob".to_vec(), b"off".to_vec())?;
+        assert_eq!(tx6.commit(), Err(super::Error::WriteConflict));
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_skew() -> Result<()> {
+        write_skew(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        write_skew(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 16. Finished transactions
+    fn finished(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+        assert_eq!(tx.commit(), Err(super::Error::Internal("transaction already finished".into())));
+        assert_eq!(tx.rollback(), Err(super::Error::Internal("transaction already finished".into())));
+
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx1.rollback()?;
+        assert!(tx1.rollback().is_err());
+        assert!(tx1.commit().is_err());
+
+        let past = mvcc.begin_as_of(tx.version())?;
+        past.commit()?;
+        assert!(past.rollback().is_err());
+
+        // Nothing was undone by the second calls
+        let tx2 = mvcc.begin()?;
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx2.get(b"key2".to_vec())?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_finished() -> Result<()> {
+        finished(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        finished(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 17. Bulk load
+    fn bulk_load(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let entries = (0..100u8).map(|i| (vec![b'k', i], vec![i])).collect::<Vec<_>>();
+        let range = || vec![b'k']..vec![b'l'];
+
+        let tx = mvcc.begin()?;
+        assert!(tx.bulk_load(range(), &entries)?);
+        // Written once, the range is not empty any more
+        assert!(!tx.bulk_load(range(), &entries)?);
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        assert_eq!(tx1.scan_prefix(vec![b'k'])?.len(), 100);
+        assert_eq!(tx1.get(vec![b'k', 42])?, Some(vec![42]));
+
+        // Refused while another transaction is active
+        let others = vec![(vec![b'x', 1], vec![1])];
+        let tx2 = mvcc.begin()?;
+        assert!(!tx2.bulk_load(vec![b'x']..vec![b'y'], &others)?);
+        tx1.commit()?;
+        assert!(tx2.bulk_load(vec![b'x']..vec![b'y'], &others)?);
+        assert!(tx2.bulk_load(vec![b'y']..vec![b'z'], &entries).is_err());
+        tx2.rollback()?;
+
+        // Rolled back like any other write
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.get(vec![b'k', 0])?, Some(vec![0]));
+        assert_eq!(tx3.get(vec![b'x', 1])?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_load() -> Result<()> {
+        bulk_load(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        bulk_load(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 18. Scan prefix with keys that are prefixes of each other
+    fn scan_prefix_nested(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key12".to_vec(), b"val3".to_vec())?;
+        tx.set(b"key".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key1".to_vec(), b"val2".to_vec())?;
+        tx.set(b"key\x00".to_vec(), b"val4".to_vec())?;
+        tx.set(b"ke".to_vec(), b"val5".to_vec())?;
+        tx.set(b"kez".to_vec(), b"val6".to_vec())?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        let keys = |res: Vec<super::ScanResult>| res.into_iter().map(|r| r.key).collect::<Vec<_>>();
+        assert_eq!(keys(tx1.scan_prefix(b"key".to_vec())?),
+            vec![b"key".to_vec(), b"key\x00".to_vec(), b"key1".to_vec(), b"key12".to_vec()]);
+        assert_eq!(keys(tx1.scan_prefix(b"key1".to_vec())?), vec![b"key1".to_vec(), b"key12".to_vec()]);
+        assert_eq!(keys(tx1.scan_prefix(b"key12".to_vec())?), vec![b"key12".to_vec()]);
+        assert_eq!(keys(tx1.scan_prefix(b"ke".to_vec())?).len(), 6);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_prefix_nested() -> Result<()> {
+        scan_prefix_nested(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        scan_prefix_nested(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // Records the writes that reached the engine, and crashes once
+    // the given number of writes is used up
+    type WriteLog = std::sync::Arc<std::sync::Mutex<Vec<(Vec<u8>, Option<Vec<u8>>)>>>;
+
+    struct CrashEngine {
+        inner: MemoryEngine,
+        log: WriteLog,
+        writes_left: usize,
+        flushes: usize,
+    }
+
+    impl CrashEngine {
+        fn write(&mut self, key: Vec<u8>, value: Option<Vec<u8>>) -> Result<()> {
+            if self.writes_left == 0 {
+                return Err(super::Error::Internal("crashed".into()));
+            }
+            self.writes_left -= 1;
+            self.log.lock()?.push((key.clone(), value.clone()));
+            match value {
+                Some(value) => self.inner.set(key, value),
+                None => self.inner.delete(key),
+            }
+        }
+    }
+
+    impl Engine for CrashEngine {
+        type EngineIterator<'a> = crate::storage::memory::MemoryEngineIterator<'a>;
+
+        fn set(&mut self, key: Vec<u8>, value: Vec<u8>) -> Result<()> {
+            self.write(key, Some(value))
+        }
+
+        fn get(&mut self, key: Vec<u8>) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+
+        fn delete(&mut self, key: Vec<u8>) -> Result<()> {
+            self.write(key, None)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            self.flushes += 1;
+            Ok(())
+        }
+
+        fn scan(&mut self, range: impl std::ops::RangeBounds<Vec<u8>>) -> Self::EngineIterator<'_> {
+            self.inner.scan(range)
+        }
+    }
+
+    // 19. Crash in begin
+    #[test]
+    fn test_begin_crash() -> Result<()> {
+        let log = WriteLog::default();
+        // begin writes NextVersion, then TxnActive
+        let mvcc = Mvcc::new(CrashEngine { inner: MemoryEngine::new(), log: log.clone(), writes_left: 3, flushes: 0 });
+        let tx1 = mvcc.begin()?;
+        assert_eq!(tx1.version(), 1);
+        assert_eq!(mvcc.engine.lock()?.flushes, 1);
+
+        // Crash between the NextVersion and the TxnActive writes
+        assert!(mvcc.begin().is_err());
+
+        // Recover from what reached the engine
+        let mut recovered = MemoryEngine::new();
+        for (key, value) in log.lock()?.drain(..) {
+            match value {
+                Some(value) => recovered.set(key, value)?,
+                None => recovered.delete(key)?,
+            }
+        }
+        let mvcc = Mvcc::new(recovered);
+        let tx2 = mvcc.begin()?;
+        // Version 2 was lost with the crash, but never reused
+        assert_eq!(tx2.version(), 3);
+        assert!(tx2.state.active_versions.contains(&1));
+        Ok(())
+    }
+
+    // AS OF after a vacuum
+    #[test]
+    fn test_vacuum_as_of() -> Result<()> {
+        let mvcc = Mvcc::new(MemoryEngine::new());
+        let tx1 = mvcc.begin()?;
+        tx1.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx1.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx1.commit()?;
+        let tx2 = mvcc.begin()?;
+        tx2.set(b"key1".to_vec(), b"val3".to_vec())?;
+        tx2.delete(b"key2".to_vec())?;
+        tx2.commit()?;
+        assert_eq!(mvcc.begin_as_of(tx1.version())?.get(b"key2".to_vec())?, Some(b"val2".to_vec()));
+
+        assert_eq!(mvcc.vacuum()?.versions, 3);
+        // The removed history is refused, from the newest version on nothing changed
+        assert!(mvcc.begin_as_of(tx1.version()).is_err());
+        let past = mvcc.begin_as_of(tx2.version())?;
+        assert_eq!(past.get(b"key1".to_vec())?, Some(b"val3".to_vec()));
+        assert_eq!(past.get(b"key2".to_vec())?, None);
+        let tx3 = mvcc.begin()?;
+        tx3.set(b"key1".to_vec(), b"val4".to_vec())?;
+        tx3.commit()?;
+        assert_eq!(mvcc.begin_as_of(tx2.version())?.get(b"key1".to_vec())?, Some(b"val3".to_vec()));
+
+        // An open AS OF read keeps the versions it reads
+        drop(past);
+        let past = mvcc.begin_as_of(tx2.version())?;
+        assert!(mvcc.vacuum().is_err());
+        assert_eq!(past.get(b"key1".to_vec())?, Some(b"val3".to_vec()));
+        past.commit()?;
+        assert_eq!(mvcc.vacuum()?.versions, 1);
+        // Dropped without finishing, it is released as well
+        let past = mvcc.begin_as_of(tx3.version())?;
+        assert!(mvcc.vacuum().is_err());
+        drop(past);
+        mvcc.vacuum()?;
+        Ok(())
+    }
+
+    // Crash in commit, before the commit point
+    #[test]
+    fn test_commit_crash() -> Result<()> {
+        let log = WriteLog::default();
+        let mvcc = Mvcc::new(CrashEngine { inner: MemoryEngine::new(), log: log.clone(), writes_left: usize::MAX, flushes: 0 });
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+
+        // Nothing is changed and the transaction stays open to retry
+        mvcc.engine.lock()?.writes_left = 0;
+        assert!(tx.commit().is_err());
+        assert!(mvcc.vacuum().is_err());
+        mvcc.engine.lock()?.writes_left = usize::MAX;
+        tx.commit()?;
+        assert_eq!(mvcc.begin_as_of(tx.version())?.get(b"key2".to_vec())?, Some(b"val2".to_vec()));
+
+        // Or to roll back, the active marker goes with it
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val3".to_vec())?;
+        mvcc.engine.lock()?.writes_left = 0;
+        assert!(tx.commit().is_err());
+        assert!(tx.rollback().is_err());
+        mvcc.engine.lock()?.writes_left = usize::MAX;
+        tx.rollback()?;
+        assert!(tx.commit().is_err());
+        assert_eq!(mvcc.begin_as_of(tx.version())?.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        mvcc.vacuum()?;
+        Ok(())
+    }
+
+    // 20. Read a key, delete it, read again in the same transaction
+    fn read_then_delete(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.set(b"key2".to_vec(), b"val2".to_vec())?;
+        tx.commit()?;
+
+        let tx1 = mvcc.begin()?;
+        let tx2 = mvcc.begin()?;
+        assert_eq!(tx1.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        tx1.delete(b"key1".to_vec())?;
+
+        // tx1 sees its own uncommitted delete through every read path
+        assert_eq!(tx1.get(b"key1".to_vec())?, None);
+        assert_eq!(tx1.scan_prefix(b"key".to_vec())?, vec![ScanResult { key: b"key2".to_vec(), value: b"val2".to_vec() }]);
+        assert_eq!(tx1.scan(b"key1".to_vec()..=b"key2".to_vec())?.len(), 1);
+
+        // tx2 does not
+        assert_eq!(tx2.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx2.scan_prefix(b"key".to_vec())?.len(), 2);
+        tx1.commit()?;
+        tx2.commit()?;
+
+        let tx3 = mvcc.begin()?;
+        assert_eq!(tx3.get(b"key1".to_vec())?, None);
+        assert_eq!(tx3.scan_prefix(b"key".to_vec())?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_then_delete() -> Result<()> {
+        read_then_delete(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        read_then_delete(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    // 21. A transaction sees its own uncommitted writes
+    fn own_writes(eng: impl Engine) -> Result<()> {
+        let mvcc = Mvcc::new(eng);
+        let tx = mvcc.begin()?;
+        tx.set(b"key1".to_vec(), b"val1".to_vec())?;
+        tx.commit()?;
+
+        // An older transaction is still active while tx1 writes
+        let tx0 = mvcc.begin()?;
+        let tx1 = mvcc.begin()?;
+        assert!(!tx1.state.active_versions.contains(&tx1.version()));
+
+        tx1.set(b"key2".to_vec(), b"val2".to_vec())?;
+        assert_eq!(tx1.get(b"key2".to_vec())?, Some(b"val2".to_vec()));
+        assert_eq!(tx1.scan_prefix(b"key".to_vec())?, vec![
+            ScanResult { key: b"key1".to_vec(), value: b"val1".to_vec() },
+            ScanResult { key: b"key2".to_vec(), value: b"val2".to_vec() },
+        ]);
+
+        // The latest own write wins
+        tx1.set(b"key1".to_vec(), b"val3".to_vec())?;
+        assert_eq!(tx1.get(b"key1".to_vec())?, Some(b"val3".to_vec()));
+        assert_eq!(tx1.scan_prefix(b"key1".to_vec())?, vec![ScanResult { key: b"key1".to_vec(), value: b"val3".to_vec() }]);
+
+        tx1.delete(b"key2".to_vec())?;
+        assert_eq!(tx1.get(b"key2".to_vec())?, None);
+        assert_eq!(tx1.scan_prefix(b"key".to_vec())?.len(), 1);
+
+        // None of it is visible to the older transaction
+        assert_eq!(tx0.get(b"key1".to_vec())?, Some(b"val1".to_vec()));
+        assert_eq!(tx0.get(b"key2".to_vec())?, None);
+        tx1.commit()?;
+        tx0.commit()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_own_writes() -> Result<()> {
+        own_writes(MemoryEngine::new())?;
+        let p = tempfile::tempdir()?.into_path().join("raydb-log");
+        own_writes(DiskEngine::new(p.clone())?)?;
+        std::fs::remove_dir_all(p.parent().unwrap())?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_version_range() -> Result<()> {
+        let key = b"key1".to_vec();
+        // The bounds get builds for version 9
+        assert_eq!(MvccKey::version_range(&key, 0, 9)?, (
+            MvccKey::Version(key.clone(), 0).encode()?,
+            MvccKey::Version(key.clone(), 9).encode()?,
+        ));
+
+        // Every version of the key falls inside, the neighbours do not
+        let (from, to) = MvccKey::version_range(&key, 0, u64::MAX)?;
+        for version in [0, 1, 9, u64::MAX] {
+            assert!((from.clone()..=to.clone()).contains(&MvccKey::Version(key.clone(), version).encode()?));
+        }
+        for other in [b"key".to_vec(), b"key0".to_vec(), b"key10".to_vec(), b"key2".to_vec()] {
+            assert!(!(from.clone()..=to.clone()).contains(&MvccKey::Version(other, 5).encode()?));
+        }
+        Ok(())
+    }
+}